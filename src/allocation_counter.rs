@@ -0,0 +1,63 @@
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell};
+
+thread_local! {
+    /// Number of allocations made by this thread.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Wraps the system allocator, counting the allocations made by each thread so tests running in parallel don't
+/// count each other's.
+struct CountingAllocator;
+
+impl CountingAllocator {
+    /// Counts an allocation by the current thread, unless the thread is being torn down.
+    fn count() {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations the current thread makes while running a function.
+/// 
+/// # Arguments
+/// 
+/// * `function` - Is the function to run.
+pub fn allocations(function: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    function();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_allocations() {
+        assert_eq!(allocations(|| {}), 0);
+        assert_eq!(allocations(|| drop(std::hint::black_box(Box::new(1)))), 1);
+    }
+}
@@ -1,71 +1,334 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc, sync::{mpsc::{self, Receiver, Sender, TryRecvError}, Arc, Condvar, Mutex}, thread::{self, JoinHandle}};
-use rustfft::{num_complex::Complex, FftPlanner};
-use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System};
-use windows::{core::{implement, Interface}, Win32::{Media::Audio::{eMultimedia, eRender, IAudioSessionControl, IAudioSessionControl2, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl, IMMDeviceEnumerator, MMDeviceEnumerator}, System::Com::{CoCreateInstance, CLSCTX_ALL}}};
+use std::{fmt, io, path::PathBuf, sync::{mpsc::{self, Receiver, Sender, TryRecvError}, Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use sysinfo::{Pid, ProcessRefreshKind, System};
+use windows::{core::{implement, Interface, GUID, PCWSTR, PWSTR}, Win32::{Foundation::BOOL, Media::Audio::{eMultimedia, eRender, AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateExpired, IAudioSessionControl, IAudioSessionControl2, IAudioSessionEvents, IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl, IMMDeviceEnumerator, MMDeviceEnumerator}, System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_ALL}}};
 use wasapi::*;
 
-use crate::FFT_FREQUENCY;
-use crate::common_audio_manager::FftHandler;
+use crate::common_audio_manager::{AnalysisOptions, FrameSender};
+use crate::mixer::{MixMode, Mixer};
+use crate::network_source::{NetworkReceiver, NetworkSettings};
+use crate::recorder::{Recording, RecordingFormat};
+use crate::sample_ring::SampleProducer;
+use crate::stream_source::{StreamReader, StreamSettings};
+
+/// Sample rates checked for support when listing the formats of an input device.
+const STANDARD_SAMPLE_RATES: [u32; 5] = [44100, 48000, 88200, 96000, 192000];
+
+/// A source of audio that can be captured.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CaptureSource {
+    /// Everything played through the default output device.
+    SystemOutput,
+    /// The audio played by an application.
+    Application(Pid),
+    /// The audio recorded by an input device such as a microphone or line-in, identified by its device ID.
+    InputDevice(String),
+    /// Audio sent from another machine over UDP.
+    Network,
+    /// Raw samples read from standard input or a named pipe.
+    Stream,
+}
+
+impl CaptureSource {
+    /// Returns a name for the source that can be used in a file name, such as `app-1234`.
+    pub fn file_name(&self) -> String {
+        match self {
+            CaptureSource::SystemOutput => "system-output".to_string(),
+            CaptureSource::Application(pid) => format!("app-{pid}"),
+            CaptureSource::InputDevice(id) => {
+                // Device IDs end in a GUID that tells the devices apart, but contain characters file names can't
+                let id: Vec<char> = id.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+                format!("input-{}", id[id.len().saturating_sub(8)..].iter().collect::<String>())
+            }
+            CaptureSource::Network => "network".to_string(),
+            CaptureSource::Stream => "stream".to_string(),
+        }
+    }
+}
+
+/// The ways captured samples can be encoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SampleEncoding {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl SampleEncoding {
+    /// Returns the encoding of a wave format, if it is one that can be decoded.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `format` - Is the wave format.
+    fn from_wave_format(format: &WaveFormat) -> Option<Self> {
+        // 24 bit samples in 32 bit containers are left aligned, so decode the same as 32 bit samples
+        match (format.get_subformat().ok()?, format.get_bitspersample()) {
+            (SampleType::Int, 16) => Some(SampleEncoding::Int16),
+            (SampleType::Int, 24) => Some(SampleEncoding::Int24),
+            (SampleType::Int, 32) => Some(SampleEncoding::Int32),
+            (SampleType::Float, 32) => Some(SampleEncoding::Float32),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes each sample takes.
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleEncoding::Int16 => 2,
+            SampleEncoding::Int24 => 3,
+            SampleEncoding::Int32 | SampleEncoding::Float32 => 4,
+        }
+    }
+
+    /// Returns the number of bits each sample takes.
+    pub fn bits(&self) -> u32 {
+        self.bytes() as u32 * 8
+    }
+
+    /// Returns a sample as a float where 1 is full scale.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `bytes` - Is the little endian bytes of the sample.
+    pub fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleEncoding::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleEncoding::Int24 => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2147483648.0,
+            SampleEncoding::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+            SampleEncoding::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    /// Replaces the output with the samples of the whole frames at the start of some bytes, returning the number of
+    /// bytes decoded.
+    /// 
+    /// The output's memory is reused, so nothing is allocated once it has room for the samples.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `bytes` - Is the little endian bytes of the frames.
+    /// 
+    /// * `frame_bytes` - Is the number of bytes in each frame.
+    /// 
+    /// * `output` - Is the vector the samples are written to.
+    pub fn decode_frames(&self, bytes: &[u8], frame_bytes: usize, output: &mut Vec<f32>) -> usize {
+        let complete = bytes.len() - bytes.len() % frame_bytes.max(1);
+        output.clear();
+        output.extend(bytes[..complete].chunks_exact(self.bytes()).map(|sample| self.decode(sample)));
+        complete
+    }
+
+    /// Returns the display name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SampleEncoding::Int16 => "16 bit",
+            SampleEncoding::Int24 => "24 bit",
+            SampleEncoding::Int32 => "32 bit",
+            SampleEncoding::Float32 => "32 bit float",
+        }
+    }
+}
+
+/// A sample rate, channel count and encoding a device can capture in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub encoding: SampleEncoding,
+}
+
+impl DeviceFormat {
+    /// Returns a description of the format, such as `48000 Hz, 2 channels, 32 bit float`.
+    pub fn name(&self) -> String {
+        format!("{} Hz, {} channel{}, {}", self.sample_rate, self.channels, if self.channels == 1 { "" } else { "s" }, self.encoding.name())
+    }
+}
+
+/// An input device audio can be captured from.
+#[derive(Clone, Debug)]
+pub struct InputDevice {
+    pub name: String,
+    pub id: String,
+    /// The formats the device supports in shared mode, starting with the format it mixes in.
+    pub formats: Vec<DeviceFormat>,
+}
+
+/// Time waited before restarting a capture that failed, doubled after each failure in a row.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest time waited before restarting a capture that failed.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+/// Time waited for the device to deliver audio before it is considered lost.
+const EVENT_TIMEOUT_MS: u32 = 2000;
+
+/// The reasons capturing a source can fail.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CaptureError {
+    /// The input device could not be found, such as after being unplugged.
+    DeviceNotFound,
+    /// A call to the audio system failed, with what was being done and the error it gave.
+    Audio(&'static str, String),
+    /// The device stopped delivering audio.
+    DeviceLost,
+    /// A network call failed, with what was being done and the error it gave.
+    Network(&'static str, String),
+    /// Reading a stream failed, with what was being done and the error it gave.
+    Stream(&'static str, String),
+    /// The program writing the stream closed it.
+    StreamEnded,
+    /// Standard input reached its end, it can't be read again.
+    InputEnded,
+    /// The capture thread panicked.
+    Panicked,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::DeviceNotFound => write!(f, "Device not found"),
+            CaptureError::Audio(action, error) => write!(f, "Failed to {action}: {error}"),
+            CaptureError::DeviceLost => write!(f, "Device stopped responding"),
+            CaptureError::Network(action, error) => write!(f, "Failed to {action}: {error}"),
+            CaptureError::Stream(action, error) => write!(f, "Failed to {action}: {error}"),
+            CaptureError::StreamEnded => write!(f, "Stream ended"),
+            CaptureError::InputEnded => write!(f, "Standard input ended"),
+            CaptureError::Panicked => write!(f, "Capture stopped unexpectedly"),
+        }
+    }
+}
+
+impl CaptureError {
+    /// Returns whether capture can't recover from the error, so shouldn't be retried.
+    pub fn is_final(&self) -> bool {
+        matches!(self, CaptureError::InputEnded)
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// Returns a function converting an error from the audio system into a capture error.
+/// 
+/// # Arguments
+/// 
+/// * `action` - Is what was being done, such as `start the stream`.
+fn audio_error<E: fmt::Display>(action: &'static str) -> impl FnOnce(E) -> CaptureError {
+    move |error| CaptureError::Audio(action, error.to_string())
+}
+
+/// The state of capturing a source.
+#[derive(Clone, Debug)]
+pub enum CaptureStatus {
+    /// The capture thread is connecting to the device.
+    Starting,
+    /// Audio is being captured.
+    Capturing,
+    /// Capture stopped because of an error and will be restarted.
+    Retrying { error: CaptureError, retry_at: Instant },
+    /// Capture stopped because of an error it can't recover from.
+    Stopped(CaptureError),
+}
+
+/// Messages sent from a capture thread to the manager.
+pub enum CaptureEvent {
+    /// The stream has started.
+    Started,
+    /// The default output device changed, so the thread should be restarted with the new one.
+    DeviceChanged,
+    /// Capture stopped because of an error.
+    Failed(CaptureError),
+}
 
 // TODO: Maybe set thread priority to high
 
 struct AudioThread {
-    device_id: String,
+    device_id: Option<String>,
     audio_client: AudioClient,
     format: WaveFormat,
+    encoding: SampleEncoding,
     playing: Arc<(Mutex<bool>, Condvar)>,
-    device_change: Sender<bool>,
-    handler: FftHandler,
+    events: Sender<CaptureEvent>,
+    samples: SampleProducer,
     kill: Receiver<bool>,
 }
 
 impl AudioThread {
     pub fn new(
-        sample_destination: Sender<Vec<(Complex<f32>, f32)>>,
-        fft_planner: Arc<Mutex<FftPlanner<f32>>>,
+        mixer: Mixer,
         playing: Arc<(Mutex<bool>, Condvar)>,
-        device_change: Sender<bool>,
-        process_id: Pid,
+        events: Sender<CaptureEvent>,
+        source: CaptureSource,
         kill: Receiver<bool>
-    ) -> Self {
-        // Get device and client
-        let device = get_default_device(&Direction::Render).unwrap();
-        let device_id = device.get_id().unwrap();
-        // let mut audio_client = device.get_iaudioclient().unwrap();
-        let mut audio_client = AudioClient::new_application_loopback_client(process_id.as_u32(), true).unwrap();
+    ) -> Result<Self, CaptureError> {
+        // Get device, client and the format the audio engine mixes the device in, output is captured from the default
+        // device so the ID is kept to notice it changing
+        let (device_id, mut audio_client, mix_format) = match &source {
+            CaptureSource::SystemOutput => {
+                // Capturing from an output device initialises the client in loopback mode
+                let device = get_default_device(&Direction::Render).map_err(audio_error("find the output device"))?;
+                let audio_client = device.get_iaudioclient().map_err(audio_error("open the output device"))?;
+                let mix_format = audio_client.get_mixformat().map_err(audio_error("get the mix format"))?;
+                (Some(device.get_id().map_err(audio_error("identify the output device"))?), audio_client, mix_format)
+            }
+            CaptureSource::Application(process_id) => {
+                // The loopback client has no mix format of its own, the application is mixed at the output device's
+                let device = get_default_device(&Direction::Render).map_err(audio_error("find the output device"))?;
+                let mix_format = device.get_iaudioclient()
+                    .and_then(|audio_client| audio_client.get_mixformat())
+                    .map_err(audio_error("get the mix format"))?;
+                let audio_client = AudioClient::new_application_loopback_client(process_id.as_u32(), true)
+                    .map_err(audio_error("capture the application"))?;
+                (Some(device.get_id().map_err(audio_error("identify the output device"))?), audio_client, mix_format)
+            }
+            CaptureSource::Network => unreachable!("Network audio is received by a NetworkReceiver"),
+            CaptureSource::Stream => unreachable!("Streams are read by a StreamReader"),
+            CaptureSource::InputDevice(id) => {
+                let device = find_input_device(id).ok_or(CaptureError::DeviceNotFound)?;
+                let audio_client = device.get_iaudioclient().map_err(audio_error("open the input device"))?;
+                let mix_format = audio_client.get_mixformat().map_err(audio_error("get the mix format"))?;
+                (None, audio_client, mix_format)
+            }
+        };
 
-        // Set desired format
-        let format = WaveFormat::new(32, 32, &SampleType::Float, 44100, 1, None);
+        // Capture in the mix format so nothing is resampled, only converting to float if its samples can't be decoded
+        let (format, encoding) = match SampleEncoding::from_wave_format(&mix_format) {
+            Some(encoding) => (mix_format, encoding),
+            None => {
+                let sample_rate = mix_format.get_samplespersec() as usize;
+                let channels = mix_format.get_nchannels() as usize;
+                (WaveFormat::new(32, 32, &SampleType::Float, sample_rate, channels, None), SampleEncoding::Float32)
+            }
+        };
 
-        // Initialize client
+        // Initialize client, conversion is still allowed as the application loopback client requires it
         audio_client.initialize_client(
             &format,
             0,
             &Direction::Capture,
             &ShareMode::Shared,
             true,
-        ).unwrap();
-
-        // Plan FFT for this format
-        let fft = fft_planner.lock().unwrap().plan_fft_forward(format.get_samplespersec() as usize / FFT_FREQUENCY as usize);
+        ).map_err(audio_error("initialise the client"))?;
 
-        // Create the FFT handler
-        let handler = FftHandler::new(sample_destination, format.get_samplespersec(), fft);
+        // Add this source to the mix
+        let capture_format = DeviceFormat { sample_rate: format.get_samplespersec(), channels: format.get_nchannels(), encoding };
+        let samples = mixer.add_input(source, capture_format);
 
-        AudioThread { device_id, audio_client, format, playing, handler, device_change, kill }
+        Ok(AudioThread { device_id, audio_client, format, encoding, playing, samples, events, kill })
     }
 
-    pub fn capture_loop(&mut self,) {
+    /// Captures audio until killed, the default output device changes or an error occurs.
+    pub fn capture_loop(&mut self) -> Result<(), CaptureError> {
         // Gather information about client
-        let event_handler = self.audio_client.set_get_eventhandle().unwrap();
-        let capture_client = self.audio_client.get_audiocaptureclient().unwrap();
+        let event_handler = self.audio_client.set_get_eventhandle().map_err(audio_error("get the event handle"))?;
+        let capture_client = self.audio_client.get_audiocaptureclient().map_err(audio_error("get the capture client"))?;
 
         // Gather information about format
-        let block_align = self.format.get_blockalign();
-        let chunk_size = self.format.get_samplespersec() as usize / FFT_FREQUENCY as usize;
+        let block_align = self.format.get_blockalign() as usize;
     
-        // Create queue for sending samples
-        let mut sample_queue: VecDeque<u8> = VecDeque::new();
+        // Allocate a second of captured bytes and decoded samples up front, devices deliver packets of around 10 ms
+        // so nothing needs to be allocated while capturing
+        let mut buffer = vec![0; self.format.get_samplespersec() as usize * block_align];
+        let mut data = Vec::with_capacity(buffer.len() / self.encoding.bytes());
 
         // Block until we first want to start stream
         let (lock, cvar) = &*self.playing;
@@ -75,58 +338,41 @@ impl AudioThread {
         }
         drop(playing);
 
-        self.audio_client.start_stream().unwrap();
-
-        // Allocate memory as f32 to ensure correct alignment
-        let mut data = Vec::with_capacity(chunk_size);
+        self.audio_client.start_stream().map_err(audio_error("start the stream"))?;
+        let _ = self.events.send(CaptureEvent::Started);
 
         // Main loop
         loop {
-            while !sample_queue.is_empty() {
-                // Temporary buffer for samples
-                let mut temp = Vec::with_capacity(std::cmp::min(sample_queue.len(), (chunk_size - data.len()) * 4));
-
-                // Try to fill the chunk with samples
-                while !sample_queue.is_empty() && temp.len() < temp.capacity() {
-                    temp.push(sample_queue.pop_front().unwrap());
-                }
+            // Read each packet from the device, packets are always whole frames
+            let new_frames = capture_client.get_next_nbr_frames().map_err(audio_error("read from the device"))?.unwrap_or(0) as usize;
+            if new_frames > 0 {
+                let packet = buffer.get_mut(..new_frames * block_align)
+                    .ok_or_else(|| CaptureError::Audio("read from the device", "Packet larger than a second".to_string()))?;
+                let (read, _) = capture_client.read_from_device(packet).map_err(audio_error("read from the device"))?;
+                self.encoding.decode_frames(&buffer[..read as usize * block_align], block_align, &mut data);
 
-                // Convert to f32
-                for chunk in temp.chunks(4) {
-                    let mut sample = [0; 4];
-                    sample.copy_from_slice(chunk);
-                    let sample = f32::from_ne_bytes(sample);
-                    data.push(sample);
+                // Pass the samples to the mixer, if it has fallen behind they are dropped rather than waited for
+                if !data.is_empty() {
+                    self.samples.push_slice(&data);
                 }
-
-                // Ensure the chunk is filled before further processing
-                if data.len() != chunk_size {
-                    break;
-                }
-
-                // Perform FFT on the data
-                self.handler.perform_fft(data.as_slice());
-
-                // Remove the first quarter of the samples, this is done to smooth the visualisation by creating overlapping windows
-                data.drain(0..data.len() / 4);
             }
 
-            let new_frames = capture_client.get_next_nbr_frames().unwrap_or(Some(0)).unwrap_or(0);
-            let additional = (new_frames as usize * block_align as usize).saturating_sub(sample_queue.capacity() - sample_queue.len());
-            sample_queue.reserve(additional);
-    
-            // Read from device to queue
-            if new_frames > 0 { capture_client.read_from_device_to_deque(&mut sample_queue).unwrap(); }
-            if event_handler.wait_for_event(1000000).is_err() {
-                self.audio_client.stop_stream().unwrap();
-                break;
+            // Loopback devices only signal when something is playing, so only a device being captured directly is lost
+            // when it stops signalling
+            if event_handler.wait_for_event(EVENT_TIMEOUT_MS).is_err() && self.device_id.is_none() {
+                let _ = self.audio_client.stop_stream();
+                return Err(CaptureError::DeviceLost);
             }
 
             // If this device is no longer active, we should kill this thread and a new one should be started with the new device
-            let test = get_default_device(&Direction::Render).unwrap().get_id().unwrap();
-            if self.device_id != test {
-                let _ = self.device_change.send(true);
-                return;
+            if let Some(device_id) = &self.device_id {
+                let test = get_default_device(&Direction::Render)
+                    .and_then(|device| device.get_id())
+                    .map_err(audio_error("find the output device"))?;
+                if *device_id != test {
+                    let _ = self.events.send(CaptureEvent::DeviceChanged);
+                    return Ok(());
+                }
             }
     
             // Check if we should stop the stream and if so, wait for command to start again
@@ -134,34 +380,51 @@ impl AudioThread {
             let mut playing = lock.lock().unwrap();
 
             if !*playing {
-                self.audio_client.stop_stream().unwrap();
+                self.audio_client.stop_stream().map_err(audio_error("stop the stream"))?;
                 while !*playing {
                     playing = cvar.wait(playing).unwrap();
                 }
 
-                self.audio_client.start_stream().unwrap()
+                self.audio_client.start_stream().map_err(audio_error("start the stream"))?;
             }
 
             // Allow the main thread to kill this thread if necessary
             match self.kill.try_recv() {
-                Ok(value) => if value { return },
+                Ok(value) => if value { return Ok(()) },
                 Err(TryRecvError::Empty) => {},
-                Err(TryRecvError::Disconnected) => return, 
+                Err(TryRecvError::Disconnected) => return Ok(()), 
             }
         }
     }
 }
 
+/// A source being captured by its own audio thread.
+struct Capture {
+    source: CaptureSource,
+    events: Receiver<CaptureEvent>,
+    kill: Sender<bool>,
+    status: CaptureStatus,
+    retry_delay: Duration,
+    thread: JoinHandle<()>,
+}
+
 /// Holds all necessary information for the app audio manager.
 pub struct AppAudioManager {
-    current_handle: Option<JoinHandle<()>>,
-    sample_destination: Sender<Vec<(Complex<f32>, f32)>>,
     playing: Arc<(Mutex<bool>, Condvar)>,
-    fft_planner: Arc<Mutex<FftPlanner<f32>>>,
-    device_change: Receiver<bool>,
+    mixer: Mixer,
     monitor: AppMonitor,
-    current_pid: Option<Pid>,
-    kill: Option<Sender<bool>>
+    input_devices: Vec<InputDevice>,
+    captures: Vec<Capture>,
+    network_settings: NetworkSettings,
+    network_enabled: bool,
+    stream_settings: Option<StreamSettings>,
+    /// Recordings in progress, one for each source.
+    recordings: Vec<Recording>,
+    recording_format: RecordingFormat,
+    /// Why the last recording couldn't be started or written, if it failed.
+    recording_error: Option<String>,
+    /// Number of samples dropped from the recordings of sources that have since stopped.
+    recording_dropped_samples: usize,
 }
 
 impl AppAudioManager {
@@ -170,20 +433,32 @@ impl AppAudioManager {
     /// # Arguments
     /// 
     /// * `sample_destination` - Is the destination to send the samples for rendering.
-    pub fn new(sample_destination: Sender<Vec<(Complex<f32>, f32)>>) -> Self {
-        // Create FFT planner
-        let fft_planner = Arc::new(Mutex::new(FftPlanner::new()));
-
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the audio.
+    pub fn new(sample_destination: FrameSender, analysis_options: Arc<Mutex<AnalysisOptions>>) -> Self {
         // Condvar for thread control
         let playing = Arc::new((Mutex::new(false), Condvar::new()));
 
-        // Communications channel for reviving thread on device change
-        let (_, device_change): (Sender<bool>, Receiver<bool>) = mpsc::channel();
-
         // Create monitor for opened applications
         let monitor = AppMonitor::new();
 
-        AppAudioManager { current_handle: None, sample_destination, playing, fft_planner, device_change, monitor, current_pid: None, kill: None }
+        // Create the mixer every audio thread passes its samples through
+        let mixer = Mixer::new(sample_destination, analysis_options, playing.clone());
+
+        AppAudioManager {
+            playing,
+            mixer,
+            monitor,
+            input_devices: enumerate_input_devices(),
+            captures: Vec::new(),
+            network_settings: NetworkSettings::default(),
+            network_enabled: false,
+            stream_settings: None,
+            recordings: Vec::new(),
+            recording_format: RecordingFormat::Wav,
+            recording_error: None,
+            recording_dropped_samples: 0,
+        }
     }
 
     /// Starts the audio stream passing samples to the FFT processor.
@@ -200,16 +475,56 @@ impl AppAudioManager {
         let mut playing = lock.lock().unwrap();
         *playing = false;
         cvar.notify_all();
+        drop(playing);
+
+        // The recording controls are hidden while stopped, so finish any recording rather than leave it open
+        self.stop_recording();
     }
 
-    /// Checks if the audio thread is still alive.
+    /// Checks if the audio threads are still alive.
     /// 
-    /// If the audio thread has died for some reason, it will be created in the correct state.
-    pub fn check_device(&mut self, pid: Pid) {
-        match self.device_change.try_recv() {
-            Ok(value) => if value { self.create_thread(pid); }
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => self.create_thread(pid)
+    /// If an audio thread has died for some reason, it will be created in the correct state. Threads that failed are
+    /// restarted after a delay that grows each time they fail again, so a missing device isn't retried constantly.
+    fn check_devices(&mut self) {
+        let now = Instant::now();
+        for index in 0..self.captures.len() {
+            let capture = &mut self.captures[index];
+            let mut restart = false;
+
+            loop {
+                match capture.events.try_recv() {
+                    Ok(CaptureEvent::Started) => {
+                        capture.status = CaptureStatus::Capturing;
+                        capture.retry_delay = RETRY_DELAY;
+                    }
+                    Ok(CaptureEvent::DeviceChanged) => restart = true,
+                    Ok(CaptureEvent::Failed(error)) if error.is_final() => capture.status = CaptureStatus::Stopped(error),
+                    Ok(CaptureEvent::Failed(error)) => {
+                        capture.status = CaptureStatus::Retrying { error, retry_at: now + capture.retry_delay };
+                        capture.retry_delay = (capture.retry_delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        // A thread that ended without saying why has panicked
+                        if !restart && !matches!(capture.status, CaptureStatus::Retrying { .. } | CaptureStatus::Stopped(_)) {
+                            capture.status = CaptureStatus::Retrying { error: CaptureError::Panicked, retry_at: now + capture.retry_delay };
+                            capture.retry_delay = (capture.retry_delay * 2).min(MAX_RETRY_DELAY);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            if let CaptureStatus::Retrying { retry_at, .. } = capture.status {
+                restart |= now >= retry_at;
+            }
+
+            if restart {
+                let capture = self.captures.remove(index);
+                let retry_delay = capture.retry_delay;
+                let restarted = Capture { retry_delay, ..self.create_thread(capture.source, Some(capture.thread)) };
+                self.captures.insert(index, restarted);
+            }
         }
     }
 
@@ -220,146 +535,712 @@ impl AppAudioManager {
         *playing
     }
 
-    /// Creates the audio thread.
-    fn create_thread(&mut self, pid: Pid) {
-        let fft_planner = self.fft_planner.clone();
-        let sample_destination = self.sample_destination.clone();
+    /// Creates the audio thread for a source.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to capture.
+    /// 
+    /// * `previous` - Is the thread that was capturing the source, which is waited for so it can release the source
+    ///   before it is opened again.
+    fn create_thread(&self, source: CaptureSource, previous: Option<JoinHandle<()>>) -> Capture {
+        let mixer = self.mixer.clone();
         let playing = self.playing.clone();
-        self.current_pid = Some(pid);
+        let thread_source = source.clone();
+        let network_settings = self.network_settings;
+        let stream_settings = self.stream_settings.clone();
 
-        // Communications channel for reviving thread on device change
-        let (transmit, device_change): (Sender<bool>, Receiver<bool>) = mpsc::channel();
-        self.device_change = device_change;
-        let (kill_transmit, kill_recv): (Sender<bool>, Receiver<bool>) = mpsc::channel();
-        self.kill = Some(kill_transmit);
+        // Communications channel for the thread to report its status, including when it needs reviving
+        let (transmit, events): (Sender<CaptureEvent>, Receiver<CaptureEvent>) = mpsc::channel();
+        let (kill, kill_recv): (Sender<bool>, Receiver<bool>) = mpsc::channel();
 
-        self.current_handle = Some(thread::Builder::new()
+        let thread = thread::Builder::new()
             .name("Capture".to_string())
             .spawn(move || {
-                let mut audio_thread = AudioThread::new(sample_destination, fft_planner, playing, transmit, pid, kill_recv);
-                audio_thread.capture_loop();
+                if let Some(previous) = previous {
+                    _ = previous.join();
+                }
+
+                let result = match thread_source {
+                    CaptureSource::Network => NetworkReceiver::new(network_settings, mixer, playing, transmit.clone(), kill_recv)
+                        .and_then(|mut receiver| receiver.receive_loop()),
+                    CaptureSource::Stream => stream_settings.ok_or(CaptureError::DeviceNotFound)
+                        .and_then(|settings| StreamReader::new(settings, mixer, playing, transmit.clone(), kill_recv))
+                        .and_then(|mut reader| reader.read_loop()),
+                    _ => AudioThread::new(mixer, playing, transmit.clone(), thread_source, kill_recv)
+                        .and_then(|mut audio_thread| audio_thread.capture_loop()),
+                };
+
+                if let Err(error) = result {
+                    let _ = transmit.send(CaptureEvent::Failed(error));
+                }
             }
-        ).unwrap());
+        ).unwrap();
+
+        Capture { source, events, kill, status: CaptureStatus::Starting, retry_delay: RETRY_DELAY, thread }
+    }
+
+    /// Returns the state of capturing a source, if it is selected.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to get the state of.
+    pub fn capture_status(&self, source: &CaptureSource) -> Option<&CaptureStatus> {
+        self.captures.iter().find(|capture| capture.source == *source).map(|capture| &capture.status)
+    }
+
+    /// Returns why applications couldn't be listed, if they couldn't.
+    pub fn monitor_error(&self) -> Option<&CaptureError> {
+        self.monitor.error.as_ref()
+    }
+
+    /// Returns where network audio is received and the format it is sent in.
+    pub fn network_settings(&self) -> NetworkSettings {
+        self.network_settings
+    }
+
+    /// Sets where network audio is received and the format it is sent in, restarting the receiver if it is running.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `network_settings` - Is the new settings.
+    pub fn set_network_settings(&mut self, network_settings: NetworkSettings) {
+        self.network_settings = network_settings;
+        // The old receiver keeps the port until it sees the kill, so the new one waits for it to finish before binding
+        if let Some(index) = self.captures.iter().position(|capture| capture.source == CaptureSource::Network) {
+            let capture = self.captures.remove(index);
+            _ = capture.kill.send(true);
+            let restarted = self.create_thread(CaptureSource::Network, Some(capture.thread));
+            self.captures.insert(index, restarted);
+        }
+    }
+
+    /// Returns whether network audio is offered as a source.
+    pub fn is_network_enabled(&self) -> bool {
+        self.network_enabled
+    }
+
+    /// Sets whether network audio is offered as a source.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `network_enabled` - Is whether network audio can be selected.
+    pub fn set_network_enabled(&mut self, network_enabled: bool) {
+        self.network_enabled = network_enabled;
+    }
+
+    /// Sets the stream of raw samples offered as a source, such as one piped in on the command line.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `stream_settings` - Is where the stream is read from and its format, or nothing to offer no stream.
+    pub fn set_stream_settings(&mut self, stream_settings: Option<StreamSettings>) {
+        self.stream_settings = stream_settings;
+    }
+
+    /// Returns the format a source is being captured in, if it is being captured.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to get the format of.
+    pub fn capture_format(&self, source: &CaptureSource) -> Option<DeviceFormat> {
+        self.mixer.input_format(source)
+    }
+
+    /// Returns the sample rate and number of channels the captured sources are mixed to, if any are being captured.
+    pub fn mix_format(&self) -> Option<(u32, u16)> {
+        self.mixer.output_format()
+    }
+
+    /// Returns the name of the source on each channel of the mixed audio when sources are mixed separately, otherwise
+    /// nothing.
+    pub fn mix_channel_names(&self) -> Vec<String> {
+        let sources = self.sources();
+        self.mixer.output_sources().iter()
+            .map(|source| sources.iter().find(|(_, named)| named == source).map(|(name, _)| name.clone()).unwrap_or_default())
+            .collect()
+    }
+
+    /// Returns the number of captured samples that weren't analysed because the mixer or the analysis fell behind.
+    pub fn dropped_samples(&self) -> usize {
+        self.mixer.dropped_samples()
+    }
+
+    /// Returns how the captured sources are combined.
+    pub fn mix_mode(&self) -> MixMode {
+        self.mixer.mode()
+    }
+
+    /// Sets how the captured sources are combined.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `mix_mode` - Is the new mix mode.
+    pub fn set_mix_mode(&mut self, mix_mode: MixMode) {
+        self.mixer.set_mode(mix_mode);
+    }
+
+    /// Returns the gain applied to a source when it is mixed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to get the gain of.
+    pub fn source_gain(&self, source: &CaptureSource) -> f32 {
+        self.mixer.gain(source)
+    }
+
+    /// Sets the gain applied to a source when it is mixed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to set the gain of.
+    /// 
+    /// * `gain` - Is the new gain where 1 leaves the source unchanged.
+    pub fn set_source_gain(&mut self, source: CaptureSource, gain: f32) {
+        self.mixer.set_gain(source, gain);
+    }
+
+    /// Returns the file format new recordings are made in.
+    pub fn recording_format(&self) -> RecordingFormat {
+        self.recording_format
+    }
+
+    /// Sets the file format new recordings are made in.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `recording_format` - Is the new file format.
+    pub fn set_recording_format(&mut self, recording_format: RecordingFormat) {
+        self.recording_format = recording_format;
+    }
+
+    /// Starts recording each captured source to its own file before it is mixed, replacing any recording in progress.
+    /// 
+    /// Each file keeps the format its source is captured in. A source that stops being captured has its file completed.
+    pub fn start_recording(&mut self) {
+        self.stop_recording();
+        self.recording_error = None;
+        self.recording_dropped_samples = 0;
+        let inputs = self.mixer.inputs();
+        if inputs.is_empty() {
+            self.recording_error = Some("Nothing is being captured, start capturing a source to record it".to_string());
+            return;
+        }
+
+        // Collected first so a file that can't be created doesn't leave the others recording
+        let recordings: io::Result<Vec<_>> = inputs.iter()
+            .map(|(source, format)| Recording::start(self.recording_format, *format, &source.file_name()).map(|recording| (source, recording)))
+            .collect();
+
+        match recordings {
+            Ok(recordings) => for (source, (recording, samples)) in recordings {
+                self.mixer.record_input(source, samples);
+                self.recordings.push(recording);
+            },
+            Err(error) => self.recording_error = Some(format!("Failed to start recording: {error}")),
+        }
+    }
+
+    /// Stops recording, waiting for the files to be completed.
+    pub fn stop_recording(&mut self) {
+        for recording in std::mem::take(&mut self.recordings) {
+            self.finish_recording(recording);
+        }
+    }
+
+    /// Completes the files of recordings whose source has stopped being captured or that failed to write.
+    fn check_recordings(&mut self) {
+        let (finished, running): (Vec<Recording>, Vec<Recording>) = std::mem::take(&mut self.recordings).into_iter().partition(Recording::is_finished);
+        self.recordings = running;
+        for recording in finished {
+            self.finish_recording(recording);
+        }
+    }
+
+    /// Waits for a recording's file to be completed, keeping any error writing it to show the user.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `recording` - Is the recording to complete.
+    fn finish_recording(&mut self, recording: Recording) {
+        let path = recording.path().to_path_buf();
+        self.recording_dropped_samples += recording.dropped_samples();
+        if let Err(error) = recording.finish() {
+            self.recording_error = Some(format!("Failed to write {}: {error}", path.display()));
+        }
+    }
+
+    /// Returns the number of samples missing from the files of the current or last recording because the disk couldn't
+    /// keep up.
+    pub fn recording_dropped_samples(&self) -> usize {
+        self.recording_dropped_samples + self.recordings.iter().map(Recording::dropped_samples).sum::<usize>()
+    }
+
+    /// Returns why the last recording couldn't be started or written, if it failed.
+    pub fn recording_error(&self) -> Option<&str> {
+        self.recording_error.as_deref()
+    }
+
+    /// Returns the paths of the files being recorded to, which is empty if not recording.
+    pub fn recording_paths(&self) -> Vec<PathBuf> {
+        self.recordings.iter().map(|recording| recording.path().to_path_buf()).collect()
     }
 
     /// Returns the names of all audio producing applications without our own app.
     pub fn opened_applications(&self) -> Vec<(String, Pid)> {
-        self.monitor.get_opened_info().into_iter().filter(|(name, _)| name != "musualiser.exe").collect()
+        let own_pid = Pid::from_u32(std::process::id());
+        self.monitor.get_opened_info().into_iter().filter(|(_, pid)| *pid != own_pid).collect()
     }
 
-    pub fn current_pid(&self) -> Option<Pid> {
-        self.current_pid
+    /// Returns the resource the icon of an application is loaded from, if it gives one.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pid` - Is the application's process.
+    pub fn application_icon_path(&self, pid: Pid) -> Option<String> {
+        self.monitor.icon_path(pid)
+    }
+
+    /// Returns the input devices found when the manager was created or last refreshed.
+    pub fn input_devices(&self) -> &[InputDevice] {
+        &self.input_devices
+    }
+
+    /// Finds the input devices again, such as after one has been plugged in.
+    pub fn refresh_input_devices(&mut self) {
+        self.input_devices = enumerate_input_devices();
+    }
+
+    /// Returns the names of every source that can be captured, the system output followed by applications, input devices,
+    /// the stream if one was given and network audio if enabled.
+    pub fn sources(&self) -> Vec<(String, CaptureSource)> {
+        let system = std::iter::once(("System Output".to_string(), CaptureSource::SystemOutput));
+        let network = self.network_enabled.then(|| (format!("Network (UDP {})", self.network_settings.port), CaptureSource::Network));
+        let applications = self.opened_applications().into_iter().map(|(name, pid)| (name, CaptureSource::Application(pid)));
+        let devices = self.input_devices.iter().map(|device| (device.name.clone(), CaptureSource::InputDevice(device.id.clone())));
+        let stream = self.stream_settings.as_ref().map(|settings| (settings.input.name(), CaptureSource::Stream));
+        system.chain(applications).chain(devices).chain(stream).chain(network).collect()
+    }
+
+    /// Returns the sources currently being captured.
+    pub fn current_sources(&self) -> Vec<CaptureSource> {
+        self.captures.iter().map(|capture| capture.source.clone()).collect()
+    }
+
+    /// Update the audio manager with the new sources, capturing and mixing every one of them.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sources` - Is the sources to capture.
+    pub fn update(&mut self, sources: &[CaptureSource]) {
+        self.check_devices();
+        self.check_recordings();
+        self.monitor.prune();
+
+        // Kill the threads of sources that are no longer selected
+        self.captures.retain(|capture| {
+            let selected = sources.contains(&capture.source);
+            if !selected {
+                _ = capture.kill.send(true);
+            }
+            selected
+        });
+
+        // Create threads for newly selected sources
+        for source in sources.iter() {
+            if !self.captures.iter().any(|capture| capture.source == *source) {
+                let capture = self.create_thread(source.clone(), None);
+                self.captures.push(capture);
+            }
+        }
+    }
+}
+
+/// Returns every active input device with the formats it supports.
+fn enumerate_input_devices() -> Vec<InputDevice> {
+    let Ok(collection) = DeviceCollection::new(&Direction::Capture) else { return Vec::new() };
+    let count = collection.get_nbr_devices().unwrap_or(0);
+
+    (0..count).filter_map(|i| {
+        let device = collection.get_device_at_index(i).ok()?;
+        let name = device.get_friendlyname().ok()?;
+        let id = device.get_id().ok()?;
+        let formats = device.get_iaudioclient().map(|audio_client| supported_formats(&audio_client)).unwrap_or_default();
+        Some(InputDevice { name, id, formats })
+    }).collect()
+}
+
+/// Returns the input device with an ID.
+/// 
+/// # Arguments
+/// 
+/// * `id` - Is the ID of the device.
+fn find_input_device(id: &str) -> Option<Device> {
+    let collection = DeviceCollection::new(&Direction::Capture).ok()?;
+    let count = collection.get_nbr_devices().ok()?;
+    (0..count)
+        .filter_map(|i| collection.get_device_at_index(i).ok())
+        .find(|device| device.get_id().is_ok_and(|device_id| device_id == id))
+}
+
+/// Returns the formats a device supports in shared mode, starting with the format it mixes in.
+/// 
+/// # Arguments
+/// 
+/// * `audio_client` - Is an uninitialised client of the device.
+fn supported_formats(audio_client: &AudioClient) -> Vec<DeviceFormat> {
+    let mut formats = Vec::new();
+    if let Ok(mix_format) = audio_client.get_mixformat() {
+        let encoding = SampleEncoding::from_wave_format(&mix_format).unwrap_or(SampleEncoding::Float32);
+        formats.push(DeviceFormat { sample_rate: mix_format.get_samplespersec(), channels: mix_format.get_nchannels(), encoding });
     }
 
-    /// Update the audio manager with the new PID.
-    pub fn update(&mut self, (_, pid): (String, Pid)) {
-        self.check_device(pid);
+    // Shared mode usually only accepts the mix format, but some drivers accept others without conversion
+    for &sample_rate in STANDARD_SAMPLE_RATES.iter() {
+        for channels in 1..=2 {
+            let format = DeviceFormat { sample_rate, channels, encoding: SampleEncoding::Float32 };
+            let wave_format = WaveFormat::new(32, 32, &SampleType::Float, sample_rate as usize, channels as usize, None);
+            if !formats.contains(&format) && matches!(audio_client.is_supported(&wave_format, &ShareMode::Shared), Ok(None)) {
+                formats.push(format);
+            }
+        }
+    }
+
+    formats
+}
+
+/// How often the application list is checked for processes that have exited.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// An open audio session of an application.
+struct AudioSession {
+    instance_id: String,
+    pid: Pid,
+    process_name: String,
+    display_name: String,
+    /// Resource the application gives for the session's icon, such as `C:\App\app.exe,0`, empty if it gives none.
+    icon_path: String,
+    expired: bool,
+    control: IAudioSessionControl,
+    events: IAudioSessionEvents,
+}
+
+/// The audio sessions shared between the monitor and its callbacks, which may be called from any thread.
+type SessionList = Arc<Mutex<Vec<AudioSession>>>;
 
-        if Some(pid) == self.current_pid { return }
+/// Returns a string allocated by COM, freeing its memory.
+/// 
+/// # Arguments
+/// 
+/// * `value` - Is the string to take.
+unsafe fn take_string(value: PWSTR) -> String {
+    let string = value.to_string().unwrap_or_default();
+    CoTaskMemFree(Some(value.0 as *const _));
+    string
+}
+
+/// Adds a session to the list and listens for it changing or closing.
+/// 
+/// # Arguments
+/// 
+/// * `sessions` - Is the list to add the session to.
+/// 
+/// * `control` - Is the session.
+/// 
+/// * `system` - Is used to look up the process that owns the session.
+fn add_session(sessions: &SessionList, control: IAudioSessionControl, system: &mut System) -> windows_core::Result<()> {
+    unsafe {
+        let control2: IAudioSessionControl2 = control.cast()?;
+        let pid = Pid::from_u32(control2.GetProcessId()?);
+        let instance_id = take_string(control2.GetSessionInstanceIdentifier()?);
+
+        // Skip idle application and sessions that have closed
+        if pid == Pid::from_u32(0) || control.GetState()? == AudioSessionStateExpired {
+            return Ok(());
+        }
+
+        // The process may have exited since its session was created
+        let Some(process_name) = process_name(system, pid) else { return Ok(()) };
+        let display_name = take_string(control.GetDisplayName()?);
+        let icon_path = take_string(control.GetIconPath()?);
 
-        // Kill old thread
-        if let Some(sender) = &self.kill {
-            _ = sender.send(true);
+        // The list stays locked until the session is added, so a session being enumerated and created at once is only
+        // listed once
+        let mut sessions_guard = sessions.lock().unwrap();
+        if sessions_guard.iter().any(|session| session.instance_id == instance_id) {
+            return Ok(());
         }
 
-        // Create new thread
-        self.create_thread(pid);
+        let events: IAudioSessionEvents = AudioSessionEvents { instance_id: instance_id.clone(), sessions: sessions.clone() }.into();
+        control.RegisterAudioSessionNotification(&events)?;
+
+        sessions_guard.push(AudioSession {
+            instance_id,
+            pid,
+            process_name,
+            display_name,
+            icon_path,
+            expired: false,
+            control,
+            events,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns whether a process is running, refreshing only that process rather than listing every process.
+/// 
+/// # Arguments
+/// 
+/// * `system` - Is the system the process is looked up in.
+/// 
+/// * `pid` - Is the process.
+fn is_running(system: &mut System, pid: Pid) -> bool {
+    system.refresh_process_specifics(pid, ProcessRefreshKind::new())
+}
+
+/// Returns the name of a running process.
+/// 
+/// # Arguments
+/// 
+/// * `system` - Is the system the process is looked up in.
+/// 
+/// * `pid` - Is the process.
+fn process_name(system: &mut System, pid: Pid) -> Option<String> {
+    if !is_running(system, pid) {
+        return None;
     }
+    system.process(pid).map(|process| process.name().to_string())
 }
 
 /// Custom callback for when new audio sessions are created.
 #[implement(IAudioSessionNotification)]
 struct AudioSessionNotification {
-    current_apps: Rc<RefCell<Vec<(String, Pid)>>>,
+    sessions: SessionList,
 }
 
-impl AudioSessionNotification {
-    pub fn new(current_apps: Rc<RefCell<Vec<(String, Pid)>>>) -> Self {
-        AudioSessionNotification { current_apps }
+impl IAudioSessionNotification_Impl for AudioSessionNotification {
+    fn OnSessionCreated(&self, newsession: Option<&IAudioSessionControl>) -> windows_core::Result<()> {
+        let Some(session) = newsession else { return Ok(()) };
+        add_session(&self.sessions, session.clone(), &mut System::new())
     }
 }
 
-impl IAudioSessionNotification_Impl for AudioSessionNotification {
-    fn OnSessionCreated(&self, newsession: Option<&IAudioSessionControl>) -> windows_core::Result<()> {
-        // Get process ID
-        let session = newsession.unwrap().clone();
-        let session2: IAudioSessionControl2 = session.cast().unwrap();
-        let pid_find = unsafe { Pid::from_u32(session2.GetProcessId().unwrap()) };
-        
-        // Ensure the PID is not already in the list
-        if !self.current_apps.borrow().iter().any(|(_, pid)| pid_find == *pid) {
-            // Refresh system
-            let mut system = System::new();
-            let refresh = RefreshKind::new().with_processes(ProcessRefreshKind::everything());
-            system.refresh_specifics(refresh);
+/// Custom callback for when an audio session changes, keeping its entry up to date.
+#[implement(IAudioSessionEvents)]
+struct AudioSessionEvents {
+    instance_id: String,
+    sessions: SessionList,
+}
 
-            let process = system.process(pid_find).unwrap();
-            self.current_apps.borrow_mut().push((process.name().to_string(), pid_find));
+impl AudioSessionEvents {
+    /// Changes the entry of this session, if it is still listed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `change` - Is the change to make.
+    fn update(&self, change: impl FnOnce(&mut AudioSession)) {
+        if let Some(session) = self.sessions.lock().unwrap().iter_mut().find(|session| session.instance_id == self.instance_id) {
+            change(session);
         }
+    }
+}
+
+impl IAudioSessionEvents_Impl for AudioSessionEvents {
+    fn OnDisplayNameChanged(&self, newdisplayname: &PCWSTR, _: *const GUID) -> windows_core::Result<()> {
+        let display_name = unsafe { newdisplayname.to_string() }.unwrap_or_default();
+        self.update(|session| session.display_name = display_name);
+        Ok(())
+    }
 
+    fn OnIconPathChanged(&self, newiconpath: &PCWSTR, _: *const GUID) -> windows_core::Result<()> {
+        let icon_path = unsafe { newiconpath.to_string() }.unwrap_or_default();
+        self.update(|session| session.icon_path = icon_path);
+        Ok(())
+    }
+
+    fn OnSimpleVolumeChanged(&self, _: f32, _: BOOL, _: *const GUID) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnChannelVolumeChanged(&self, _: u32, _: *const f32, _: u32, _: *const GUID) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnGroupingParamChanged(&self, _: *const GUID, _: *const GUID) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnStateChanged(&self, newstate: AudioSessionState) -> windows_core::Result<()> {
+        // Sessions can't be unregistered from inside their own callback, so they are only marked here
+        if newstate == AudioSessionStateExpired {
+            self.update(|session| session.expired = true);
+        }
+        Ok(())
+    }
+
+    fn OnSessionDisconnected(&self, _: AudioSessionDisconnectReason) -> windows_core::Result<()> {
+        self.update(|session| session.expired = true);
         Ok(())
     }
 }
 
 struct AppMonitor {
-    current_apps: Rc<RefCell<Vec<(String, Pid)>>>,
-    session_manager: IAudioSessionManager2,
-    notification: IAudioSessionNotification,
+    sessions: SessionList,
+    registration: Option<(IAudioSessionManager2, IAudioSessionNotification)>,
+    /// Holds only the processes with sessions, so checking them doesn't list every process.
+    system: System,
+    last_prune: Instant,
+    error: Option<CaptureError>,
 }
 
 impl AppMonitor {
     pub fn new() -> Self {
-        // Get PIDs of all audio producing applications
-        let current_apps = Rc::new(RefCell::new(Vec::new()));
-        let session_manager: IAudioSessionManager2;
-        let notification: IAudioSessionNotification;
+        // Get all audio producing applications, if this fails the other sources can still be captured
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let (registration, error) = match Self::register(&sessions) {
+            Ok(registration) => (Some(registration), None),
+            Err(error) => (None, Some(CaptureError::Audio("list applications", error.to_string()))),
+        };
+
+        AppMonitor { sessions, registration, system: System::new(), last_prune: Instant::now(), error }
+    }
 
+    /// Registers for notifications of new audio sessions and lists the sessions that are already open.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sessions` - Is the list the sessions are added to.
+    fn register(sessions: &SessionList) -> windows_core::Result<(IAudioSessionManager2, IAudioSessionNotification)> {
         unsafe {
             // Get default audio endpoint
-            let device_enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL).unwrap();
-            let device = device_enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia).unwrap();
-            session_manager = device.Activate(CLSCTX_ALL, None).unwrap();
+            let device_enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            let device = device_enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)?;
+            let session_manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
 
-            // Register with audio notifications
-            notification = AudioSessionNotification::new(current_apps.clone()).into();
-            session_manager.RegisterSessionNotification(&notification).unwrap();
+            // Register with audio notifications first so no session is missed, any listed twice is skipped
+            let notification: IAudioSessionNotification = AudioSessionNotification { sessions: sessions.clone() }.into();
+            session_manager.RegisterSessionNotification(&notification)?;
 
-            // Enumerate all applications with an open audio session
-            let enumerator = session_manager.GetSessionEnumerator().unwrap();
-            let count = enumerator.GetCount().unwrap();
-
-            // Create system to get info
+            // Enumerate all applications with an open audio session, skipping any that can't be read
             let mut system = System::new();
-            let refresh = RefreshKind::new().with_processes(ProcessRefreshKind::everything());
-            system.refresh_specifics(refresh);
+            if let Ok(enumerator) = session_manager.GetSessionEnumerator() {
+                for i in 0..enumerator.GetCount().unwrap_or(0) {
+                    if let Ok(session) = enumerator.GetSession(i) {
+                        let _ = add_session(sessions, session, &mut system);
+                    }
+                }
+            }
 
-            for i in 0..count {
-                let session = enumerator.GetSession(i).unwrap();
+            Ok((session_manager, notification))
+        }
+    }
 
-                // Get process ID
-                let session2: IAudioSessionControl2 = session.cast().unwrap();
-                let pid = Pid::from_u32(session2.GetProcessId().unwrap());
+    /// Removes sessions that have closed or whose process has exited.
+    pub fn prune(&mut self) {
+        if self.last_prune.elapsed() < PRUNE_INTERVAL {
+            return;
+        }
+        self.last_prune = Instant::now();
 
-                // Skip idle application
-                if pid == Pid::from_u32(0) { continue; }
+        // Only the processes with sessions are checked, without holding the lock the callbacks need
+        let mut pids: Vec<Pid> = self.sessions.lock().unwrap().iter().map(|session| session.pid).collect();
+        pids.sort_unstable();
+        pids.dedup();
+        let exited: Vec<Pid> = pids.into_iter().filter(|&pid| !is_running(&mut self.system, pid)).collect();
 
-                let process = system.process(pid).unwrap();
+        let removed: Vec<AudioSession> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let (removed, kept) = sessions.drain(..).partition(|session| session.expired || exited.contains(&session.pid));
+            *sessions = kept;
+            removed
+        };
 
-                current_apps.borrow_mut().push((process.name().to_string(), pid));
-            }
+        // Unregistering waits for callbacks in progress, so is done without holding the lock they need
+        for session in removed {
+            unsafe { let _ = session.control.UnregisterAudioSessionNotification(&session.events); }
         }
+    }
 
-        AppMonitor { current_apps, session_manager, notification }
+    /// Returns the resource the icon of an application's sessions is loaded from, if it gives one.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `pid` - Is the application's process.
+    pub fn icon_path(&self, pid: Pid) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.iter().find(|session| session.pid == pid && !session.icon_path.is_empty()).map(|session| session.icon_path.clone())
     }
 
+    /// Returns the name and PID of every application with an open session, listing each application once.
+    /// 
+    /// The session's display name is used where the application gives one, otherwise its process name.
     pub fn get_opened_info(&self) -> Vec<(String, Pid)> {
-        self.current_apps.borrow().clone()
+        let sessions = self.sessions.lock().unwrap();
+        let mut applications: Vec<(String, Pid)> = Vec::new();
+
+        for session in sessions.iter().filter(|session| !session.expired) {
+            // Names starting with @ are references to resources rather than text to show
+            let display_name = Some(session.display_name.as_str()).filter(|name| !name.is_empty() && !name.starts_with('@'));
+            match applications.iter_mut().find(|(_, pid)| *pid == session.pid) {
+                Some((name, _)) => if let Some(display_name) = display_name {
+                    *name = display_name.to_string();
+                },
+                None => {
+                    let name = display_name.unwrap_or_else(|| session.process_name.trim_end_matches(".exe"));
+                    applications.push((name.to_string(), session.pid));
+                }
+            }
+        }
+
+        applications
     }
 }
 
 impl Drop for AppMonitor {
     fn drop(&mut self) {
         // Unregister from audio notifications
-        unsafe { self.session_manager.UnregisterSessionNotification(&self.notification).unwrap(); }
+        if let Some((session_manager, notification)) = &self.registration {
+            unsafe { let _ = session_manager.UnregisterSessionNotification(notification); }
+        }
+
+        let sessions: Vec<AudioSession> = self.sessions.lock().unwrap().drain(..).collect();
+        for session in sessions {
+            unsafe { let _ = session.control.UnregisterAudioSessionNotification(&session.events); }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation_counter;
+    use crate::sample_ring;
+
+    #[test]
+    fn decode_frames_keeps_incomplete_frames() {
+        let bytes = [0x00, 0x40, 0x00, 0xC0, 0xFF, 0x7F];
+        let mut data = Vec::new();
+
+        // Two channels of 16 bit samples, the last sample is half a frame
+        assert_eq!(SampleEncoding::Int16.decode_frames(&bytes, 4, &mut data), 4);
+        assert_eq!(data, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn capture_decode_does_not_allocate() {
+        let packet: Vec<u8> = (0..480 * 2).flat_map(|i| (i as f32 / 960.0).to_le_bytes()).collect();
+        let mut data = Vec::with_capacity(48000 * 2);
+        let (mut samples, _consumer) = sample_ring::sample_ring(48000 * 2);
+
+        let allocations = allocation_counter::allocations(|| {
+            for _ in 0..10 {
+                SampleEncoding::Float32.decode_frames(&packet, 8, &mut data);
+                samples.push_slice(&data);
+            }
+        });
+        assert_eq!(allocations, 0);
+        assert_eq!(data.len(), 960);
+    }
+}
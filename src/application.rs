@@ -1,4 +1,4 @@
-use std::{ num::NonZeroU32, sync::mpsc::{self, Receiver, Sender}, time::Instant };
+use std::{ num::NonZeroU32, sync::{Arc, Mutex}, time::Instant };
 use glutin::{ 
     context::{ ContextAttributesBuilder, NotCurrentGlContext, PossiblyCurrentContext },
     config::ConfigTemplateBuilder,
@@ -18,9 +18,8 @@ use imgui_winit_support::{
 };
 use raw_window_handle::HasRawWindowHandle;
 use imgui::Ui;
-use rustfft::num_complex::Complex;
 
-use crate::{fft_renderer::FftRenderer, file_audio_manager::FileAudioManager, app_audio_manager::AppAudioManager};
+use crate::{common_audio_manager::{self, AnalysisOptions}, fft_renderer::FftRenderer, file_audio_manager::FileAudioManager, app_audio_manager::{AppAudioManager, CaptureSource}, settings::Settings, stream_source::StreamSettings};
 
 /// Holds all necessary information about our application.
 pub struct Application {
@@ -34,11 +33,17 @@ pub struct Application {
     visualisation_renderer: FftRenderer,
     file_audio_manager: FileAudioManager,
     app_audio_manager: AppAudioManager,
+    /// Why the settings couldn't be loaded, in which case they aren't saved so the file isn't overwritten.
+    settings_error: Option<String>,
 }
 
 impl Application {
     /// Initialises and creates a new application.
-    pub fn new() -> Self {
+    /// 
+    /// # Arguments
+    /// 
+    /// * `stream_settings` - Is the stream of raw samples given on the command line, which is captured straight away.
+    pub fn new(stream_settings: Option<StreamSettings>) -> Self {
         let (event_loop, window, surface, context) = Self::create_window();
         let (winit_platform, mut imgui_context) = Self::imgui_init(&window);
         let glow_context = Self::glow_context(&context);
@@ -48,12 +53,31 @@ impl Application {
             .expect("Failed to create ImGui renderer: ");
 
         // Create communications channels between the audio managers and renderer
-        let (transmit, receive): (Sender<Vec<(Complex<f32>, f32)>>, Receiver<Vec<(Complex<f32>, f32)>>) = mpsc::channel();
+        let (transmit, receive) = common_audio_manager::frame_channel();
+
+        // Options used by the audio managers, shared with the renderer so they can be changed from the UI
+        let analysis_options = Arc::new(Mutex::new(AnalysisOptions::default()));
 
         // Initialise the FFT visualisation renderer and audio managers
-        let visualisation_renderer = FftRenderer::new(receive);
-        let file_audio_manager = FileAudioManager::new(transmit.clone());
-        let app_audio_manager = AppAudioManager::new(transmit);
+        let mut visualisation_renderer = FftRenderer::new(receive, analysis_options.clone());
+        let mut file_audio_manager = FileAudioManager::new(transmit.clone(), analysis_options.clone());
+        let mut app_audio_manager = AppAudioManager::new(transmit, analysis_options);
+
+        // Start visualising a stream given on the command line without needing it selected
+        if stream_settings.is_some() {
+            app_audio_manager.set_stream_settings(stream_settings);
+            app_audio_manager.update(&[CaptureSource::Stream]);
+            app_audio_manager.start();
+        }
+
+        // Restore the settings from the last run
+        let (settings, settings_error) = match Settings::load() {
+            Ok(settings) => (settings, None),
+            Err(error) => (Settings::default(), Some(error)),
+        };
+        visualisation_renderer.set_theme(settings.theme);
+        file_audio_manager.set_equaliser(settings.equaliser);
+        file_audio_manager.set_eq_presets(settings.eq_presets);
 
         Application {
             event_loop,
@@ -65,7 +89,8 @@ impl Application {
             ig_renderer,
             visualisation_renderer,
             file_audio_manager,
-            app_audio_manager
+            app_audio_manager,
+            settings_error
         }
     }
 
@@ -83,7 +108,8 @@ impl Application {
             mut ig_renderer,
             mut visualisation_renderer,
             mut file_audio_manager,
-            mut app_audio_manager
+            mut app_audio_manager,
+            settings_error
         } = self;
         let mut last_frame = Instant::now();
 
@@ -108,6 +134,14 @@ impl Application {
                     let ui = imgui_context.frame();
                     let mut run = true;
                     run_ui(&mut run, ui, &mut visualisation_renderer, &mut file_audio_manager, &mut app_audio_manager);
+
+                    // Keep telling the user their settings weren't loaded, as changes to them won't be saved
+                    if let Some(error) = &settings_error {
+                        ui.window("Settings").size([300.0, 80.0], imgui::Condition::FirstUseEver).build(|| {
+                            ui.text_colored(crate::ERROR_COLOUR, error);
+                            ui.text_wrapped("Settings won't be saved until the file is fixed or removed.");
+                        });
+                    }
                     if !run {
                         window_target.exit();
                     }
@@ -119,8 +153,16 @@ impl Application {
                     // TODO: Can potentially recover from this so maybe change away from expect
                     surface.swap_buffers(&context).expect("Failed to swap buffers: ");
                 }
-                // Exit when requested
+                // Save settings and exit when requested, failing to save shouldn't prevent the app closing
                 event::Event::WindowEvent { event: event::WindowEvent::CloseRequested, .. } => {
+                    if settings_error.is_none() {
+                        let settings = Settings {
+                            theme: visualisation_renderer.theme().clone(),
+                            equaliser: file_audio_manager.equaliser(),
+                            eq_presets: file_audio_manager.eq_presets().to_vec(),
+                        };
+                        let _ = settings.save();
+                    }
                     window_target.exit();
                 }
                 // When resize is requested, ensure everything is done correctly
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use rustfft::num_complex::Complex;
+
+/// Length in seconds of the onset history used to estimate the tempo.
+const HISTORY_LENGTH: f32 = 8.0;
+
+/// Minimum length in seconds of onset history needed before a tempo is estimated.
+const MIN_HISTORY_LENGTH: f32 = 4.0;
+
+/// Length in seconds of the recent flux averaged for the onset threshold.
+const THRESHOLD_LENGTH: f32 = 0.5;
+
+/// Amount the flux must exceed the recent average by to count as an onset.
+const THRESHOLD_MULTIPLIER: f32 = 1.5;
+
+/// Minimum time in seconds between onsets.
+const MIN_ONSET_INTERVAL: f32 = 0.1;
+
+/// Slowest tempo that can be detected.
+const MIN_BPM: f32 = 60.0;
+
+/// Fastest tempo that can be detected.
+const MAX_BPM: f32 = 200.0;
+
+/// Tempo that ambiguous estimates are biased towards to avoid half and double time errors.
+const PREFERRED_BPM: f32 = 120.0;
+
+/// Compression applied to magnitudes before calculating the flux, so quiet bins still contribute.
+const LOG_COMPRESSION: f32 = 1000.0;
+
+/// Fraction of the phase error corrected when an onset lands near a predicted beat.
+const PHASE_CORRECTION: f32 = 0.3;
+
+/// Fraction of a beat period either side of a predicted beat an onset is used for phase correction.
+const PHASE_WINDOW: f32 = 0.2;
+
+/// Results of beat tracking for a single frame.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BeatInfo {
+    pub onset: bool,
+    pub beat: bool,
+    pub bpm: Option<f32>,
+}
+
+/// Holds all information needed to detect onsets and track the beat from a sequence of spectra.
+/// 
+/// Onsets are found with spectral flux, the tempo is estimated from the autocorrelation of the flux history, and the
+/// phase of the beat is predicted from the tempo and nudged towards onsets that land near a predicted beat.
+pub struct BeatTracker {
+    frame_rate: f32,
+    current_spectrum: Vec<f32>,
+    previous_spectrum: Vec<f32>,
+    flux_history: VecDeque<f32>,
+    history_len: usize,
+    envelope: Vec<f32>,
+    scores: Vec<f32>,
+    frames_since_onset: f32,
+    beat_phase: f32,
+    bpm: Option<f32>,
+}
+
+impl BeatTracker {
+    /// Create a new beat tracker.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frame_rate` - Is the number of spectra processed per second.
+    pub fn new(frame_rate: f32) -> Self {
+        let history_len = (HISTORY_LENGTH * frame_rate) as usize;
+
+        BeatTracker {
+            frame_rate,
+            current_spectrum: Vec::new(),
+            previous_spectrum: Vec::new(),
+            flux_history: VecDeque::with_capacity(history_len),
+            history_len,
+            envelope: Vec::with_capacity(history_len),
+            scores: Vec::with_capacity(history_len),
+            frames_since_onset: f32::MAX,
+            beat_phase: 0.0,
+            bpm: None,
+        }
+    }
+
+    /// Processes the next spectrum and returns whether it contains an onset or beat, and the current tempo.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `spectrum` - Is the FFT data of the frame.
+    pub fn process(&mut self, spectrum: &[(Complex<f32>, f32)]) -> BeatInfo {
+        let flux = self.spectral_flux(spectrum);
+        let onset = self.detect_onset(flux);
+
+        // Keep a fixed length history of the flux
+        if self.flux_history.len() == self.history_len {
+            self.flux_history.pop_front();
+        }
+        self.flux_history.push_back(flux);
+
+        self.bpm = self.estimate_tempo();
+        let beat = self.track_phase(onset);
+
+        BeatInfo { onset, beat, bpm: self.bpm }
+    }
+
+    /// Returns the sum of the increases in log magnitude of each bin since the last spectrum.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `spectrum` - Is the FFT data of the frame.
+    fn spectral_flux(&mut self, spectrum: &[(Complex<f32>, f32)]) -> f32 {
+        // The buffers of this and the previous spectrum are swapped each frame so their memory is reused
+        std::mem::swap(&mut self.current_spectrum, &mut self.previous_spectrum);
+        self.current_spectrum.clear();
+        self.current_spectrum.extend(spectrum.iter().map(|x| (1.0 + LOG_COMPRESSION * x.0.norm()).ln()));
+
+        // If the source has changed there is nothing to compare against
+        if self.current_spectrum.len() == self.previous_spectrum.len() {
+            self.current_spectrum.iter().zip(self.previous_spectrum.iter()).map(|(now, before)| (now - before).max(0.0)).sum()
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns whether the flux is an onset, that is it is well above the recent average and not too close to the last onset.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `flux` - Is the spectral flux of the frame.
+    fn detect_onset(&mut self, flux: f32) -> bool {
+        let recent = ((THRESHOLD_LENGTH * self.frame_rate) as usize).max(1);
+        let count = recent.min(self.flux_history.len());
+        self.frames_since_onset += 1.0;
+        if count == 0 {
+            return false;
+        }
+
+        let average = self.flux_history.iter().rev().take(count).sum::<f32>() / count as f32;
+        let onset = flux > average * THRESHOLD_MULTIPLIER && self.frames_since_onset >= MIN_ONSET_INTERVAL * self.frame_rate;
+        if onset {
+            self.frames_since_onset = 0.0;
+        }
+
+        onset
+    }
+
+    /// Estimates the tempo by finding the lag with the strongest autocorrelation in the flux history.
+    fn estimate_tempo(&mut self) -> Option<f32> {
+        if (self.flux_history.len() as f32) < MIN_HISTORY_LENGTH * self.frame_rate {
+            return None;
+        }
+
+        // Remove the mean so the autocorrelation isn't dominated by the overall level
+        let mean = self.flux_history.iter().sum::<f32>() / self.flux_history.len() as f32;
+        self.envelope.clear();
+        self.envelope.extend(self.flux_history.iter().map(|x| x - mean));
+        let envelope = &self.envelope;
+
+        let min_lag = (60.0 * self.frame_rate / MAX_BPM).floor().max(1.0) as usize;
+        let max_lag = (60.0 * self.frame_rate / MIN_BPM).ceil() as usize;
+        if max_lag + 1 >= envelope.len() {
+            return None;
+        }
+
+        let autocorrelation = |lag: usize| -> f32 {
+            envelope.iter().zip(envelope.iter().skip(lag)).map(|(a, b)| a * b).sum::<f32>() / (envelope.len() - lag) as f32
+        };
+
+        // Weight each lag by how close its tempo is to the preferred tempo on a log scale
+        let frame_rate = self.frame_rate;
+        let weight = |lag: f32| -> f32 {
+            let octaves = (60.0 * frame_rate / lag / PREFERRED_BPM).log2();
+            (-0.5 * octaves * octaves).exp()
+        };
+
+        self.scores.clear();
+        self.scores.extend((min_lag - 1..=max_lag + 1).map(|lag| autocorrelation(lag) * weight(lag as f32)));
+        let scores = &self.scores;
+        let (best, &score) = scores.iter().enumerate().skip(1).take(scores.len() - 2).max_by(|a, b| a.1.total_cmp(b.1))?;
+        if score <= 0.0 {
+            return None;
+        }
+
+        // Refine the lag between frames with a parabola through the neighbouring scores
+        let (before, after) = (scores[best - 1], scores[best + 1]);
+        let denominator = before - 2.0 * score + after;
+        let offset = if denominator != 0.0 { (0.5 * (before - after) / denominator).clamp(-0.5, 0.5) } else { 0.0 };
+        let lag = (min_lag - 1 + best) as f32 + offset;
+
+        Some(60.0 * self.frame_rate / lag)
+    }
+
+    /// Advances the predicted beat and returns whether a beat occurs in this frame.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `onset` - Is whether an onset was detected in this frame.
+    fn track_phase(&mut self, onset: bool) -> bool {
+        let Some(bpm) = self.bpm else {
+            self.beat_phase = 0.0;
+            return false;
+        };
+
+        let period = 60.0 * self.frame_rate / bpm;
+
+        // Pull the phase towards onsets that land near the predicted beat
+        if onset {
+            if self.beat_phase < PHASE_WINDOW * period {
+                self.beat_phase -= PHASE_CORRECTION * self.beat_phase;
+            } else if self.beat_phase > (1.0 - PHASE_WINDOW) * period {
+                self.beat_phase += PHASE_CORRECTION * (period - self.beat_phase);
+            }
+        }
+
+        self.beat_phase += 1.0;
+        if self.beat_phase >= period {
+            self.beat_phase -= period;
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of spectra processed per second by the analysis.
+    const FRAME_RATE: f32 = 20.0;
+
+    /// Feeds the spectra of a click train to a beat tracker, returning the result of each frame.
+    fn click_train(bpm: f32, seconds: f32) -> Vec<BeatInfo> {
+        let mut tracker = BeatTracker::new(FRAME_RATE);
+        let period = 60.0 * FRAME_RATE / bpm;
+        let quiet: Vec<(Complex<f32>, f32)> = (0..64).map(|bin| (Complex::new(0.001, 0.0), bin as f32 * 100.0)).collect();
+        let click: Vec<(Complex<f32>, f32)> = quiet.iter().map(|&(_, frequency)| (Complex::new(0.5, 0.0), frequency)).collect();
+
+        let mut next_click = 0.0;
+        (0..(seconds * FRAME_RATE) as usize).map(|frame| {
+            let clicked = frame as f32 >= next_click;
+            if clicked {
+                next_click += period;
+            }
+            tracker.process(if clicked { &click } else { &quiet })
+        }).collect()
+    }
+
+    /// Returns the number of frames between each beat.
+    fn beat_spacing(frames: &[BeatInfo]) -> Vec<usize> {
+        let beats: Vec<usize> = frames.iter().enumerate().filter(|(_, info)| info.beat).map(|(frame, _)| frame).collect();
+        beats.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[test]
+    fn detects_clicks_as_onsets() {
+        let frames = click_train(120.0, 4.0);
+        let onsets: Vec<usize> = frames.iter().enumerate().filter(|(_, info)| info.onset).map(|(frame, _)| frame).collect();
+
+        // The first click has no history to compare against
+        assert_eq!(onsets, (10..80).step_by(10).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn finds_tempo_and_beats_of_click_train() {
+        for bpm in [90.0, 120.0, 150.0] {
+            let frames = click_train(bpm, 12.0);
+            assert!(frames[..(MIN_HISTORY_LENGTH * FRAME_RATE) as usize - 1].iter().all(|info| info.bpm.is_none()));
+
+            let estimate = frames.last().unwrap().bpm.unwrap();
+            assert!((estimate - bpm).abs() < 2.0, "{bpm} BPM was estimated as {estimate}");
+
+            // Beats fall on whole frames, so their spacing alternates around the period when it isn't whole
+            let period = 60.0 * FRAME_RATE / bpm;
+            let spacing = beat_spacing(&frames[frames.len() / 2..]);
+            assert!(!spacing.is_empty());
+            assert!(spacing.iter().all(|&frames| (frames as f32 - period).abs() <= 1.0), "{bpm} BPM: {spacing:?}");
+        }
+    }
+
+    #[test]
+    fn silence_has_no_tempo() {
+        let mut tracker = BeatTracker::new(FRAME_RATE);
+        let silence = vec![(Complex::new(0.0, 0.0), 100.0); 64];
+        let frames: Vec<BeatInfo> = (0..200).map(|_| tracker.process(&silence)).collect();
+        assert!(frames.iter().all(|info| !info.onset && !info.beat && info.bpm.is_none()));
+    }
+}
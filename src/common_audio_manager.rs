@@ -1,44 +1,792 @@
-use std::sync::{mpsc::Sender, Arc};
+use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, SyncSender, TryIter, TrySendError}, Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
 use rustfft::{num_complex::Complex, Fft};
 
+use crate::{FFT_FREQUENCY, FFT_OVERLAP};
+use crate::beat_tracker::{BeatInfo, BeatTracker};
+use crate::constant_q::ConstantQ;
+use crate::level_meter::{ChannelLevel, LevelMeter};
+use crate::loudness_meter::{LoudnessMeter, LoudnessReading};
+use crate::pitch_analyser::{PitchAnalyser, PitchInfo};
+use crate::sample_ring::{self, SampleConsumer, SampleProducer};
+use crate::scales::{MAX_FREQUENCY, MIN_FREQUENCY};
+use crate::spectral_features::{FeatureExtractor, SpectralFeatures};
+
+/// Number of frames that can wait in the channel for the renderer, frames are dropped rather than queued beyond this.
+const FRAME_QUEUE_LENGTH: usize = 64;
+
+/// Number of frames shared between the analysis workers and the renderer, enough for a full queue and a full renderer.
+const FRAME_POOL_LENGTH: usize = 2 * FRAME_QUEUE_LENGTH;
+
+/// Length in seconds of audio the ring between an audio thread and its analysis worker can hold.
+const SAMPLE_RING_LENGTH: f32 = 2.0;
+
+/// Time the analysis worker sleeps for when there are no samples waiting.
+const WORKER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The transforms that can be used to calculate the spectrum.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpectrumMode {
+    /// A linear FFT with evenly spaced bins.
+    Linear,
+    /// A constant-Q transform with a bin for each semitone.
+    ConstantQ,
+}
+
+impl SpectrumMode {
+    /// All spectrum modes in the order they are shown to the user.
+    pub const ALL: [SpectrumMode; 2] = [SpectrumMode::Linear, SpectrumMode::ConstantQ];
+
+    /// Returns the display name of the spectrum mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpectrumMode::Linear => "Linear FFT",
+            SpectrumMode::ConstantQ => "Constant-Q",
+        }
+    }
+}
+
+/// How the channels of a source are combined before the FFT.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelMode {
+    /// Only the first channel.
+    Left,
+    /// Only the second channel, or the first if the source is mono.
+    Right,
+    /// The average of the left and right channels.
+    Mid,
+    /// Half the difference between the left and right channels.
+    Side,
+    /// Every channel analysed separately and drawn on top of each other, the mid signal is used for analysis.
+    Overlay,
+    /// A single channel of a multi-channel source.
+    Channel(u16),
+}
+
+impl ChannelMode {
+    /// Returns the modes that can be used with a number of channels in the order they are shown to the user.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `channels` - Is the number of channels in the source.
+    pub fn available(channels: u16) -> Vec<ChannelMode> {
+        let mut modes = vec![ChannelMode::Left, ChannelMode::Right, ChannelMode::Mid, ChannelMode::Side, ChannelMode::Overlay];
+        if channels > 2 {
+            modes.extend((0..channels).map(ChannelMode::Channel));
+        }
+
+        modes
+    }
+
+    /// Returns the display name of the channel mode.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `channels` - Is the number of channels in the source.
+    pub fn name(&self, channels: u16) -> String {
+        match self {
+            ChannelMode::Left => String::from("Left"),
+            ChannelMode::Right => String::from("Right"),
+            ChannelMode::Mid => String::from("Mid (L+R)"),
+            ChannelMode::Side => String::from("Side (L-R)"),
+            ChannelMode::Overlay => String::from("Overlay"),
+            ChannelMode::Channel(channel) => format!("Channel {}", channel_name(*channel, channels)),
+        }
+    }
+
+    /// Combines the channels into the signal that is analysed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `channels` - Is the samples of each channel.
+    /// 
+    /// * `output` - Is the vector the signal is written to, its memory is reused.
+    fn mix(&self, channels: &[Vec<f32>], output: &mut Vec<f32>) {
+        output.clear();
+        let Some(left) = channels.first() else {
+            return;
+        };
+        let right = channels.get(1).unwrap_or(left);
+
+        match self {
+            ChannelMode::Left => output.extend_from_slice(left),
+            ChannelMode::Right => output.extend_from_slice(right),
+            ChannelMode::Mid | ChannelMode::Overlay => output.extend(left.iter().zip(right.iter()).map(|(l, r)| (l + r) / 2.0)),
+            ChannelMode::Side => output.extend(left.iter().zip(right.iter()).map(|(l, r)| (l - r) / 2.0)),
+            ChannelMode::Channel(channel) => output.extend_from_slice(channels.get(*channel as usize).unwrap_or(left)),
+        }
+    }
+}
+
+/// Returns the name of a channel, using the standard speaker names for stereo, 5.1 and 7.1 sources.
+/// 
+/// # Arguments
+/// 
+/// * `channel` - Is the index of the channel.
+/// 
+/// * `channels` - Is the number of channels in the source.
+pub fn channel_name(channel: u16, channels: u16) -> String {
+    let names: &[&str] = match channels {
+        2 => &["L", "R"],
+        6 => &["L", "R", "C", "LFE", "Ls", "Rs"],
+        8 => &["L", "R", "C", "LFE", "Lb", "Rb", "Ls", "Rs"],
+        _ => &[],
+    };
+
+    names.get(channel as usize).map(|name| name.to_string()).unwrap_or_else(|| (channel + 1).to_string())
+}
+
+/// Options controlling how the audio is analysed, shared between the UI and the audio managers.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AnalysisOptions {
+    pub spectrum_mode: SpectrumMode,
+    pub channel_mode: ChannelMode,
+    pub spectral_features: bool,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        AnalysisOptions { spectrum_mode: SpectrumMode::Linear, channel_mode: ChannelMode::Mid, spectral_features: false }
+    }
+}
+
+/// A single frame of FFT data along with the moment it becomes audible.
+pub struct FftFrame {
+    pub data: Vec<(Complex<f32>, f32)>,
+    pub overlays: Vec<Vec<(Complex<f32>, f32)>>,
+    pub timestamp: Instant,
+    pub beat: BeatInfo,
+    pub pitch: PitchInfo,
+    pub loudness: LoudnessReading,
+    pub levels: Vec<ChannelLevel>,
+    pub features: Option<SpectralFeatures>,
+}
+
+impl FftFrame {
+    /// Returns a frame with no data, its buffers grow to the size needed the first time it is used.
+    fn new() -> Self {
+        FftFrame {
+            data: Vec::new(),
+            overlays: Vec::new(),
+            timestamp: Instant::now(),
+            beat: BeatInfo::default(),
+            pitch: PitchInfo::default(),
+            loudness: LoudnessReading::default(),
+            levels: Vec::new(),
+            features: None,
+        }
+    }
+}
+
+/// Creates a channel for sending frames from the analysis workers to the renderer.
+/// 
+/// Every frame is created here and passed back and forth through a fixed pool, the renderer returns frames it has
+/// finished with, so once the buffers of the frames have grown to size no memory is allocated for them.
+pub fn frame_channel() -> (FrameSender, FrameReceiver) {
+    let (sender, receiver) = mpsc::sync_channel(FRAME_QUEUE_LENGTH);
+    let pool = Arc::new(Mutex::new((0..FRAME_POOL_LENGTH).map(|_| FftFrame::new()).collect::<Vec<_>>()));
+
+    (FrameSender { sender, pool: pool.clone() }, FrameReceiver { receiver, pool })
+}
+
+/// The sending half of a frame channel.
+#[derive(Clone)]
+pub struct FrameSender {
+    sender: SyncSender<FftFrame>,
+    pool: Arc<Mutex<Vec<FftFrame>>>,
+}
+
+impl FrameSender {
+    /// Returns a used frame to fill, or nothing if every frame is waiting for the renderer.
+    pub fn frame(&self) -> Option<FftFrame> {
+        self.pool.lock().unwrap().pop()
+    }
+
+    /// Sends a frame to the renderer, if the renderer has fallen behind the frame is returned to the pool instead.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frame` - Is the frame to send.
+    pub fn send(&self, frame: FftFrame) {
+        if let Err(TrySendError::Full(frame) | TrySendError::Disconnected(frame)) = self.sender.try_send(frame) {
+            self.pool.lock().unwrap().push(frame);
+        }
+    }
+}
+
+/// The receiving half of a frame channel.
+pub struct FrameReceiver {
+    receiver: Receiver<FftFrame>,
+    pool: Arc<Mutex<Vec<FftFrame>>>,
+}
+
+impl FrameReceiver {
+    /// Returns an iterator over the frames that have been sent, without waiting for more.
+    pub fn try_iter(&self) -> TryIter<'_, FftFrame> {
+        self.receiver.try_iter()
+    }
+
+    /// Returns a frame that is no longer needed so its buffers can be reused.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frame` - Is the frame to return.
+    pub fn recycle(&self, frame: FftFrame) {
+        self.pool.lock().unwrap().push(frame);
+    }
+}
+
+/// Records when the audio passed to an analysis worker is heard.
+/// 
+/// The clock holds the moment a single frame is heard, every other frame is heard at the sample rate from there. The
+/// thread passing the audio on updates it as it learns more, such as when the output device takes a new buffer.
+#[derive(Clone, Default)]
+pub struct PlaybackClock {
+    anchor: Arc<Mutex<Option<(u64, Instant)>>>,
+}
+
+impl PlaybackClock {
+    /// Records the moment a frame is heard, without waiting if the worker is reading the clock.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frame` - Is the index of the frame, counting every frame written to the worker.
+    /// 
+    /// * `heard` - Is the moment the frame is heard.
+    pub fn set(&self, frame: u64, heard: Instant) {
+        if let Ok(mut anchor) = self.anchor.try_lock() {
+            *anchor = Some((frame, heard));
+        }
+    }
+
+    /// Returns the moment a frame is heard, or now if the clock hasn't been set.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frame` - Is the index of the frame, counting every frame written to the worker.
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    fn heard_at(&self, frame: u64, sample_rate: u32) -> Instant {
+        let now = Instant::now();
+        let Some((anchor_frame, anchor)) = *self.anchor.lock().unwrap() else {
+            return now;
+        };
+
+        let offset = |frames: u64| Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+        if frame >= anchor_frame {
+            anchor + offset(frame - anchor_frame)
+        } else {
+            anchor.checked_sub(offset(anchor_frame - frame)).unwrap_or(now)
+        }
+    }
+}
+
 /// Holds all information needed for the calculating the FFT and sending the data to its destination.
-pub struct FftHandler {
-    sample_destination: Sender<Vec<(Complex<f32>, f32)>>,
+struct FftHandler {
+    sample_destination: FrameSender,
     sample_rate: u32,
     fft: Arc<dyn Fft<f32>>,
+    fft_buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    clock: PlaybackClock,
+    analysis_options: Arc<Mutex<AnalysisOptions>>,
+    mixed: Vec<f32>,
+    constant_q: Vec<ConstantQ>,
+    beat_tracker: BeatTracker,
+    pitch_analyser: PitchAnalyser,
+    loudness_meter: LoudnessMeter,
+    level_meter: LevelMeter,
+    feature_extractor: FeatureExtractor,
 }
 
 impl FftHandler {
     /// Create a new FFT handler.
-    pub fn new(sample_destination: Sender<Vec<(Complex<f32>, f32)>>, sample_rate: u32, fft: Arc<dyn Fft<f32>>) -> Self {
-        FftHandler { sample_destination, sample_rate, fft }
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_destination` - Is the sender to the renderer.
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    /// 
+    /// * `channels` - Is the number of interleaved channels passed to `measure`.
+    /// 
+    /// * `fft` - Is the FFT algorithm to use.
+    /// 
+    /// * `clock` - Is when the samples given to the handler are heard.
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the audio, shared with the UI so they can be changed.
+    pub fn new(
+        sample_destination: FrameSender,
+        sample_rate: u32,
+        channels: u16,
+        fft: Arc<dyn Fft<f32>>,
+        clock: PlaybackClock,
+        analysis_options: Arc<Mutex<AnalysisOptions>>
+    ) -> Self {
+        let constant_q = vec![ConstantQ::new(sample_rate)];
+        let beat_tracker = BeatTracker::new((FFT_FREQUENCY * FFT_OVERLAP) as f32);
+        let pitch_analyser = PitchAnalyser::new(sample_rate);
+        let loudness_meter = LoudnessMeter::new(sample_rate, channels);
+        let level_meter = LevelMeter::new(channels);
+
+        // Buffers for the FFT are allocated up front so none are needed on the audio thread
+        let fft_buffer = Vec::with_capacity(fft.len());
+        let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+        let mixed = Vec::with_capacity(fft.len());
+
+        FftHandler {
+            sample_destination,
+            sample_rate,
+            fft,
+            fft_buffer,
+            scratch,
+            clock,
+            analysis_options,
+            mixed,
+            constant_q,
+            beat_tracker,
+            pitch_analyser,
+            loudness_meter,
+            level_meter,
+            feature_extractor: FeatureExtractor::default()
+        }
     }
 
-    /// Performs the FFT on the provided data and sends the FFT data to the renderer.
+    /// Measures the loudness and level of the audio, this must be given every sample of every channel.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample` - Is the next interleaved sample.
+    pub fn measure(&mut self, sample: f32) {
+        self.loudness_meter.process_sample(sample);
+        self.level_meter.process_sample(sample);
+    }
+
+    /// Performs the FFT on the provided channels and sends the FFT data to the renderer.
     /// 
     /// # Arguments 
     ///
-    /// * `data` - Is the audio data to perform the FFT on.
-    pub fn perform_fft(&mut self, data: &[f32]) {
-        // Create vec for processed data
-        let mut processed_data = data.iter().map(|&x| Complex::new(x, 0.0)).collect::<Vec<Complex<f32>>>();
-
-        // Perform FFT
-        self.fft.process(&mut processed_data);
-        processed_data.drain((processed_data.len() / 2)..processed_data.len());
-        
-        // Calculate the frequency for each bin
-        let step = self.sample_rate as f32 / processed_data.len() as f32;
-        let mut transformed_data = Vec::new();
-        for (index, amp) in processed_data.iter().enumerate() {
-            let fr = index as f32 * step;
-            transformed_data.push((*amp, fr));
+    /// * `channels` - Is the audio data of each channel to perform the FFT on.
+    /// 
+    /// * `end_frame` - Is the index of the frame following the window, counting every frame given to the handler.
+    pub fn perform_fft(&mut self, channels: &[Vec<f32>], end_frame: u64) {
+        let options = *self.analysis_options.lock().unwrap();
+
+        // The mixed signal is taken out of the handler while it is analysed so the handler can still be borrowed
+        let mut mixed = std::mem::take(&mut self.mixed);
+        options.channel_mode.mix(channels, &mut mixed);
+        let data = mixed.as_slice();
+        let timestamp = self.timestamp(data.len(), end_frame);
+
+        // The constant-Q histories are always kept up to date so the modes can be switched seamlessly, the first is
+        // for the mixed signal and the rest are for each channel
+        while self.constant_q.len() < channels.len() + 1 {
+            let constant_q = self.constant_q[0].clone();
+            self.constant_q.push(constant_q);
+        }
+        self.constant_q[0].push_window(data, FFT_OVERLAP as usize);
+        for (channel, constant_q) in channels.iter().zip(self.constant_q.iter_mut().skip(1)) {
+            constant_q.push_window(channel, FFT_OVERLAP as usize);
+        }
+
+        // Reuse a frame the renderer has finished with, if the renderer is holding every frame this one is skipped
+        let Some(mut frame) = self.sample_destination.frame() else {
+            self.mixed = mixed;
+            return;
+        };
+        self.spectrum(options.spectrum_mode, 0, data, &mut frame.data);
+
+        // Each channel is drawn separately when overlaid
+        let overlays = if options.channel_mode == ChannelMode::Overlay { channels.len() } else { 0 };
+        frame.overlays.resize_with(overlays, Vec::new);
+        for (index, overlay) in frame.overlays.iter_mut().enumerate() {
+            self.spectrum(options.spectrum_mode, index + 1, &channels[index], overlay);
+        }
+
+        frame.timestamp = timestamp;
+        frame.beat = self.beat_tracker.process(&frame.data);
+        frame.pitch = self.pitch_analyser.process(data, &frame.data);
+        frame.features = options.spectral_features.then(|| self.feature_extractor.process(data, &frame.data));
+        frame.loudness = self.loudness_meter.reading();
+        self.level_meter.take_levels(&mut frame.levels);
+
+        self.sample_destination.send(frame);
+        self.mixed = mixed;
+    }
+
+    /// Replaces the output with the audible part of the spectrum of a signal.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `mode` - Is the transform to use.
+    /// 
+    /// * `index` - Is the index of the constant-Q history of the signal.
+    /// 
+    /// * `data` - Is the audio data of the signal.
+    /// 
+    /// * `output` - Is the vector the spectrum is written to, its memory is reused.
+    fn spectrum(&mut self, mode: SpectrumMode, index: usize, data: &[f32], output: &mut Vec<(Complex<f32>, f32)>) {
+        output.clear();
+        match mode {
+            SpectrumMode::Linear => self.linear_spectrum(data, output),
+            SpectrumMode::ConstantQ => self.constant_q[index].transform(output),
         }
 
         // Remove inaudible frequencies
-        transformed_data.retain(|&x| x.1 > 20.0 && x.1 < 20000.0);
+        output.retain(|&x| x.1 > MIN_FREQUENCY && x.1 < MAX_FREQUENCY);
+    }
+
+    /// Performs a linear FFT on the provided data, adding the value and frequency of each bin to the output.
+    /// 
+    /// # Arguments 
+    ///
+    /// * `data` - Is the audio data to perform the FFT on.
+    /// 
+    /// * `output` - Is the vector the bins are added to.
+    fn linear_spectrum(&mut self, data: &[f32], output: &mut Vec<(Complex<f32>, f32)>) {
+        // Perform FFT in place
+        self.fft_buffer.clear();
+        self.fft_buffer.extend(data.iter().map(|&x| Complex::new(x, 0.0)));
+        self.fft.process_with_scratch(&mut self.fft_buffer, &mut self.scratch);
+
+        // Calculate the frequency for each bin, bins are spaced by the sample rate over the full FFT length
+        let step = self.sample_rate as f32 / data.len() as f32;
+
+        // Scale the bins so a full scale sine has a magnitude of 1, only the first half is unique
+        let scale = 2.0 / data.len() as f32;
+        let half = self.fft_buffer.len() / 2;
+        output.extend(self.fft_buffer[..half].iter().enumerate().map(|(index, amp)| (*amp * scale, index as f32 * step)));
+    }
+
+    /// Returns the moment the centre of the analysis window will be heard.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `window_len` - Is the number of samples of each channel in the analysis window.
+    /// 
+    /// * `end_frame` - Is the index of the frame following the window.
+    fn timestamp(&self, window_len: usize, end_frame: u64) -> Instant {
+        let centre = end_frame.saturating_sub(window_len as u64 / 2);
+        self.clock.heard_at(centre, self.sample_rate)
+    }
+}
+
+/// Stops an analysis worker and waits for its thread to finish when dropped.
+pub struct WorkerHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Holds all information needed for analysing audio on its own thread.
+/// 
+/// Audio threads only copy samples into a lock-free ring, so the analysis can never hold up playback or capture. The
+/// worker collects each channel into overlapping windows, performs the FFT on them and sends the frames on.
+pub struct AnalysisWorker {
+    samples: SampleConsumer,
+    incoming: Vec<f32>,
+    windows: Vec<Vec<f32>>,
+    window_len: usize,
+    channels: u16,
+    current_channel: u16,
+    /// Number of whole frames read from the ring.
+    frames: u64,
+    handler: FftHandler,
+}
+
+impl AnalysisWorker {
+    /// Starts a worker on a new thread, returning the producer the audio thread writes interleaved samples to and the
+    /// handle that stops it.
+    /// 
+    /// The worker stops once the producer is dropped and every sample written to it has been analysed, or straight away
+    /// when the handle is dropped.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_destination` - Is the sender to the renderer.
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the audio.
+    /// 
+    /// * `fft` - Is the FFT algorithm to use.
+    /// 
+    /// * `clock` - Is when the samples written to the worker are heard, counting frames from the first written.
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the audio, shared with the UI so they can be changed.
+    pub fn spawn(
+        sample_destination: FrameSender,
+        sample_rate: u32,
+        channels: u16,
+        fft: Arc<dyn Fft<f32>>,
+        clock: PlaybackClock,
+        analysis_options: Arc<Mutex<AnalysisOptions>>
+    ) -> (SampleProducer, WorkerHandle) {
+        let capacity = (SAMPLE_RING_LENGTH * sample_rate as f32) as usize * channels.max(1) as usize;
+        let (producer, samples) = sample_ring::sample_ring(capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+
+        let thread = thread::Builder::new()
+            .name("Analysis".to_string())
+            .spawn(move || {
+                // The handler precomputes its analysers, so is created here to keep that work off the calling thread
+                let handler = FftHandler::new(sample_destination, sample_rate, channels, fft, clock, analysis_options);
+                AnalysisWorker::new(samples, capacity, channels, handler).run(&worker_stop);
+            }).unwrap();
+
+        (producer, WorkerHandle { stop, thread: Some(thread) })
+    }
+
+    /// Analyses a whole signal on the calling thread as fast as it can be read, rather than as it is heard.
+    /// 
+    /// Frames are timestamped as if the signal started playing at `start`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the interleaved samples of the signal.
+    /// 
+    /// * `sample_rate` - Is the sample rate of the signal.
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the signal.
+    /// 
+    /// * `fft` - Is the FFT algorithm to use.
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the signal.
+    /// 
+    /// * `start` - Is the moment the first sample is treated as being heard.
+    /// 
+    /// * `on_frame` - Is called with each frame as soon as it is analysed.
+    pub fn analyse_offline(
+        mut samples: impl Iterator<Item = f32>,
+        sample_rate: u32,
+        channels: u16,
+        fft: Arc<dyn Fft<f32>>,
+        analysis_options: AnalysisOptions,
+        start: Instant,
+        mut on_frame: impl FnMut(&FftFrame)
+    ) {
+        // Samples are passed on a window at a time, so only a few frames are waiting when the channel is emptied
+        let batch_len = fft.len() * channels.max(1) as usize;
+        let (mut producer, consumer) = sample_ring::sample_ring(batch_len);
+        let (sender, receiver) = frame_channel();
+        let clock = PlaybackClock::default();
+        clock.set(0, start);
+
+        let handler = FftHandler::new(sender, sample_rate, channels, fft, clock, Arc::new(Mutex::new(analysis_options)));
+        let mut worker = AnalysisWorker::new(consumer, batch_len, channels, handler);
+        let mut batch = Vec::with_capacity(batch_len);
+
+        loop {
+            batch.clear();
+            batch.extend(samples.by_ref().take(batch_len));
+            if batch.is_empty() {
+                return;
+            }
+
+            producer.push_slice(&batch);
+            worker.analyse_waiting();
+            for frame in receiver.try_iter() {
+                on_frame(&frame);
+                receiver.recycle(frame);
+            }
+        }
+    }
+
+    /// Creates a worker reading from a ring.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the ring the interleaved samples are read from.
+    /// 
+    /// * `capacity` - Is the number of samples the ring can hold.
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the audio.
+    /// 
+    /// * `handler` - Is the handler the windows are analysed by.
+    fn new(samples: SampleConsumer, capacity: usize, channels: u16, handler: FftHandler) -> Self {
+        let window_len = handler.fft.len();
+        AnalysisWorker {
+            samples,
+            incoming: Vec::with_capacity(capacity),
+            windows: (0..channels.max(1)).map(|_| Vec::with_capacity(window_len)).collect(),
+            window_len,
+            channels: channels.max(1),
+            current_channel: 0,
+            frames: 0,
+            handler
+        }
+    }
+
+    /// Analyses samples as they arrive until the producer is dropped or the worker is stopped.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `stop` - Is set when the worker should stop without analysing the samples still waiting.
+    fn run(mut self, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            // Check before reading so samples written just before the producer was dropped are still analysed
+            let closed = self.samples.is_closed();
+            if self.analyse_waiting() == 0 {
+                if closed {
+                    return;
+                }
+
+                thread::sleep(WORKER_POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Analyses the samples waiting in the ring, returning how many there were.
+    fn analyse_waiting(&mut self) -> usize {
+        let count = self.samples.pop_into(&mut self.incoming);
+        for &sample in self.incoming.iter() {
+            // Loudness is measured across every channel
+            self.handler.measure(sample);
+
+            self.windows[self.current_channel as usize].push(sample);
+            self.current_channel = (self.current_channel + 1) % self.channels;
+            if self.current_channel == 0 {
+                self.frames += 1;
+            }
+
+            // If we have enough samples of every channel to perform an FFT, then do so
+            if self.current_channel == 0 && self.windows[0].len() == self.window_len {
+                self.handler.perform_fft(&self.windows, self.frames);
+
+                // Remove the first part of the samples, this is done to smooth the visualisation by creating overlapping windows
+                for window in self.windows.iter_mut() {
+                    window.drain(0..self.window_len / FFT_OVERLAP as usize);
+                }
+            }
+        }
 
-        // Send data to visualisation renderer, this should always succeed if our program is still running
-        let _ = self.sample_destination.send(transformed_data);
+        count
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use rustfft::FftPlanner;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Returns the linear spectrum of a mono signal, one window long, as it is sent to the renderer.
+    fn analyse(signal: impl Fn(f32) -> f32) -> Vec<(Complex<f32>, f32)> {
+        let len = (SAMPLE_RATE / FFT_FREQUENCY) as usize;
+        let fft = FftPlanner::new().plan_fft_forward(len);
+        let (sender, receiver) = frame_channel();
+        let options = Arc::new(Mutex::new(AnalysisOptions::default()));
+        let mut handler = FftHandler::new(sender, SAMPLE_RATE, 1, fft, PlaybackClock::default(), options);
+
+        let samples = (0..len).map(|index| signal(index as f32 / SAMPLE_RATE as f32)).collect();
+        handler.perform_fft(&[samples], len as u64);
+        receiver.try_iter().next().unwrap().data
+    }
+
+    /// Returns the magnitude of the bin nearest a frequency.
+    fn level_at(spectrum: &[(Complex<f32>, f32)], frequency: f32) -> f32 {
+        spectrum.iter()
+            .min_by(|a, b| (a.1 - frequency).abs().total_cmp(&(b.1 - frequency).abs()))
+            .unwrap().0.norm()
+    }
+
+    #[test]
+    fn finds_peak_of_sine() {
+        // A sine at -6 dBFS has half the magnitude of a full scale one
+        let spectrum = analyse(|time| 0.5 * (TAU * 1000.0 * time).sin());
+        let (peak, frequency) = spectrum.iter().max_by(|a, b| a.0.norm().total_cmp(&b.0.norm())).unwrap();
+
+        assert_eq!(*frequency, 1000.0);
+        assert!((peak.norm() - 0.5).abs() < 1e-3, "{}", peak.norm());
+        assert!(spectrum.iter().filter(|x| x.1 != 1000.0).all(|x| x.0.norm() < 1e-3));
+    }
+
+    #[test]
+    fn finds_peaks_of_multiple_tones() {
+        let tones = [(110.0, 0.25), (440.0, 0.5), (3000.0, 0.125)];
+        let spectrum = analyse(|time| tones.iter().map(|(frequency, level)| level * (TAU * frequency * time).sin()).sum());
+
+        for (frequency, level) in tones {
+            let measured = level_at(&spectrum, frequency);
+            assert!((measured - level).abs() < 1e-3, "{frequency} Hz is {measured}");
+        }
+        assert!(spectrum.iter().filter(|x| !tones.iter().any(|tone| tone.0 == x.1)).all(|x| x.0.norm() < 1e-3));
+    }
+
+    /// Splits interleaved samples into the samples of each channel, as the analysis worker does.
+    fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+        (0..channels).map(|channel| samples.iter().skip(channel).step_by(channels).copied().collect()).collect()
+    }
+
+    #[test]
+    fn mixes_channels() {
+        // Two frames where each sample is the channel number plus ten times the frame number
+        let interleaved = |channels: usize| -> Vec<f32> {
+            (0..2 * channels).map(|index| (index % channels + 1) as f32 + 10.0 * (index / channels) as f32).collect()
+        };
+
+        let cases: [(usize, ChannelMode, [f32; 2]); 12] = [
+            (1, ChannelMode::Left, [1.0, 11.0]),
+            (1, ChannelMode::Right, [1.0, 11.0]),
+            (1, ChannelMode::Side, [0.0, 0.0]),
+            (2, ChannelMode::Left, [1.0, 11.0]),
+            (2, ChannelMode::Right, [2.0, 12.0]),
+            (2, ChannelMode::Mid, [1.5, 11.5]),
+            (2, ChannelMode::Side, [-0.5, -0.5]),
+            (2, ChannelMode::Overlay, [1.5, 11.5]),
+            (6, ChannelMode::Channel(2), [3.0, 13.0]),
+            (6, ChannelMode::Channel(5), [6.0, 16.0]),
+            (8, ChannelMode::Channel(7), [8.0, 18.0]),
+            (8, ChannelMode::Mid, [1.5, 11.5]),
+        ];
+
+        let mut output = Vec::new();
+        for (channels, mode, expected) in cases {
+            mode.mix(&deinterleave(&interleaved(channels), channels), &mut output);
+            assert_eq!(output, expected, "{mode:?} of {channels} channels");
+        }
+
+        // A channel the source doesn't have falls back to the first
+        ChannelMode::Channel(7).mix(&deinterleave(&interleaved(6), 6), &mut output);
+        assert_eq!(output, [1.0, 11.0]);
+    }
+
+    #[test]
+    fn names_surround_channels() {
+        let names: Vec<String> = ChannelMode::available(8).iter().skip(5).map(|mode| mode.name(8)).collect();
+        assert_eq!(names, ["Channel L", "Channel R", "Channel C", "Channel LFE", "Channel Lb", "Channel Rb", "Channel Ls", "Channel Rs"]);
+        assert_eq!(ChannelMode::available(2).len(), 5);
+        assert_eq!(ChannelMode::Channel(3).name(4), "Channel 4");
+    }
+
+    #[test]
+    fn frame_pool_is_fixed() {
+        let (sender, receiver) = frame_channel();
+        let frames: Vec<FftFrame> = std::iter::from_fn(|| sender.frame()).collect();
+        assert_eq!(frames.len(), FRAME_POOL_LENGTH);
+
+        // Frames sent beyond the length of the queue go straight back to the pool
+        for frame in frames {
+            sender.send(frame);
+        }
+        let returned: Vec<FftFrame> = std::iter::from_fn(|| sender.frame()).collect();
+        assert_eq!(returned.len(), FRAME_POOL_LENGTH - FRAME_QUEUE_LENGTH);
+
+        for frame in receiver.try_iter() {
+            receiver.recycle(frame);
+        }
+        assert!(sender.frame().is_some());
+    }
+}
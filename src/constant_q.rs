@@ -0,0 +1,165 @@
+use std::{f32::consts::PI, sync::Arc};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::notes;
+use crate::scales::MAX_FREQUENCY;
+
+/// MIDI note of the lowest bin, C1.
+const LOWEST_NOTE: i32 = 24;
+
+/// Number of bins per octave, one per semitone.
+const BINS_PER_OCTAVE: f32 = 12.0;
+
+/// Fraction of the largest value of a spectral kernel below which values are dropped to keep it sparse.
+const SPARSITY_THRESHOLD: f32 = 0.0054;
+
+/// The sparse spectral kernel of a single bin.
+#[derive(Clone)]
+struct Kernel {
+    frequency: f32,
+    /// Index of each FFT bin used and the conjugated weight it is multiplied by.
+    weights: Vec<(usize, Complex<f32>)>,
+}
+
+/// Holds all information needed to calculate a constant-Q transform.
+/// 
+/// Each bin is centred on a semitone and has a bandwidth proportional to its frequency, so low notes are resolved with
+/// longer windows than high notes. The transform is calculated efficiently by multiplying a single FFT of the sample
+/// history with precomputed sparse spectral kernels.
+#[derive(Clone)]
+pub struct ConstantQ {
+    history: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    fft: Arc<dyn Fft<f32>>,
+    kernels: Vec<Kernel>,
+    primed: bool,
+}
+
+impl ConstantQ {
+    /// Create a new constant-Q transform, this precomputes the kernels so should not be done on the audio thread.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let q = 1.0 / (2_f32.powf(1.0 / BINS_PER_OCTAVE) - 1.0);
+
+        // The history must be long enough for the window of the lowest bin
+        let lowest = notes::note_frequency(LOWEST_NOTE as f32);
+        let length = ((q * sample_rate / lowest).ceil() as usize).next_power_of_two();
+        let fft = FftPlanner::new().plan_fft_forward(length);
+
+        let highest = MAX_FREQUENCY.min(0.45 * sample_rate);
+        let mut kernels = Vec::new();
+        let mut note = LOWEST_NOTE;
+        loop {
+            let frequency = notes::note_frequency(note as f32);
+            if frequency > highest {
+                break;
+            }
+
+            let weights = Self::spectral_kernel(fft.as_ref(), length, frequency, q, sample_rate);
+            kernels.push(Kernel { frequency, weights });
+            note += 1;
+        }
+
+        let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+
+        ConstantQ { history: vec![0.0; length], buffer: Vec::with_capacity(length), scratch, fft, kernels, primed: false }
+    }
+
+    /// Adds the newest samples of an analysis window to the history.
+    /// 
+    /// Windows overlap, so after the first only the part that was not in the previous window is new.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - Is the analysis window.
+    /// 
+    /// * `overlap` - Is the number of windows each sample appears in.
+    pub fn push_window(&mut self, data: &[f32], overlap: usize) {
+        let new = if self.primed { data.len() / overlap } else { data.len() };
+        let new = new.min(self.history.len());
+        self.primed = true;
+
+        let length = self.history.len();
+        self.history.copy_within(new.., 0);
+        self.history[length - new..].copy_from_slice(&data[data.len() - new..]);
+    }
+
+    /// Calculates the transform of the history, adding the value and frequency of each bin to the output.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `output` - Is the vector the bins are added to.
+    pub fn transform(&mut self, output: &mut Vec<(Complex<f32>, f32)>) {
+        self.buffer.clear();
+        self.buffer.extend(self.history.iter().map(|&x| Complex::new(x, 0.0)));
+        self.fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        // Correlate with each kernel in the frequency domain
+        let scale = 1.0 / self.history.len() as f32;
+        output.extend(self.kernels.iter().map(|kernel| {
+            let value: Complex<f32> = kernel.weights.iter().map(|(index, weight)| self.buffer[*index] * weight).sum();
+            (value * scale, kernel.frequency)
+        }));
+    }
+
+    /// Returns the sparse, conjugated spectral kernel of a bin.
+    /// 
+    /// The temporal kernel is a Hann windowed complex sinusoid aligned with the end of the history, so every bin
+    /// includes the most recent samples. It is scaled so a full scale sine has a magnitude of 1.
+    fn spectral_kernel(fft: &dyn Fft<f32>, length: usize, frequency: f32, q: f32, sample_rate: f32) -> Vec<(usize, Complex<f32>)> {
+        let window_length = ((q * sample_rate / frequency).ceil() as usize).min(length);
+        let start = length - window_length;
+
+        let window: Vec<f32> = (0..window_length)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / window_length as f32).cos())
+            .collect();
+        let window_sum: f32 = window.iter().sum();
+
+        let mut kernel = vec![Complex::new(0.0, 0.0); length];
+        for (n, weight) in window.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * n as f32 / sample_rate;
+            kernel[start + n] = Complex::from_polar(2.0 * weight / window_sum, phase);
+        }
+        fft.process(&mut kernel);
+
+        let largest = kernel.iter().map(|x| x.norm()).fold(0.0, f32::max);
+        kernel.into_iter()
+            .enumerate()
+            .filter(|(_, x)| x.norm() > largest * SPARSITY_THRESHOLD)
+            .map(|(index, x)| (index, x.conj()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_peaks_in_its_semitone() {
+        let sample_rate = 48000;
+        let mut constant_q = ConstantQ::new(sample_rate);
+        let length = constant_q.history.len();
+
+        // A full scale A4 filling the history
+        let data: Vec<f32> = (0..length).map(|n| (2.0 * PI * 440.0 * n as f32 / sample_rate as f32).sin()).collect();
+        constant_q.push_window(&data, 1);
+        let mut output = Vec::new();
+        constant_q.transform(&mut output);
+
+        let peak = output.iter().enumerate().max_by(|a, b| a.1.0.norm().total_cmp(&b.1.0.norm())).unwrap().0;
+        assert!((output[peak].1 - 440.0).abs() < 0.01, "{}", output[peak].1);
+        assert!((output[peak].0.norm() - 1.0).abs() < 0.05, "{}", output[peak].0.norm());
+
+        // Each window is a semitone wide, so the Hann window lets half through to the neighbouring semitones
+        for (offset, limit) in [(1, 0.6), (2, 0.05)] {
+            assert!(output[peak - offset].0.norm() < limit, "{}", output[peak - offset].0.norm());
+            assert!(output[peak + offset].0.norm() < limit, "{}", output[peak + offset].0.norm());
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use std::{f32::consts::TAU, sync::{Arc, Mutex}, time::Duration};
+use rodio::Source;
+use rustfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+/// Largest boost or cut a band can apply, in dB.
+pub const MAX_GAIN: f32 = 18.0;
+
+/// Narrowest and widest bandwidth a band can have, as its Q.
+pub const Q_RANGE: (f32, f32) = (0.1, 18.0);
+
+/// Number of frames filtered between checks for changed bands.
+const UPDATE_FRAMES: usize = 512;
+
+/// The shapes of filter a band can use.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FilterType {
+    /// Boosts or cuts around the frequency.
+    Peaking,
+    /// Boosts or cuts everything below the frequency.
+    LowShelf,
+    /// Boosts or cuts everything above the frequency.
+    HighShelf,
+    /// Removes everything below the frequency.
+    HighPass,
+    /// Removes everything above the frequency.
+    LowPass,
+}
+
+impl FilterType {
+    /// All filter types in the order they are shown to the user.
+    pub const ALL: [FilterType; 5] = [FilterType::Peaking, FilterType::LowShelf, FilterType::HighShelf, FilterType::HighPass, FilterType::LowPass];
+
+    /// Returns the display name of the filter type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterType::Peaking => "Peaking",
+            FilterType::LowShelf => "Low Shelf",
+            FilterType::HighShelf => "High Shelf",
+            FilterType::HighPass => "High-pass",
+            FilterType::LowPass => "Low-pass",
+        }
+    }
+
+    /// Returns whether the gain of the band changes its response, pass filters only have a frequency and Q.
+    pub fn has_gain(&self) -> bool {
+        !matches!(self, FilterType::HighPass | FilterType::LowPass)
+    }
+}
+
+/// A single band of the equaliser.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EqBand {
+    pub filter_type: FilterType,
+    /// Centre or corner frequency, in Hz.
+    pub frequency: f32,
+    /// Boost or cut, in dB.
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl EqBand {
+    /// Creates a flat band.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `filter_type` - Is the shape of the filter.
+    /// 
+    /// * `frequency` - Is the centre or corner frequency in Hz.
+    pub fn new(filter_type: FilterType, frequency: f32) -> Self {
+        // Shelves and pass filters are maximally flat at this Q, one octave wide peaks are a common starting point
+        let q = if filter_type == FilterType::Peaking { 1.41 } else { 0.707 };
+        EqBand { filter_type, frequency, gain: 0.0, q }
+    }
+
+    /// Returns the level the band changes a frequency by, in dB.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frequency` - Is the frequency in Hz.
+    /// 
+    /// * `sample_rate` - Is the sample rate the band filters at.
+    pub fn response(&self, frequency: f32, sample_rate: u32) -> f32 {
+        Biquad::new(self, sample_rate).response(frequency, sample_rate)
+    }
+}
+
+/// The bands of the equaliser and whether it is applied.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Equaliser {
+    pub enabled: bool,
+    pub bands: Vec<EqBand>,
+}
+
+impl Equaliser {
+    /// Returns the level the equaliser changes a frequency by, in dB.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frequency` - Is the frequency in Hz.
+    /// 
+    /// * `sample_rate` - Is the sample rate the equaliser filters at.
+    pub fn response(&self, frequency: f32, sample_rate: u32) -> f32 {
+        self.bands.iter().map(|band| band.response(frequency, sample_rate)).sum()
+    }
+}
+
+impl Default for Equaliser {
+    fn default() -> Self {
+        Equaliser {
+            enabled: false,
+            bands: vec![
+                EqBand::new(FilterType::LowShelf, 100.0),
+                EqBand::new(FilterType::Peaking, 1000.0),
+                EqBand::new(FilterType::HighShelf, 8000.0),
+            ],
+        }
+    }
+}
+
+/// A named set of bands saved by the user.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub name: String,
+    pub bands: Vec<EqBand>,
+}
+
+impl EqPreset {
+    /// Saves bands as a preset, replacing any preset with the same name. Returns whether it was saved, which it isn't
+    /// if the name is blank.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `presets` - Is the presets the new one is added to.
+    /// 
+    /// * `name` - Is the name of the preset, surrounding whitespace is removed.
+    /// 
+    /// * `bands` - Is the bands of the preset.
+    pub fn save(presets: &mut Vec<EqPreset>, name: &str, bands: &[EqBand]) -> bool {
+        let name = name.trim();
+        if name.is_empty() {
+            return false;
+        }
+
+        presets.retain(|preset| preset.name != name);
+        presets.push(EqPreset { name: name.to_string(), bands: bands.to_vec() });
+        true
+    }
+}
+
+/// Normalised coefficients of a biquad filter, from the Audio EQ Cookbook.
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    /// Calculates the coefficients of a band.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `band` - Is the band.
+    /// 
+    /// * `sample_rate` - Is the sample rate the band filters at.
+    fn new(band: &EqBand, sample_rate: u32) -> Self {
+        // Frequencies at or above Nyquist make the filter unstable
+        let frequency = band.frequency.clamp(1.0, sample_rate as f32 * 0.49);
+        let a = 10f32.powf(band.gain / 40.0);
+        let w0 = TAU * frequency / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q.clamp(Q_RANGE.0, Q_RANGE.1));
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
+            FilterType::Peaking => (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+        };
+
+        Biquad { b0: b0 / a0, b1: b1 / a0, b2: b2 / a0, a1: a1 / a0, a2: a2 / a0 }
+    }
+
+    /// Returns the level the filter changes a frequency by, in dB.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frequency` - Is the frequency in Hz.
+    /// 
+    /// * `sample_rate` - Is the sample rate the filter runs at.
+    fn response(&self, frequency: f32, sample_rate: u32) -> f32 {
+        let z1 = Complex::from_polar(1.0, -TAU * frequency / sample_rate as f32);
+        let z2 = z1 * z1;
+        let numerator = self.b0 + z1 * self.b1 + z2 * self.b2;
+        let denominator = 1_f32 + z1 * self.a1 + z2 * self.a2;
+        20.0 * (numerator.norm() / denominator.norm()).max(1e-10).log10()
+    }
+
+    /// Filters a sample using the transposed direct form II.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample` - Is the sample to filter.
+    /// 
+    /// * `state` - Is the state of the filter for the channel the sample belongs to.
+    fn process(&self, sample: f32, state: &mut [f32; 2]) -> f32 {
+        let output = self.b0 * sample + state[0];
+        state[0] = self.b1 * sample - self.a1 * output + state[1];
+        state[1] = self.b2 * sample - self.a2 * output;
+        output
+    }
+}
+
+/// A rodio source adapter applying the equaliser to its input.
+/// 
+/// The equaliser is shared so it can be changed while playing, each change is picked up within a few milliseconds.
+pub struct EqFilter<I> {
+    input: I,
+    equaliser: Arc<Mutex<Equaliser>>,
+    current: Equaliser,
+    filters: Vec<Biquad>,
+    /// State of each filter for each channel, the channels of a filter are next to each other.
+    states: Vec<[f32; 2]>,
+    channel: usize,
+    frames_until_update: usize,
+}
+
+impl<I> EqFilter<I>
+where I: Source<Item = f32>, {
+    /// Returns a new equaliser filter.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `input` - Is the audio source to equalise.
+    /// 
+    /// * `equaliser` - Is the equaliser to apply, changes are picked up while playing.
+    pub fn new(input: I, equaliser: Arc<Mutex<Equaliser>>) -> Self {
+        let current = equaliser.lock().unwrap().clone();
+        let mut filter = EqFilter { input, equaliser, current, filters: Vec::new(), states: Vec::new(), channel: 0, frames_until_update: UPDATE_FRAMES };
+        filter.update_filters();
+        filter
+    }
+
+    /// Recalculates the filters from the current bands, keeping the state of bands that still exist.
+    fn update_filters(&mut self) {
+        let sample_rate = self.input.sample_rate();
+        self.filters = self.current.bands.iter().map(|band| Biquad::new(band, sample_rate)).collect();
+        self.states.resize(self.filters.len() * self.input.channels() as usize, [0.0; 2]);
+    }
+
+    /// Picks up a changed equaliser, without waiting on the UI if it is changing it.
+    fn update_equaliser(&mut self) {
+        let changed = match self.equaliser.try_lock() {
+            Ok(equaliser) if *equaliser != self.current => Some(equaliser.clone()),
+            _ => None,
+        };
+
+        if let Some(equaliser) = changed {
+            self.current = equaliser;
+            self.update_filters();
+        }
+    }
+}
+
+impl<I> Iterator for EqFilter<I>
+where I: Source<Item = f32>, {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.input.next()?;
+        let channels = self.input.channels().max(1) as usize;
+
+        // Only change the filters between frames so every channel of a frame is filtered the same
+        if self.channel == 0 {
+            if self.frames_until_update == 0 {
+                self.update_equaliser();
+                self.frames_until_update = UPDATE_FRAMES;
+            }
+            self.frames_until_update -= 1;
+
+            // Decoders can change the channel count between frames
+            if self.states.len() != self.filters.len() * channels {
+                self.update_filters();
+            }
+        }
+
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % channels;
+        if !self.current.enabled {
+            return Some(sample);
+        }
+
+        let mut output = sample;
+        for (index, filter) in self.filters.iter().enumerate() {
+            output = filter.process(output, &mut self.states[index * channels + channel]);
+        }
+
+        Some(output)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<I> ExactSizeIterator for EqFilter<I> where I: Source<Item = f32> + ExactSizeIterator {}
+
+impl<I> Source for EqFilter<I>
+where I: Source<Item = f32>, {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn band(filter_type: FilterType, frequency: f32, gain: f32) -> EqBand {
+        EqBand { gain, ..EqBand::new(filter_type, frequency) }
+    }
+
+    fn assert_response(band: &EqBand, frequency: f32, expected: f32) {
+        let response = band.response(frequency, SAMPLE_RATE);
+        assert!((response - expected).abs() < 0.1, "{:?} at {frequency} Hz is {response} dB", band.filter_type);
+    }
+
+    #[test]
+    fn peaking_boosts_around_its_frequency() {
+        let peak = band(FilterType::Peaking, 1000.0, 6.0);
+        assert_response(&peak, 1000.0, 6.0);
+        assert_response(&peak, 20.0, 0.0);
+        assert_response(&peak, 20000.0, 0.0);
+        assert_response(&band(FilterType::Peaking, 1000.0, -12.0), 1000.0, -12.0);
+    }
+
+    #[test]
+    fn shelves_have_half_their_gain_at_their_frequency() {
+        let low = band(FilterType::LowShelf, 100.0, 6.0);
+        assert_response(&low, 10.0, 6.0);
+        assert_response(&low, 100.0, 3.0);
+        assert_response(&low, 10000.0, 0.0);
+
+        let high = band(FilterType::HighShelf, 8000.0, -6.0);
+        assert_response(&high, 100.0, 0.0);
+        assert_response(&high, 8000.0, -3.0);
+        assert_response(&high, 23000.0, -6.0);
+    }
+
+    #[test]
+    fn pass_filters_cut_12_db_per_octave() {
+        // Butterworth filters are 3 dB down at their corner, and their gain is ignored
+        let high_pass = band(FilterType::HighPass, 1000.0, 6.0);
+        assert_response(&high_pass, 1000.0, -3.0);
+        assert_response(&high_pass, 20000.0, 0.0);
+        assert!((high_pass.response(125.0, SAMPLE_RATE) + 36.0).abs() < 0.5);
+
+
+        // Far from Nyquist, where the bilinear transform makes low-pass filters steeper
+        let low_pass = band(FilterType::LowPass, 200.0, 0.0);
+        assert_response(&low_pass, 200.0, -3.0);
+        assert_response(&low_pass, 10.0, 0.0);
+        assert!((low_pass.response(1600.0, SAMPLE_RATE) + 36.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn filtering_matches_response() {
+        let peak = band(FilterType::Peaking, 1000.0, 6.0);
+        let biquad = Biquad::new(&peak, SAMPLE_RATE);
+        let mut state = [0.0; 2];
+
+        // Measure the peak once the filter has settled
+        let output: Vec<f32> = (0..SAMPLE_RATE)
+            .map(|index| biquad.process((TAU * 1000.0 * index as f32 / SAMPLE_RATE as f32).sin() * 0.25, &mut state))
+            .collect();
+        let peak_level = output[SAMPLE_RATE as usize / 2..].iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!((20.0 * (peak_level / 0.25).log10() - 6.0).abs() < 0.1, "{peak_level}");
+    }
+
+    #[test]
+    fn bands_are_combined() {
+        let equaliser = Equaliser {
+            enabled: true,
+            bands: vec![band(FilterType::Peaking, 1000.0, 6.0), band(FilterType::Peaking, 1000.0, -2.0)],
+        };
+        assert!((equaliser.response(1000.0, SAMPLE_RATE) - 4.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn filter_picks_up_changes() {
+        let equaliser = Arc::new(Mutex::new(Equaliser::default()));
+        let input = SamplesBuffer::new(1, SAMPLE_RATE, vec![1.0; UPDATE_FRAMES * 4]);
+        let mut filter = EqFilter::new(input, equaliser.clone());
+
+        // The default equaliser is disabled so passes the input straight through
+        assert!(filter.by_ref().take(UPDATE_FRAMES).all(|sample| sample == 1.0));
+
+        equaliser.lock().unwrap().enabled = true;
+        equaliser.lock().unwrap().bands = vec![band(FilterType::HighPass, 1000.0, 0.0)];
+        let output: Vec<f32> = filter.by_ref().take(UPDATE_FRAMES).collect();
+        assert!(output.last().unwrap().abs() < 0.01, "{output:?}");
+    }
+
+    #[test]
+    fn saving_preset_replaces_same_name() {
+        let mut presets = Vec::new();
+        let flat = Equaliser::default().bands;
+        let boosted = vec![band(FilterType::Peaking, 1000.0, 6.0)];
+
+        assert!(EqPreset::save(&mut presets, "Flat", &flat));
+        assert!(EqPreset::save(&mut presets, "Boost", &flat));
+        assert!(EqPreset::save(&mut presets, " Boost ", &boosted));
+        assert!(!EqPreset::save(&mut presets, "  ", &boosted));
+
+        let names: Vec<&str> = presets.iter().map(|preset| preset.name.as_str()).collect();
+        assert_eq!(names, ["Flat", "Boost"]);
+        assert_eq!(presets[1].bands, boosted);
+    }
+
+    #[test]
+    fn presets_survive_saving() {
+        let mut presets = Vec::new();
+        EqPreset::save(&mut presets, "Bass", &[band(FilterType::LowShelf, 80.0, 4.5), band(FilterType::HighPass, 30.0, 0.0)]);
+
+        let json = serde_json::to_string(&presets).unwrap();
+        let loaded: Vec<EqPreset> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, presets);
+    }
+}
@@ -1,13 +1,128 @@
-use std::sync::mpsc::Receiver;
+use std::{collections::VecDeque, path::Path, sync::{Arc, Mutex}, time::{Duration, Instant}};
 use rustfft::num_complex::Complex;
-use imgui::{DrawListMut, ImColor32};
+use imgui::{DrawListMut, ImColor32, MouseButton, Ui};
 use splines::{Key, Spline};
 
+use crate::common_audio_manager::{self, AnalysisOptions, FftFrame, FrameReceiver};
+use crate::equaliser::{EqBand, Equaliser, MAX_GAIN, Q_RANGE};
+use crate::level_meter::{self, Ballistics, ChannelLevel, ChannelMeter};
+use crate::loudness_meter::LoudnessReading;
+use crate::notes;
+use crate::pitch_analyser::PitchInfo;
+use crate::scales::{self, AmplitudeScale, FrequencyScale};
+use crate::spectral_features::{FeatureLog, SpectralFeatures};
+use crate::theme::{self, GradientMode, Theme};
+
+/// Default time in seconds for the visualisation to rise towards a louder frame.
+const DEFAULT_ATTACK: f32 = 0.04;
+
+/// Default time in seconds for the visualisation to fall towards a quieter frame.
+const DEFAULT_RELEASE: f32 = 0.25;
+
+/// Number of frequency bands the spectrum is divided into.
+const BANDS: usize = 150;
+
+/// Default lowest level shown when using the decibel amplitude scale.
+const DEFAULT_DB_FLOOR: f32 = -90.0;
+
+/// Frequencies in Hz that are labelled on the x axis.
+const FREQUENCY_LABELS: [(f32, &str); 10] = [
+    (20.0, "20"), (50.0, "50"), (100.0, "100"), (200.0, "200"), (500.0, "500"),
+    (1000.0, "1k"), (2000.0, "2k"), (5000.0, "5k"), (10000.0, "10k"), (20000.0, "20k")
+];
+
+/// Levels in decibels relative to the peak that have gridlines when using the normalised amplitude scale.
+const NORMALISED_GRIDLINES: [f32; 5] = [0.0, -3.0, -6.0, -12.0, -20.0];
+
+/// Minimum spacing in pixels between gridlines, closer gridlines are skipped to keep labels legible.
+const MIN_GRID_SPACING: f32 = 30.0;
+
+/// Colour of the gridlines.
+const GRID_COLOUR: ImColor32 = ImColor32::from_rgba(255, 255, 255, 40);
+
+/// Colour of the axis labels.
+const LABEL_COLOUR: ImColor32 = ImColor32::from_rgba(255, 255, 255, 140);
+
+/// Colour of the crosshair shown when hovering over the visualisation.
+const CROSSHAIR_COLOUR: ImColor32 = ImColor32::from_rgba(255, 255, 255, 120);
+
+/// Number of translucent curves drawn beneath the line to create a glow.
+const GLOW_PASSES: u32 = 3;
+
+/// Time in seconds for the beat pulse to fade after a beat.
+const BEAT_PULSE_DECAY: f32 = 0.15;
+
+/// Number of frames of chroma kept for the scrolling chroma view.
+const CHROMA_HISTORY: usize = 200;
+
+/// Maximum number of frames waiting to become audible, older frames are dropped beyond this.
+const MAX_PENDING_FRAMES: usize = 64;
+
+/// Number of points the equaliser response is drawn through.
+const EQ_CURVE_POINTS: usize = 200;
+
+/// Radius of the handle drawn for each equaliser band.
+const EQ_HANDLE_RADIUS: f32 = 6.0;
+
+/// Colour of the equaliser response while it is applied.
+const EQ_CURVE_COLOUR: ImColor32 = ImColor32::from_rgba(255, 200, 60, 220);
+
+/// Colour of the equaliser response while it is bypassed.
+const EQ_BYPASSED_COLOUR: ImColor32 = ImColor32::from_rgba(255, 200, 60, 80);
+
+/// Colours of the equaliser handles, normally and when hovered or dragged.
+const EQ_HANDLE_COLOURS: [ImColor32; 2] = [ImColor32::from_rgba(255, 200, 60, 160), ImColor32::from_rgba(255, 255, 255, 255)];
+
+/// Width of a character of the default font, used to right align the legend of overlaid channels.
+const LEGEND_CHAR_WIDTH: f32 = 7.0;
+
+/// Colours of the curve of each channel when channels are overlaid.
+const CHANNEL_COLOURS: [[f32; 4]; 8] = [
+    [0.30, 0.65, 1.00, 1.0], [1.00, 0.40, 0.35, 1.0], [0.40, 0.90, 0.40, 1.0], [0.95, 0.85, 0.30, 1.0],
+    [0.80, 0.45, 1.00, 1.0], [0.30, 0.90, 0.90, 1.0], [1.00, 0.60, 0.20, 1.0], [0.90, 0.90, 0.90, 1.0]
+];
+
 /// Holds all necessary information for the visualisation renderer.
 pub struct FftRenderer {
-    samples: Receiver<Vec<(Complex<f32>, f32)>>,
-    current_render_data: Vec<[f32; 2]>,
-    current_size: [f32; 2]
+    samples: FrameReceiver,
+    analysis_options: Arc<Mutex<AnalysisOptions>>,
+    pending_frames: VecDeque<FftFrame>,
+    current_frame: Vec<(Complex<f32>, f32)>,
+    target_data: Vec<f32>,
+    smoothed_data: Vec<f32>,
+    overlay_targets: Vec<Vec<f32>>,
+    overlay_smoothed: Vec<Vec<f32>>,
+    /// Name of each overlaid curve in the legend, the standard channel names are used if it has none.
+    overlay_names: Vec<String>,
+    channels: u16,
+    attack: f32,
+    release: f32,
+    av_offset: f32,
+    frequency_scale: FrequencyScale,
+    amplitude_scale: AmplitudeScale,
+    db_floor: f32,
+    show_grid: bool,
+    show_axis_labels: bool,
+    theme: Theme,
+    bpm: Option<f32>,
+    beat_pulse: f32,
+    onset_pulse: f32,
+    pitch: PitchInfo,
+    chroma_history: VecDeque<[f32; 12]>,
+    loudness: LoudnessReading,
+    /// Why the loudness couldn't be exported, shown until the next export.
+    loudness_export_error: Option<String>,
+    pending_levels: Option<Vec<ChannelLevel>>,
+    channel_meters: Vec<ChannelMeter>,
+    ballistics: Ballistics,
+    features: Option<SpectralFeatures>,
+    feature_log: Option<FeatureLog>,
+    /// Why the features couldn't be exported, shown until the next export.
+    feature_log_error: Option<String>,
+    show_equaliser: bool,
+    /// Equaliser band under the mouse or being dragged.
+    active_band: Option<usize>,
+    dragged_band: Option<usize>,
 }
 
 impl FftRenderer {
@@ -16,14 +131,288 @@ impl FftRenderer {
     /// # Arguments
     ///
     /// * `samples` - Is the receiver that samples are sent to for the renderer.
-    pub fn new(samples: Receiver<Vec<(Complex<f32>, f32)>>) -> Self {
-        let current_size = [0.0, 0.0];
-        let current_render_data = Vec::new();
+    /// 
+    /// * `analysis_options` - Is the options used by the audio managers to analyse the audio.
+    pub fn new(samples: FrameReceiver, analysis_options: Arc<Mutex<AnalysisOptions>>) -> Self {
+        let pending_frames = VecDeque::new();
+        let current_frame = Vec::new();
+        let target_data = Vec::new();
+        let smoothed_data = Vec::new();
+
+        FftRenderer {
+            samples,
+            analysis_options,
+            pending_frames,
+            current_frame,
+            target_data,
+            smoothed_data,
+            overlay_targets: Vec::new(),
+            overlay_smoothed: Vec::new(),
+            overlay_names: Vec::new(),
+            channels: 0,
+            attack: DEFAULT_ATTACK,
+            release: DEFAULT_RELEASE,
+            av_offset: 0.0,
+            frequency_scale: FrequencyScale::Logarithmic,
+            amplitude_scale: AmplitudeScale::Normalised,
+            db_floor: DEFAULT_DB_FLOOR,
+            show_grid: true,
+            show_axis_labels: true,
+            theme: Theme::default(),
+            bpm: None,
+            beat_pulse: 0.0,
+            onset_pulse: 0.0,
+            pitch: PitchInfo::default(),
+            chroma_history: VecDeque::with_capacity(CHROMA_HISTORY),
+            loudness: LoudnessReading::default(),
+            loudness_export_error: None,
+            pending_levels: None,
+            channel_meters: Vec::new(),
+            ballistics: Ballistics::Digital,
+            features: None,
+            feature_log: None,
+            feature_log_error: None,
+            show_equaliser: false,
+            active_band: None,
+            dragged_band: None,
+        }
+    }
+
+    /// Returns the attack time in seconds.
+    pub fn attack(&self) -> f32 {
+        self.attack
+    }
+
+    /// Sets the time in seconds taken to rise towards a louder frame.
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack = attack.max(0.0);
+    }
+
+    /// Returns the release time in seconds.
+    pub fn release(&self) -> f32 {
+        self.release
+    }
+
+    /// Sets the time in seconds taken to fall towards a quieter frame.
+    pub fn set_release(&mut self, release: f32) {
+        self.release = release.max(0.0);
+    }
+
+    /// Returns the A/V offset in seconds.
+    pub fn av_offset(&self) -> f32 {
+        self.av_offset
+    }
+
+    /// Sets the A/V offset in seconds, positive values delay the visualisation to match slower outputs such as Bluetooth headsets.
+    pub fn set_av_offset(&mut self, av_offset: f32) {
+        self.av_offset = av_offset;
+    }
+
+    /// Returns the options used to analyse the audio.
+    pub fn analysis_options(&self) -> AnalysisOptions {
+        *self.analysis_options.lock().unwrap()
+    }
+
+    /// Sets the options used to analyse the audio, spectral features stay enabled while the theme or export needs them.
+    pub fn set_analysis_options(&mut self, mut analysis_options: AnalysisOptions) {
+        analysis_options.spectral_features |= self.needs_features();
+        *self.analysis_options.lock().unwrap() = analysis_options;
+    }
+
+    /// Returns whether the spectral features must be extracted, for the brightness gradient or to be exported.
+    fn needs_features(&self) -> bool {
+        self.theme.gradient == GradientMode::Brightness || self.feature_log.is_some()
+    }
+
+    /// Returns the scale used for the frequency axis.
+    pub fn frequency_scale(&self) -> FrequencyScale {
+        self.frequency_scale
+    }
+
+    /// Sets the scale used for the frequency axis.
+    pub fn set_frequency_scale(&mut self, frequency_scale: FrequencyScale) {
+        self.frequency_scale = frequency_scale;
+    }
+
+    /// Returns the scale used for the amplitude axis.
+    pub fn amplitude_scale(&self) -> AmplitudeScale {
+        self.amplitude_scale
+    }
+
+    /// Sets the scale used for the amplitude axis.
+    pub fn set_amplitude_scale(&mut self, amplitude_scale: AmplitudeScale) {
+        self.amplitude_scale = amplitude_scale;
+    }
+
+    /// Returns the lowest level in decibels shown by the decibel amplitude scale.
+    pub fn db_floor(&self) -> f32 {
+        self.db_floor
+    }
+
+    /// Sets the lowest level in decibels shown by the decibel amplitude scale.
+    pub fn set_db_floor(&mut self, db_floor: f32) {
+        self.db_floor = db_floor.min(-1.0);
+    }
+
+    /// Returns whether gridlines are drawn.
+    pub fn show_grid(&self) -> bool {
+        self.show_grid
+    }
+
+    /// Sets whether gridlines are drawn.
+    pub fn set_show_grid(&mut self, show_grid: bool) {
+        self.show_grid = show_grid;
+    }
+
+    /// Returns whether axis labels are drawn.
+    pub fn show_axis_labels(&self) -> bool {
+        self.show_axis_labels
+    }
+
+    /// Sets whether axis labels are drawn.
+    pub fn set_show_axis_labels(&mut self, show_axis_labels: bool) {
+        self.show_axis_labels = show_axis_labels;
+    }
+
+    /// Returns the theme used to colour the visualisation.
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Sets the theme used to colour the visualisation, enabling the spectral features if its gradient needs them.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        if self.needs_features() {
+            self.analysis_options.lock().unwrap().spectral_features = true;
+        }
+    }
+
+    /// Returns the tempo of the audible audio if one has been detected.
+    pub fn bpm(&self) -> Option<f32> {
+        self.bpm
+    }
+
+    /// Returns a value that jumps to 1 on each audible beat and fades to 0, for pulsing effects.
+    pub fn beat_pulse(&self) -> f32 {
+        self.beat_pulse
+    }
+
+    /// Returns a value that jumps to 1 on each audible onset and fades to 0.
+    pub fn onset_pulse(&self) -> f32 {
+        self.onset_pulse
+    }
+
+    /// Returns the pitch of the audible audio.
+    pub fn pitch(&self) -> &PitchInfo {
+        &self.pitch
+    }
+
+    /// Returns the loudness of the audible audio.
+    pub fn loudness(&self) -> &LoudnessReading {
+        &self.loudness
+    }
+
+    /// Returns why the loudness couldn't be exported, if the last export failed.
+    pub fn loudness_export_error(&self) -> Option<&str> {
+        self.loudness_export_error.as_deref()
+    }
+
+    /// Sets why the loudness couldn't be exported.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `loudness_export_error` - Is the error of the last export, or nothing if it succeeded.
+    pub fn set_loudness_export_error(&mut self, loudness_export_error: Option<String>) {
+        self.loudness_export_error = loudness_export_error;
+    }
+
+    /// Returns the number of channels in the audible audio.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Sets the names the overlaid curves are shown with in the legend, such as the sources mixed to each channel.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `overlay_names` - Is the name of each curve, or nothing to use the standard channel names.
+    pub fn set_overlay_names(&mut self, overlay_names: Vec<String>) {
+        self.overlay_names = overlay_names;
+    }
+
+    /// Returns the spectral features of the audible audio if they are being extracted.
+    pub fn features(&self) -> Option<&SpectralFeatures> {
+        self.features.as_ref()
+    }
+
+    /// Returns whether the spectral features are being written to a file.
+    pub fn is_logging_features(&self) -> bool {
+        self.feature_log.is_some()
+    }
+
+    /// Starts writing the spectral features of each audible frame to a CSV file, enabling their extraction.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Is the path of the CSV file.
+    pub fn start_feature_log(&mut self, path: &Path) {
+        match FeatureLog::create(path, Instant::now()) {
+            Ok(feature_log) => {
+                self.feature_log = Some(feature_log);
+                self.feature_log_error = None;
+                self.analysis_options.lock().unwrap().spectral_features = true;
+            }
+            Err(error) => self.feature_log_error = Some(format!("Failed to export spectral features: {error}")),
+        }
+    }
+
+    /// Returns why the features couldn't be exported, if the last export failed.
+    pub fn feature_log_error(&self) -> Option<&str> {
+        self.feature_log_error.as_deref()
+    }
+
+    /// Stops writing the spectral features, flushing the file.
+    pub fn stop_feature_log(&mut self) {
+        self.feature_log = None;
+    }
+
+    /// Returns the ballistics of the level meters.
+    pub fn ballistics(&self) -> Ballistics {
+        self.ballistics
+    }
+
+    /// Sets the ballistics of the level meters.
+    pub fn set_ballistics(&mut self, ballistics: Ballistics) {
+        self.ballistics = ballistics;
+    }
 
-        FftRenderer { samples, current_render_data, current_size }
+    /// Clears the clip indicators of every level meter.
+    pub fn reset_clips(&mut self) {
+        self.channel_meters.iter_mut().for_each(|meter| meter.clipped = false);
     }
 
-    /// Handle rendering, if the size of the window hasn't changed or the data is the same this is unecessary and skipped.
+    /// Returns whether the equaliser is drawn over the spectrum to be edited.
+    pub fn show_equaliser(&self) -> bool {
+        self.show_equaliser
+    }
+
+    /// Sets whether the equaliser is drawn over the spectrum to be edited.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `show_equaliser` - Is whether the equaliser is drawn.
+    pub fn set_show_equaliser(&mut self, show_equaliser: bool) {
+        self.show_equaliser = show_equaliser;
+        self.active_band = None;
+        self.dragged_band = None;
+    }
+
+    /// Returns whether an equaliser band is under the mouse or being dragged.
+    pub fn is_editing_equaliser(&self) -> bool {
+        self.active_band.is_some()
+    }
+
+    /// Handle rendering, the latest audible analysis frame is used as a target which the visualisation animates towards.
     /// 
     /// # Arguments
     /// 
@@ -32,116 +421,799 @@ impl FftRenderer {
     /// * `size` - Is the size of the render window.
     /// 
     /// * `origin` - Is the origin of the render window.
-    pub fn render(&mut self, draw_list: DrawListMut<'_>, size: [f32; 2], origin: [f32; 2]) {
-        // If the size of the window has changed, the data needs to be recalculated
-        if self.current_size != size {
-            self.resize(size);
+    /// 
+    /// * `delta_time` - Is the time in seconds since the last rendered frame.
+    pub fn render(&mut self, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2], delta_time: f32) {
+        // Present the most recent frame that has become audible
+        // The previously presented data is handed back in the frame so its memory can be reused
+        if let Some(mut frame) = self.next_audible_frame() {
+            self.target_data = self.preprocess_data(&frame.data);
+            self.overlay_targets = self.preprocess_overlays(&frame.overlays);
+            std::mem::swap(&mut self.current_frame, &mut frame.data);
+            self.samples.recycle(frame);
+        }
+
+        // Animate the displayed data towards the latest frame
+        self.smooth(delta_time);
+        self.beat_pulse = (self.beat_pulse - delta_time / BEAT_PULSE_DECAY).max(0.0);
+        self.onset_pulse = (self.onset_pulse - delta_time / BEAT_PULSE_DECAY).max(0.0);
+
+        let background = self.theme.background_colour;
+        if background[3] > 0.0 {
+            draw_list.add_rect(origin, [origin[0] + size[0], origin[1] + size[1]], theme::with_alpha(background, 1.0)).filled(true).build();
+        }
+
+        // Draw the axes underneath the visualisation
+        self.draw_axes(draw_list, size, origin);
+
+        // Overlaid channels replace the themed curve with a curve in a different colour for each channel
+        if !self.overlay_smoothed.is_empty() {
+            self.draw_overlays(draw_list, size, origin);
+            return;
         }
 
-        // See if there is new data to render
-        if let Ok(data) = self.samples.try_recv() {
-            self.current_render_data = self.preprocess_data(&data);
-            self.current_render_data = self.interpolate_data();        
+        if self.smoothed_data.len() < 4 {
+            return;
+        }
+
+        let render_data = self.interpolate_data(&self.scale_data(&self.smoothed_data, size));
+
+        if self.theme.fill {
+            self.draw_fill(draw_list, &render_data, size, origin);
         }
 
-        // Draw bezier curves for the visualisation
-        for set in self.current_render_data.windows(4).step_by(3) {
+        // Thicken the line on each beat if the theme pulses
+        let line_thickness = if self.theme.pulse {
+            self.theme.line_thickness * (1.0 + self.beat_pulse)
+        } else {
+            self.theme.line_thickness
+        };
+
+        // Glow is drawn as wider translucent curves underneath the line
+        if self.theme.glow {
+            for pass in (1..=GLOW_PASSES).rev() {
+                let thickness = line_thickness * (1.0 + 2.0 * pass as f32);
+                let alpha = self.theme.glow_intensity / pass as f32;
+                self.draw_curve(draw_list, &render_data, size, origin, thickness, alpha, None);
+            }
+        }
+
+        self.draw_curve(draw_list, &render_data, size, origin, line_thickness, 1.0, None);
+    }
+
+    /// Draws the curve of each overlaid channel along with a legend naming them.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    fn draw_overlays(&self, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2]) {
+        let channels = self.overlay_smoothed.len();
+        for (channel, data) in self.overlay_smoothed.iter().enumerate() {
+            if data.len() < 4 {
+                continue;
+            }
+
+            let render_data = self.interpolate_data(&self.scale_data(data, size));
+            self.draw_curve(draw_list, &render_data, size, origin, self.theme.line_thickness, 1.0, Some(channel));
+
+            let name = self.overlay_names.get(channel)
+                .cloned()
+                .unwrap_or_else(|| common_audio_manager::channel_name(channel as u16, channels as u16));
+            let colour = CHANNEL_COLOURS[channel % CHANNEL_COLOURS.len()];
+            // Names are right aligned by estimating their width, as source names can be long
+            let x = origin[0] + size[0] - 8.0 - LEGEND_CHAR_WIDTH * name.chars().count() as f32;
+            draw_list.add_text([x, origin[1] + 4.0 + 14.0 * channel as f32], colour, name);
+        }
+    }
+
+    /// Draws bezier curves through the render data for the visualisation.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `render_data` - Is the interpolated data to draw.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    /// 
+    /// * `thickness` - Is the thickness of the curve.
+    /// 
+    /// * `alpha` - Is the factor the alpha of the theme colour is multiplied by.
+    /// 
+    /// * `channel` - Is the channel the curve belongs to when channels are overlaid, which replaces the theme colour.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_curve(
+        &self,
+        draw_list: &DrawListMut<'_>,
+        render_data: &[[f32; 2]],
+        size: [f32; 2],
+        origin: [f32; 2],
+        thickness: f32,
+        alpha: f32,
+        channel: Option<usize>
+    ) {
+        for set in render_data.windows(4).step_by(3) {
             if set.len() < 4 {
                 break;
             }
 
+            let colour = match channel {
+                Some(channel) => CHANNEL_COLOURS[channel % CHANNEL_COLOURS.len()],
+                None => self.segment_colour(set[0], set[3], size),
+            };
             draw_list.add_bezier_curve(
                 [origin[0] + set[0][0], origin[1] + set[0][1]],
                 [origin[0] + set[1][0], origin[1] + set[1][1]],
                 [origin[0] + set[2][0], origin[1] + set[2][1]],
                 [origin[0] + set[3][0], origin[1] + set[3][1]],
-                ImColor32::from_rgba(255, 255, 255, 255)
-            ).build();
+                theme::with_alpha(colour, alpha)
+            ).thickness(thickness).build();
         }
     }
 
-    /// Resizes the visualisation to fit the new window size.
+    /// Fills the area underneath the curve.
     /// 
     /// # Arguments
     /// 
-    /// * `size` - Is the new size of the window.
-    fn resize(&mut self, size: [f32; 2]) {
-        let width_factor = size[0] / self.current_size[0];
-        let height_factor = size[1] / self.current_size[1];
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `render_data` - Is the interpolated data to fill beneath.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    fn draw_fill(&self, draw_list: &DrawListMut<'_>, render_data: &[[f32; 2]], size: [f32; 2], origin: [f32; 2]) {
+        let bottom = origin[1] + size[1];
+
+        // Each pair of points forms a convex quad down to the bottom of the window
+        for pair in render_data.windows(2) {
+            if pair[1][0] <= pair[0][0] {
+                continue;
+            }
 
-        self.current_render_data = self.current_render_data.iter().map(|x| [x[0] * width_factor, x[1] * height_factor]).collect();
-        self.current_size = size;
+            let colour = self.segment_colour(pair[0], pair[1], size);
+            let points = vec![
+                [origin[0] + pair[0][0], origin[1] + pair[0][1]],
+                [origin[0] + pair[1][0], origin[1] + pair[1][1]],
+                [origin[0] + pair[1][0], bottom],
+                [origin[0] + pair[0][0], bottom],
+            ];
+
+            draw_list.add_polyline(points, theme::with_alpha(colour, self.theme.fill_opacity)).filled(true).build();
+        }
+    }
+
+    /// Returns the theme colour of the segment of the curve between two points.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - Is the first point of the segment.
+    /// 
+    /// * `end` - Is the last point of the segment.
+    /// 
+    /// * `size` - Is the size of the render window.
+    fn segment_colour(&self, start: [f32; 2], end: [f32; 2], size: [f32; 2]) -> [f32; 4] {
+        let frequency = match (self.theme.gradient, self.features) {
+            (GradientMode::Brightness, Some(features)) => self.frequency_scale.position(features.centroid),
+            // Features are always extracted for this gradient, so are only missing until the first frame arrives
+            (GradientMode::Brightness, None) => 0.0,
+            _ => (start[0] + end[0]) / 2.0 / size[0],
+        };
+        let amplitude = 1.0 - ((start[1] + end[1]) / 2.0 + 1.0) / size[1];
+
+        self.theme.colour_at(frequency, amplitude)
+    }
+
+    /// Draws a crosshair at the mouse and a tooltip describing the spectrum at that frequency.
+    /// 
+    /// The tooltip gives the frequency under the cursor, the nearest note and how far from it the frequency is in
+    /// cents, and the level of the latest frame at that frequency.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `ui` - Is the ImGui UI the tooltip is created with.
+    /// 
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    pub fn render_hover(&self, ui: &Ui, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2]) {
+        let mouse = ui.io().mouse_pos;
+        let x = mouse[0] - origin[0];
+        let y = mouse[1] - origin[1];
+        if x < 0.0 || y < 0.0 || x > size[0] || y > size[1] || size[0] <= 0.0 {
+            return;
+        }
+
+        // Crosshair
+        draw_list.add_line([mouse[0], origin[1]], [mouse[0], origin[1] + size[1]], CROSSHAIR_COLOUR).build();
+        draw_list.add_line([origin[0], mouse[1]], [origin[0] + size[0], mouse[1]], CROSSHAIR_COLOUR).build();
+
+        let frequency = self.frequency_scale.frequency(x / size[0]);
+        let (note, cents) = notes::nearest_note(frequency);
+        let mut text = format!("{frequency:.1} Hz\n{note} {cents:+.0} cents");
+
+        // Level of the bin closest to the frequency in the latest frame
+        if !self.current_frame.is_empty() {
+            let index = self.current_frame.partition_point(|x| x.1 < frequency);
+            let len = self.current_frame.len();
+            let index = if index == len || (index > 0 && frequency - self.current_frame[index - 1].1 < self.current_frame[index].1 - frequency) {
+                index - 1
+            } else {
+                index
+            };
+
+            let level = scales::to_decibels(self.current_frame[index].0.norm());
+            text.push_str(&format!("\n{level:.1} dBFS"));
+        }
+
+        ui.tooltip_text(text);
+    }
+
+    /// Draws the response of the equaliser over the spectrum with a handle for each band.
+    /// 
+    /// Dragging a handle changes the frequency and gain of its band and scrolling over it changes its Q. The whole area
+    /// is covered by an invisible button so dragging doesn't move the window.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `ui` - Is the ImGui UI the mouse is read from.
+    /// 
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    /// 
+    /// * `equaliser` - Is the equaliser, which is changed by the user.
+    /// 
+    /// * `sample_rate` - Is the sample rate the equaliser filters at.
+    pub fn render_equaliser(
+        &mut self,
+        ui: &Ui,
+        draw_list: &DrawListMut<'_>,
+        size: [f32; 2],
+        origin: [f32; 2],
+        equaliser: &mut Equaliser,
+        sample_rate: u32
+    ) {
+        self.active_band = None;
+        if size[0] <= 0.0 || size[1] <= 0.0 {
+            return;
+        }
+
+        // Gains are drawn on their own axis, with 0 dB across the middle
+        let scale = self.frequency_scale;
+        let centre = origin[1] + size[1] / 2.0;
+        let gain_y = |gain: f32| centre - gain.clamp(-MAX_GAIN, MAX_GAIN) / MAX_GAIN * size[1] / 2.0;
+
+        ui.set_cursor_screen_pos(origin);
+        ui.invisible_button("##equaliser", size);
+        let hovered = ui.is_item_hovered() || ui.is_item_active();
+        let mouse = ui.io().mouse_pos;
+
+        // Pick up the handle under the mouse, pass filters have no gain so their handles stay on the 0 dB line
+        let handle = |band: &EqBand| {
+            let y = if band.filter_type.has_gain() { gain_y(band.gain) } else { centre };
+            [origin[0] + scale.position(band.frequency) * size[0], y]
+        };
+        let hovered_band = equaliser.bands.iter()
+            .map(handle)
+            .position(|[x, y]| (x - mouse[0]).hypot(y - mouse[1]) <= EQ_HANDLE_RADIUS * 2.0)
+            .filter(|_| hovered);
+
+        // The band being dragged is kept even if the mouse moves faster than its handle
+        if ui.is_item_clicked() {
+            self.dragged_band = hovered_band;
+        } else if !ui.is_mouse_down(MouseButton::Left) {
+            self.dragged_band = None;
+        }
+
+        let active_band = self.dragged_band.or(hovered_band);
+        if let Some(band) = active_band.and_then(|index| equaliser.bands.get_mut(index)) {
+            if self.dragged_band.is_some() {
+                band.frequency = scale.frequency(((mouse[0] - origin[0]) / size[0]).clamp(0.0, 1.0));
+                if band.filter_type.has_gain() {
+                    band.gain = ((centre - mouse[1]) / (size[1] / 2.0) * MAX_GAIN).clamp(-MAX_GAIN, MAX_GAIN);
+                }
+            }
+
+            let wheel = ui.io().mouse_wheel;
+            if wheel != 0.0 {
+                band.q = (band.q * 1.1_f32.powf(wheel)).clamp(Q_RANGE.0, Q_RANGE.1);
+            }
+        }
+        self.active_band = active_band;
+
+        // Response of every band combined
+        let points: Vec<[f32; 2]> = (0..=EQ_CURVE_POINTS).map(|point| {
+            let position = point as f32 / EQ_CURVE_POINTS as f32;
+            [origin[0] + position * size[0], gain_y(equaliser.response(scale.frequency(position), sample_rate))]
+        }).collect();
+        let colour = if equaliser.enabled { EQ_CURVE_COLOUR } else { EQ_BYPASSED_COLOUR };
+        draw_list.add_line([origin[0], centre], [origin[0] + size[0], centre], GRID_COLOUR).build();
+        draw_list.add_polyline(points, colour).thickness(2.0).build();
+
+        for (index, band) in equaliser.bands.iter().enumerate() {
+            let position = handle(band);
+            let colour = EQ_HANDLE_COLOURS[(active_band == Some(index)) as usize];
+            draw_list.add_circle(position, EQ_HANDLE_RADIUS, colour).filled(true).build();
+            draw_list.add_text([position[0] + EQ_HANDLE_RADIUS, position[1] - 2.0 * EQ_HANDLE_RADIUS], LABEL_COLOUR, format!("{}", index + 1));
+        }
+
+        if let Some(band) = active_band.and_then(|index| equaliser.bands.get(index)) {
+            let mut text = format!("{}\n{:.1} Hz", band.filter_type.name(), band.frequency);
+            if band.filter_type.has_gain() {
+                text.push_str(&format!("\n{:+.1} dB", band.gain));
+            }
+            text.push_str(&format!("\nQ {:.2}", band.q));
+            ui.tooltip_text(text);
+        }
+    }
+
+    /// Draws a peak and RMS meter for each channel, with a peak hold line and clip indicator.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `draw_list` - Is the draw list for the level window.
+    /// 
+    /// * `size` - Is the size of the level window.
+    /// 
+    /// * `origin` - Is the origin of the level window.
+    /// 
+    /// * `delta_time` - Is the time in seconds since the last rendered frame.
+    pub fn render_levels(&mut self, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2], delta_time: f32) {
+        const CLIP_HEIGHT: f32 = 8.0;
+        const GAP: f32 = 2.0;
+
+        // Update the ballistics with the levels of the frames presented since last time
+        let levels = self.pending_levels.take();
+        if let Some(levels) = &levels {
+            if levels.len() != self.channel_meters.len() {
+                self.channel_meters = vec![ChannelMeter::default(); levels.len()];
+            }
+        }
+
+        for (channel, meter) in self.channel_meters.iter_mut().enumerate() {
+            meter.update(levels.as_ref().map(|levels| levels[channel]), self.ballistics, delta_time);
+        }
+
+        if self.channel_meters.is_empty() {
+            return;
+        }
+
+        let width = (size[0] - GAP * (self.channel_meters.len() - 1) as f32) / self.channel_meters.len() as f32;
+        let top = origin[1] + CLIP_HEIGHT + GAP;
+        let bottom = origin[1] + size[1];
+        let level_y = |level: f32| bottom - (bottom - top) * (1.0 - level / level_meter::METER_FLOOR).clamp(0.0, 1.0);
+
+        for (channel, meter) in self.channel_meters.iter().enumerate() {
+            let left = origin[0] + channel as f32 * (width + GAP);
+            let right = left + width;
+
+            draw_list.add_rect([left, top], [right, bottom], [1.0, 1.0, 1.0, 0.1]).filled(true).build();
+            draw_list.add_rect([left, level_y(meter.peak)], [right, bottom], Self::meter_colour(meter.peak, 0.5)).filled(true).build();
+            draw_list.add_rect([left, level_y(meter.rms)], [right, bottom], Self::meter_colour(meter.rms, 1.0)).filled(true).build();
+
+            let hold = level_y(meter.hold);
+            draw_list.add_line([left, hold], [right, hold], Self::meter_colour(meter.hold, 1.0)).thickness(2.0).build();
+
+            // Clip indicator stays lit until it is reset
+            let clip_colour = if meter.clipped { [1.0, 0.1, 0.1, 1.0] } else { [1.0, 1.0, 1.0, 0.1] };
+            draw_list.add_rect([left, origin[1]], [right, origin[1] + CLIP_HEIGHT], clip_colour).filled(true).build();
+        }
+    }
+
+    /// Returns the colour of a meter at a level, green in the normal range, then yellow and red approaching full scale.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `level` - Is the level in dBFS.
+    /// 
+    /// * `alpha` - Is the alpha of the colour.
+    fn meter_colour(level: f32, alpha: f32) -> [f32; 4] {
+        if level > -6.0 {
+            [1.0, 0.2, 0.1, alpha]
+        } else if level > -18.0 {
+            [1.0, 0.85, 0.1, alpha]
+        } else {
+            [0.2, 0.85, 0.3, alpha]
+        }
+    }
+
+    /// Draws the scrolling chroma view, with time along the x axis and a row for each pitch class.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `draw_list` - Is the draw list for the chroma window.
+    /// 
+    /// * `size` - Is the size of the chroma window.
+    /// 
+    /// * `origin` - Is the origin of the chroma window.
+    pub fn render_chroma(&self, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2]) {
+        const LABEL_WIDTH: f32 = 24.0;
+
+        let row_height = size[1] / 12.0;
+        let column_width = (size[0] - LABEL_WIDTH) / CHROMA_HISTORY as f32;
+
+        // Newest frames are on the right, with C at the bottom
+        let start = CHROMA_HISTORY - self.chroma_history.len();
+        for (i, chroma) in self.chroma_history.iter().enumerate() {
+            let x = origin[0] + LABEL_WIDTH + (start + i) as f32 * column_width;
+            for (pitch_class, energy) in chroma.iter().enumerate() {
+                let y = origin[1] + size[1] - (pitch_class + 1) as f32 * row_height;
+                let colour = self.theme.colour_at(pitch_class as f32 / 11.0, *energy);
+                draw_list.add_rect([x, y], [x + column_width + 1.0, y + row_height], theme::with_alpha(colour, *energy)).filled(true).build();
+            }
+        }
+
+        for pitch_class in 0..12 {
+            let y = origin[1] + size[1] - (pitch_class + 1) as f32 * row_height;
+            let name = notes::note_name(pitch_class + 60);
+            draw_list.add_text([origin[0], y], LABEL_COLOUR, name.trim_end_matches(char::is_numeric));
+        }
+    }
+
+    /// Draws the frequency and amplitude gridlines along with their labels.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `draw_list` - Is the draw list for the render window.
+    /// 
+    /// * `size` - Is the size of the render window.
+    /// 
+    /// * `origin` - Is the origin of the render window.
+    fn draw_axes(&self, draw_list: &DrawListMut<'_>, size: [f32; 2], origin: [f32; 2]) {
+        if !self.show_grid && !self.show_axis_labels {
+            return;
+        }
+
+        let width = size[0];
+        let height = size[1];
+
+        // Vertical gridlines for frequencies
+        let mut last_x = f32::MIN;
+        for (frequency, label) in FREQUENCY_LABELS {
+            let x = self.frequency_scale.position(frequency) * width;
+            if x - last_x < MIN_GRID_SPACING {
+                continue;
+            }
+            last_x = x;
+
+            if self.show_grid {
+                draw_list.add_line([origin[0] + x, origin[1]], [origin[0] + x, origin[1] + height], GRID_COLOUR).build();
+            }
+
+            if self.show_axis_labels {
+                draw_list.add_text([origin[0] + x + 2.0, origin[1] + height - 14.0], LABEL_COLOUR, label);
+            }
+        }
+
+        // Horizontal gridlines for levels, relative to the peak when normalised and to full scale otherwise
+        let levels: Vec<f32> = match self.amplitude_scale {
+            AmplitudeScale::Normalised => NORMALISED_GRIDLINES.to_vec(),
+            AmplitudeScale::Decibel => (0..).map(|i| i as f32 * -10.0).take_while(|&level| level > self.db_floor).collect(),
+        };
+
+        let mut last_y = f32::MIN;
+        for level in levels {
+            let y = height - self.level_position(level) * height - 1.0;
+            if y - last_y < MIN_GRID_SPACING {
+                continue;
+            }
+            last_y = y;
+
+            if self.show_grid {
+                draw_list.add_line([origin[0], origin[1] + y], [origin[0] + width, origin[1] + y], GRID_COLOUR).build();
+            }
+
+            if self.show_axis_labels {
+                draw_list.add_text([origin[0] + 2.0, origin[1] + y + 1.0], LABEL_COLOUR, format!("{level} dB"));
+            }
+        }
+    }
+
+    /// Returns the position between 0 and 1 on the amplitude axis of a level.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `decibels` - Is the level in decibels.
+    fn level_position(&self, decibels: f32) -> f32 {
+        match self.amplitude_scale {
+            AmplitudeScale::Normalised => scales::from_decibels(decibels),
+            AmplitudeScale::Decibel => ((decibels - self.db_floor) / -self.db_floor).clamp(0.0, 1.0),
+        }
+    }
+
+    /// Drains the channel into the pending queue and returns the most recent frame that is now audible.
+    /// 
+    /// Frames that became audible before it are skipped, frames that are still in the future are kept for later renders.
+    fn next_audible_frame(&mut self) -> Option<FftFrame> {
+        self.pending_frames.extend(self.samples.try_iter());
+        while self.pending_frames.len() > MAX_PENDING_FRAMES {
+            if let Some(frame) = self.pending_frames.pop_front() {
+                self.samples.recycle(frame);
+            }
+        }
+
+        // Shift the clock rather than the frames so a negative offset can present frames early
+        let offset = Duration::from_secs_f32(self.av_offset.abs());
+        let now = if self.av_offset >= 0.0 {
+            Instant::now().checked_sub(offset).unwrap_or_else(Instant::now)
+        } else {
+            Instant::now() + offset
+        };
+
+        // Beats, chroma and levels in skipped frames still need to be shown
+        let mut latest = None;
+        while let Some(frame) = self.pending_frames.pop_front() {
+            if frame.timestamp > now {
+                self.pending_frames.push_front(frame);
+                break;
+            }
+
+            if frame.beat.beat {
+                self.beat_pulse = 1.0;
+            }
+            if frame.beat.onset {
+                self.onset_pulse = 1.0;
+            }
+            self.bpm = frame.beat.bpm;
+            self.pitch = frame.pitch;
+            self.loudness = frame.loudness;
+            self.features = frame.features;
+            self.channels = frame.levels.len() as u16;
+
+            // A failed write stops the log rather than reporting the same error every frame
+            if let (Some(log), Some(features)) = (self.feature_log.as_mut(), frame.features.as_ref()) {
+                if let Err(error) = log.write(frame.timestamp, features) {
+                    self.feature_log_error = Some(format!("Failed to write spectral features: {error}"));
+                    self.feature_log = None;
+                }
+            }
+
+            // Combine the levels of every frame so no peaks are missed
+            self.pending_levels = Some(match self.pending_levels.take() {
+                Some(levels) if levels.len() == frame.levels.len() => {
+                    levels.iter().zip(frame.levels.iter()).map(|(a, b)| a.max(*b)).collect()
+                }
+                _ => frame.levels.clone(),
+            });
+
+            // Every frame is kept for the chroma view so it scrolls at a constant rate
+            if self.chroma_history.len() == CHROMA_HISTORY {
+                self.chroma_history.pop_front();
+            }
+            self.chroma_history.push_back(frame.pitch.chroma);
+
+            if let Some(skipped) = latest.replace(frame) {
+                self.samples.recycle(skipped);
+            }
+        }
+
+        latest
+    }
+
+    /// Moves the displayed data towards the target data.
+    /// 
+    /// Separate attack and release times are used so peaks appear quickly and decay gracefully, the amount moved is
+    /// scaled by the frame time so the animation looks the same at any refresh rate.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `delta_time` - Is the time in seconds since the last rendered frame.
+    fn smooth(&mut self, delta_time: f32) {
+        let attack = Self::smoothing_coefficient(self.attack, delta_time);
+        let release = Self::smoothing_coefficient(self.release, delta_time);
+
+        Self::smooth_towards(&mut self.smoothed_data, &self.target_data, attack, release);
+
+        // Overlaid channels are animated in the same way, each towards its own target
+        self.overlay_smoothed.resize_with(self.overlay_targets.len(), Vec::new);
+        for (smoothed, target) in self.overlay_smoothed.iter_mut().zip(self.overlay_targets.iter()) {
+            Self::smooth_towards(smoothed, target, attack, release);
+        }
+    }
+
+    /// Moves a single curve towards its target.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `smoothed` - Is the displayed data of the curve.
+    /// 
+    /// * `target` - Is the data the curve is moving towards.
+    /// 
+    /// * `attack` - Is the fraction of the distance moved when rising.
+    /// 
+    /// * `release` - Is the fraction of the distance moved when falling.
+    fn smooth_towards(smoothed: &mut Vec<f32>, target: &[f32], attack: f32, release: f32) {
+        // If the number of points has changed there is nothing sensible to animate from
+        if smoothed.len() != target.len() {
+            *smoothed = target.to_vec();
+            return;
+        }
+
+        for (current, target) in smoothed.iter_mut().zip(target.iter()) {
+            let coefficient = if *target > *current { attack } else { release };
+            *current += (*target - *current) * coefficient;
+        }
+    }
+
+    /// Returns the fraction of the remaining distance to move this frame for a given time constant.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `time` - Is the time constant in seconds.
+    /// 
+    /// * `delta_time` - Is the time in seconds since the last rendered frame.
+    fn smoothing_coefficient(time: f32, delta_time: f32) -> f32 {
+        if time <= 0.0 {
+            return 1.0;
+        }
+
+        1.0 - (-delta_time / time).exp()
     }
 
     /// Performs necessary preprocessing.
     /// 
-    /// This includes calculating the mel of each frequency, averaging the data into 100 chunks, and scaling the data between 0 and 1.
+    /// This includes averaging the data into bands evenly spaced on the frequency scale, and scaling the data between 0 and 1.
     /// 
     /// # Arguments
     /// 
     /// * `data` - Is the data to process.
-    fn preprocess_data(&self, data: &[(Complex<f32>, f32)]) -> Vec<[f32; 2]> {
-        let width = self.current_size[0];
-        let height = self.current_size[1];
+    fn preprocess_data(&self, data: &[(Complex<f32>, f32)]) -> Vec<f32> {
+        if data.is_empty() {
+            return Vec::new();
+        }
 
-        // Calculate the mel of each frequency
-        let mel_data: Vec<(Complex<f32>, f32)> = data.iter().map(|&x| (x.0, 1127.0 * (1.0 + x.1 / 700.0).ln())).collect();
+        let averaged_data = self.band_magnitudes(data);
+        let largest = averaged_data.iter().cloned().fold(0.0, f32::max);
 
-        // Average data into arbitrary number of 150 chunks
-        let per_chunk = mel_data.len() / 150;
-        let mut averaged_data = Vec::with_capacity(150);
-        for chunk in mel_data.chunks(per_chunk) {
-            let mut sum = 0.0;
-            let mut i = 0;
-            for value in chunk {
-                sum += value.0.norm();
-                i += 1;
-            }
+        self.scale_amplitudes(averaged_data, largest)
+    }
 
-            averaged_data.push(sum / i as f32);
+    /// Returns the data of each overlaid channel ready to be visualised.
+    /// 
+    /// Channels are normalised together so their levels can be compared.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `overlays` - Is the FFT data of each channel.
+    fn preprocess_overlays(&self, overlays: &[Vec<(Complex<f32>, f32)>]) -> Vec<Vec<f32>> {
+        let averaged: Vec<Vec<f32>> = overlays.iter().filter(|data| !data.is_empty()).map(|data| self.band_magnitudes(data)).collect();
+        let largest = averaged.iter().flatten().cloned().fold(0.0, f32::max);
+
+        averaged.into_iter().map(|data| self.scale_amplitudes(data, largest)).collect()
+    }
+
+    /// Scales the band magnitudes between 0 and 1 using the amplitude scale.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `averaged_data` - Is the magnitude of each band.
+    /// 
+    /// * `largest` - Is the magnitude that is scaled to 1 when normalising.
+    fn scale_amplitudes(&self, mut averaged_data: Vec<f32>, largest: f32) -> Vec<f32> {
+        // Scale data between 0 and 1, silence would otherwise produce NaN when normalised which would never be smoothed away
+        match self.amplitude_scale {
+            AmplitudeScale::Normalised => {
+                if largest > 0.0 {
+                    averaged_data.iter_mut().for_each(|x| *x /= largest);
+                } else {
+                    averaged_data.iter_mut().for_each(|x| *x = 0.0);
+                }
+            }
+            AmplitudeScale::Decibel => {
+                averaged_data.iter_mut().for_each(|x| *x = self.level_position(scales::to_decibels(*x)));
+            }
         }
 
         // Add a chunk of 0 at the start and end to improve visualisation
         averaged_data.insert(0, 0.0);
         averaged_data.push(0.0);
 
-        // Normalise data between 0 and 1, scale it to the height of the window and invert for visualisation
-        let largest = averaged_data.iter().max_by(|a, b| a.total_cmp(b)).unwrap();
-        let processed_data: Vec<f32> = averaged_data.iter().map(|x| height - ((x / largest) * height) - 1_f32).collect();
+        averaged_data
+    }
 
-        // Get x coords of each point
-        let mut x_values = Vec::with_capacity(averaged_data.len());
-        let step = width / averaged_data.len() as f32;
-        for i in 0..averaged_data.len() {
-            x_values.push(step * i as f32);
-        }
+    /// Averages the magnitude of the bins into bands of equal width on the frequency scale.
+    /// 
+    /// Bands too narrow to contain a bin, which happens in the bass on logarithmic scales, are interpolated from the
+    /// neighbouring bins instead.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - Is the FFT data ordered by frequency.
+    fn band_magnitudes(&self, data: &[(Complex<f32>, f32)]) -> Vec<f32> {
+        let mut bands = Vec::with_capacity(BANDS);
+        for band in 0..BANDS {
+            let low = self.frequency_scale.frequency(band as f32 / BANDS as f32);
+            let high = self.frequency_scale.frequency((band + 1) as f32 / BANDS as f32);
 
-        // Combine x and y coords
-        let mut final_data = Vec::with_capacity(processed_data.len());
-        for i in 0..processed_data.len() {
-            final_data.push([x_values[i], processed_data[i]])
+            let start = data.partition_point(|x| x.1 < low);
+            let end = data.partition_point(|x| x.1 < high);
+
+            if end > start {
+                let sum: f32 = data[start..end].iter().map(|x| x.0.norm()).sum();
+                bands.push(sum / (end - start) as f32);
+                continue;
+            }
+
+            // Interpolate between the bins either side of the centre of the band
+            let centre = self.frequency_scale.frequency((band as f32 + 0.5) / BANDS as f32);
+            let index = data.partition_point(|x| x.1 < centre);
+            let magnitude = if index == 0 {
+                data[0].0.norm()
+            } else if index == data.len() {
+                data[data.len() - 1].0.norm()
+            } else {
+                let (below, above) = (data[index - 1], data[index]);
+                let t = (centre - below.1) / (above.1 - below.1);
+                below.0.norm() + (above.0.norm() - below.0.norm()) * t
+            };
+
+            bands.push(magnitude);
         }
 
-        final_data
+        bands
+    }
+
+    /// Converts smoothed data into coordinates within the render window.
+    /// 
+    /// Each band is placed at its centre on the frequency scale, with the padding points at either edge of the window.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - Is the smoothed data of a curve.
+    /// 
+    /// * `size` - Is the size of the render window.
+    fn scale_data(&self, data: &[f32], size: [f32; 2]) -> Vec<[f32; 2]> {
+        let width = size[0];
+        let height = size[1];
+        let last = data.len() - 1;
+
+        // Scale to the height of the window and invert for visualisation
+        data.iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let position = match i {
+                    0 => 0.0,
+                    i if i == last => 1.0,
+                    i => (i as f32 - 0.5) / (last - 1) as f32,
+                };
+
+                [position * width, height - (x * height) - 1_f32]
+            })
+            .collect()
     }
 
     /// Performs an interpolation of the data, this is used to create a smooth visualisation.
     /// 
     /// The data is interpolated using a Catmull-Rom spline.
-    fn interpolate_data(&self) -> Vec<[f32; 2]> {
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - Is the data to interpolate.
+    fn interpolate_data(&self, data: &[[f32; 2]]) -> Vec<[f32; 2]> {
         // Create spline keys
-        let mut keys = Vec::with_capacity(self.current_render_data.len());
-        for value in self.current_render_data.iter() {
+        let mut keys = Vec::with_capacity(data.len());
+        for value in data.iter() {
             keys.push(Key::new(value[0], value[1], splines::Interpolation::CatmullRom));
         }
 
         // Create spline
         let spline = Spline::from_vec(keys);
-        let mut sampled_data = Vec::with_capacity(self.current_render_data.len() * 3);
+        let mut sampled_data = Vec::with_capacity(data.len() * 3);
 
         // Skip the first and last point during interpolation as it leads to odd behaviour
-        sampled_data.push(self.current_render_data[0]);
-        for set in self.current_render_data.chunks(2).skip(1).rev().skip(1).rev() {
+        sampled_data.push(data[0]);
+        for set in data.chunks(2).skip(1).rev().skip(1).rev() {
             if set.len() < 2 {
                 break;
             }
@@ -156,8 +1228,8 @@ impl FftRenderer {
             sampled_data.push([x2, spline.sample(x2).unwrap_or(0.0)]);
             sampled_data.push(set[1]);
         }
-        sampled_data.push(*self.current_render_data.last().unwrap());
+        sampled_data.push(*data.last().unwrap());
 
         sampled_data
     }
-}
\ No newline at end of file
+}
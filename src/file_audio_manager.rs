@@ -1,45 +1,73 @@
-use std::{ fs::File, io::BufReader, path::PathBuf, sync::{ mpsc::Sender, Arc }, time::Duration };
+use std::{ fs::File, io::{ self, BufReader }, path::{ Path, PathBuf }, sync::{ atomic::{ AtomicUsize, Ordering }, Arc, Mutex }, time::{ Duration, Instant } };
 use rodio::{ Decoder, OutputStream, source::Source, Sink, OutputStreamHandle };
-use rustfft::{ FftPlanner, num_complex::Complex, Fft };
+use rustfft::FftPlanner;
 
 use crate::FFT_FREQUENCY;
-use crate::common_audio_manager::FftHandler;
+use crate::common_audio_manager::{ AnalysisOptions, AnalysisWorker, FrameSender, PlaybackClock, WorkerHandle };
+use crate::equaliser::{ EqFilter, EqPreset, Equaliser };
+use crate::sample_ring::SampleProducer;
+use crate::signal_generator::{SignalGenerator, SignalSettings};
+use crate::spectral_features::FeatureLog;
+
+/// Shortest pause between rodio pulling samples that shows the output device has taken a new buffer.
+const BUFFER_GAP: Duration = Duration::from_millis(1);
+
+/// Number of frames of samples collected before they are passed to the analysis worker.
+const BATCH_FRAMES: usize = 256;
+
+/// Number of channels the test signal is generated with.
+const SIGNAL_CHANNELS: u16 = 2;
+
+/// Sample rate the equaliser is shown filtering at before any song has played.
+const DEFAULT_EQ_SAMPLE_RATE: u32 = 48000;
 
 // TODO: Look into using rodio's buffer to handle audio data
 
 /// Holds all information needed for the FFT filter over a Rodio stream.
 struct FftFilter<I> {
     input: I,
-    internal_vector: Vec<f32>,
-    counter: u16,
-    handler: FftHandler,
+    batch: Vec<f32>,
+    samples: SampleProducer,
+    clock: PlaybackClock,
+    /// Number of samples the analysis worker has fallen too far behind to take, shared with the audio manager.
+    dropped_samples: Arc<AtomicUsize>,
+    /// Number of frames written to the analysis worker.
+    written_frames: u64,
+    /// Number of frames rodio has pulled.
+    pulled_frames: u64,
+    /// When rodio last pulled a frame.
+    last_pull: Option<Instant>,
+    /// Frame the output device's current buffer started at, counted in pulled frames.
+    buffer_start: u64,
+    /// Number of frames in the last complete buffer the output device took.
+    buffer_frames: u64,
 }
 
 impl<I> Iterator for FftFilter<I>
 where I: Source<Item = f32>, {
     type Item = f32;
 
-    /// Handle each sample in the audio data and perform an FFT when enough samples have been collected.
+    /// Handle each sample in the audio data, passing them to the analysis worker in batches.
+    /// 
+    /// This runs on the audio output thread so does no analysis itself. Batches are whole frames, so if the worker
+    /// has fallen behind and a batch is dropped the channels stay aligned.
     fn next(&mut self) -> Option<f32> {
         let sample = match self.input.next() {
-            None => return None,
+            None => {
+                // Pass on the end of the song, which rarely fills a whole batch
+                self.write_batch();
+                return None;
+            }
             Some(s) => s,
         };
 
-        // Ensure we only get samples from the first channel
-        if self.counter % self.input.channels() == 0 {
-            self.internal_vector.push(sample);
+        if self.batch.len().is_multiple_of(self.input.channels().max(1) as usize) {
+            self.pull_frame();
         }
 
-        self.counter += 1;
-
-        // If we have enough samples to perform an FFT, then do so
-        if self.internal_vector.len() == (self.input.sample_rate() / FFT_FREQUENCY) as usize {
-            self.handler.perform_fft(self.internal_vector.as_slice());
-
-            // Remove the first quarter of the samples, this is done to smooth the visualisation by creating overlapping windows
-            self.internal_vector.drain(0..self.internal_vector.len() / 4);
-            self.counter = self.internal_vector.len() as u16;
+        self.batch.push(sample);
+        if self.batch.len() == self.batch.capacity() {
+            self.write_batch();
         }
 
         Some(sample)
@@ -80,15 +108,64 @@ where I: Source<Item = f32>, {
     /// 
     /// * `input` - Is the audio source to perform the FFT on.
     /// 
-    /// * `sample_destination` - Is the sender to the renderer.
+    /// * `samples` - Is the producer the analysis worker reads the samples from.
+    /// 
+    /// * `clock` - Is the clock the analysis worker times its frames with.
     /// 
-    /// * `filter` - Is the FFT algorithm to use.
-    pub fn new(input: I, sample_destination: Sender<Vec<(Complex<f32>, f32)>>, filter: Arc<dyn Fft<f32>>) -> Self {
-        let counter: u16 = 0;
-        let internal_vector = Vec::with_capacity(input.sample_rate() as usize / FFT_FREQUENCY as usize);
-        let handler = FftHandler::new(sample_destination, input.sample_rate(), filter);
+    /// * `dropped_samples` - Is the count of samples the analysis worker couldn't take.
+    pub fn new(input: I, samples: SampleProducer, clock: PlaybackClock, dropped_samples: Arc<AtomicUsize>) -> Self {
+        let batch = Vec::with_capacity(BATCH_FRAMES * input.channels() as usize);
+
+        FftFilter {
+            input,
+            batch,
+            samples,
+            clock,
+            dropped_samples,
+            written_frames: 0,
+            pulled_frames: 0,
+            last_pull: None,
+            buffer_start: 0,
+            buffer_frames: 0
+        }
+    }
 
-        FftFilter { input, internal_vector, counter, handler}
+    /// Passes the whole frames in the batch to the analysis worker, counting them as dropped if it has fallen behind.
+    fn write_batch(&mut self) {
+        let channels = self.input.channels().max(1) as usize;
+        let length = self.batch.len() - self.batch.len() % channels;
+        if length == 0 {
+            return;
+        }
+
+        if self.samples.push_slice(&self.batch[..length]) {
+            self.written_frames += (length / channels) as u64;
+        } else {
+            self.dropped_samples.fetch_add(length, Ordering::Relaxed);
+        }
+        self.batch.clear();
+    }
+
+    /// Counts a frame pulled by rodio, updating when the frames are heard whenever the output device takes a new buffer.
+    /// 
+    /// Rodio fills each buffer of the output device in one go, then waits while it plays. The frames at the start of a
+    /// buffer are heard once the buffer before has played, which takes as long as the frames rodio pulled for it.
+    fn pull_frame(&mut self) {
+        let now = Instant::now();
+        if self.last_pull.is_none_or(|last| now.duration_since(last) >= BUFFER_GAP) {
+            if self.last_pull.is_some() {
+                self.buffer_frames = self.pulled_frames - self.buffer_start;
+            }
+            self.buffer_start = self.pulled_frames;
+
+            // Frames waiting in the batch are counted as they are passed on with it
+            let frame = self.written_frames + (self.batch.len() / self.input.channels().max(1) as usize) as u64;
+            let latency = Duration::from_secs_f64(self.buffer_frames as f64 / self.input.sample_rate() as f64);
+            self.clock.set(frame, now + latency);
+        }
+
+        self.last_pull = Some(now);
+        self.pulled_frames += 1;
     }
 }
 
@@ -97,10 +174,24 @@ pub struct FileAudioManager {
     sink: Sink,
     _stream: OutputStream,
     _stream_handle: OutputStreamHandle,
-    sample_destination: Sender<Vec<(Complex<f32>, f32)>>,
+    sample_destination: FrameSender,
+    analysis_options: Arc<Mutex<AnalysisOptions>>,
     fft_planner: FftPlanner<f32>,
     opened_songs: Vec<PathBuf>,
-    selected_song_idx: usize
+    selected_song_idx: usize,
+    signal_settings: SignalSettings,
+    /// Settings shared with the test signal while it plays.
+    signal: Option<Arc<Mutex<SignalSettings>>>,
+    signal_audible: bool,
+    /// Equaliser shared with the filter of the playing song.
+    equaliser: Arc<Mutex<Equaliser>>,
+    eq_presets: Vec<EqPreset>,
+    /// Sample rate of the last song played, which the equaliser filters at.
+    eq_sample_rate: u32,
+    /// Number of samples of the playing song the analysis worker couldn't take.
+    dropped_samples: Arc<AtomicUsize>,
+    /// Worker analysing the playing song, stopped whenever the song changes.
+    analysis_worker: Option<WorkerHandle>,
 }
 
 impl FileAudioManager {
@@ -109,7 +200,9 @@ impl FileAudioManager {
     /// # Arguments
     /// 
     /// * `sample_destination`- Is the sender to the renderer.
-    pub fn new(sample_destination: Sender<Vec<(Complex<f32>, f32)>>) -> Self {
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the audio.
+    pub fn new(sample_destination: FrameSender, analysis_options: Arc<Mutex<AnalysisOptions>>) -> Self {
         // Initialise rodio
         let (_stream, stream_handle) = OutputStream::try_default().expect("Failed to get audio output device: ");
         let sink = Sink::try_new(&stream_handle).expect("Failed to create audio sink: ");
@@ -118,7 +211,24 @@ impl FileAudioManager {
         let opened_songs = Vec::new();
         let selected_song_idx = usize::MAX;
 
-        FileAudioManager { sink, _stream, _stream_handle: stream_handle, sample_destination, fft_planner, opened_songs, selected_song_idx }
+        FileAudioManager {
+            sink,
+            _stream,
+            _stream_handle: stream_handle,
+            sample_destination,
+            analysis_options,
+            fft_planner,
+            opened_songs,
+            selected_song_idx,
+            signal_settings: SignalSettings::default(),
+            signal: None,
+            signal_audible: true,
+            equaliser: Arc::new(Mutex::new(Equaliser::default())),
+            eq_presets: Vec::new(),
+            eq_sample_rate: DEFAULT_EQ_SAMPLE_RATE,
+            dropped_samples: Arc::new(AtomicUsize::new(0)),
+            analysis_worker: None,
+        }
     }
 
     /// Update list of currently opened songs.
@@ -163,9 +273,18 @@ impl FileAudioManager {
         self.play();
     }
 
-    /// Clears all audio in the current sink.
+    /// Clears all audio in the current sink, including the test signal.
     pub fn clear_queue(&mut self) {
         self.sink.clear();
+        self.analysis_worker = None;
+        self.signal = None;
+        self.sink.set_volume(1.0);
+        self.dropped_samples.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the number of samples of the playing song that weren't analysed because the analysis fell behind.
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped_samples.load(Ordering::Relaxed)
     }
 
     /// Returns whether the sink is paused.
@@ -183,6 +302,98 @@ impl FileAudioManager {
         self.sink.play();
     }
 
+    /// Returns the test signal that is generated.
+    pub fn signal_settings(&self) -> &SignalSettings {
+        &self.signal_settings
+    }
+
+    /// Sets the test signal that is generated, changing it straight away if it is playing.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `signal_settings` - Is the new signal.
+    pub fn set_signal_settings(&mut self, signal_settings: SignalSettings) {
+        let sample_rate_changed = signal_settings.sample_rate != self.signal_settings.sample_rate;
+        self.signal_settings = signal_settings;
+
+        // The sample rate of a rodio source can't change, so the generator is replaced
+        if self.signal.is_some() && sample_rate_changed {
+            self.play_signal();
+        } else if let Some(signal) = &self.signal {
+            *signal.lock().unwrap() = self.signal_settings.clone();
+        }
+    }
+
+    /// Returns whether the test signal is playing.
+    pub fn is_signal_playing(&self) -> bool {
+        self.signal.is_some()
+    }
+
+    /// Stops any song and plays the test signal instead, which is analysed like a song.
+    pub fn play_signal(&mut self) {
+        self.clear_queue();
+        self.selected_song_idx = usize::MAX;
+
+        let settings = Arc::new(Mutex::new(self.signal_settings.clone()));
+        let source = SignalGenerator::new(settings.clone(), SIGNAL_CHANNELS);
+        let filter = self.fft_filter(source);
+
+        self.sink.append(filter);
+        self.sink.set_volume(if self.signal_audible { 1.0 } else { 0.0 });
+        self.signal = Some(settings);
+        self.play();
+    }
+
+    /// Returns whether the test signal can be heard.
+    pub fn is_signal_audible(&self) -> bool {
+        self.signal_audible
+    }
+
+    /// Sets whether the test signal can be heard, it is analysed either way.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `signal_audible` - Is whether the test signal is played through the output device.
+    pub fn set_signal_audible(&mut self, signal_audible: bool) {
+        self.signal_audible = signal_audible;
+        if self.signal.is_some() {
+            self.sink.set_volume(if signal_audible { 1.0 } else { 0.0 });
+        }
+    }
+
+    /// Returns the equaliser applied to songs.
+    pub fn equaliser(&self) -> Equaliser {
+        self.equaliser.lock().unwrap().clone()
+    }
+
+    /// Sets the equaliser applied to songs, changing the playing song straight away.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `equaliser` - Is the new equaliser.
+    pub fn set_equaliser(&mut self, equaliser: Equaliser) {
+        *self.equaliser.lock().unwrap() = equaliser;
+    }
+
+    /// Returns the sample rate the equaliser filters the playing song at, or the last song if none is playing.
+    pub fn equaliser_sample_rate(&self) -> u32 {
+        self.eq_sample_rate
+    }
+
+    /// Returns the equaliser presets saved by the user.
+    pub fn eq_presets(&self) -> &[EqPreset] {
+        &self.eq_presets
+    }
+
+    /// Sets the equaliser presets saved by the user.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `eq_presets` - Is the new presets.
+    pub fn set_eq_presets(&mut self, eq_presets: Vec<EqPreset>) {
+        self.eq_presets = eq_presets;
+    }
+
     /// Adds a specified audio file to the audio manager, while applying necessary filters and converting data.
     /// 
     /// # Arguments
@@ -195,11 +406,111 @@ impl FileAudioManager {
             .pausable(false)
             .convert_samples();
 
+        // Apply the equaliser then the FFT filter to song and add to sink, so the equalised audio is visualised
+        let source = EqFilter::new(source, self.equaliser.clone());
+        self.eq_sample_rate = source.sample_rate();
+        let filter = self.fft_filter(source);
+        self.sink.append(filter);
+    }
+
+    /// Starts an analysis worker for a source, returning the FFT filter that passes the source's samples to it.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the audio source to analyse.
+    fn fft_filter<I: Source<Item = f32>>(&mut self, source: I) -> FftFilter<I> {
         // Plan FFT algorithm to use
         let fft = self.fft_planner.plan_fft_forward((source.sample_rate() / FFT_FREQUENCY) as usize);
+        let clock = PlaybackClock::default();
+        let (samples, worker) = AnalysisWorker::spawn(
+            self.sample_destination.clone(),
+            source.sample_rate(),
+            source.channels(),
+            fft,
+            clock.clone(),
+            self.analysis_options.clone()
+        );
+        self.analysis_worker = Some(worker);
+
+        FftFilter::new(source, samples, clock, self.dropped_samples.clone())
+    }
+}
 
-        // Apply FFT filter to song and add to sink
-        let filter = FftFilter::new(source, self.sample_destination.clone(), fft);
-        self.sink.append(filter);
+/// Writes the spectral features of a whole audio file to a CSV file without playing it, so it can be done without the UI.
+/// 
+/// # Arguments
+/// 
+/// * `song` - Is the path of the audio file.
+/// 
+/// * `path` - Is the path of the CSV file.
+pub fn export_features(song: &Path, path: &Path) -> io::Result<()> {
+    let reader = BufReader::new(File::open(song)?);
+    let source = Decoder::new(reader).map_err(io::Error::other)?.convert_samples();
+    let (sample_rate, channels) = (source.sample_rate(), source.channels());
+    let fft = FftPlanner::new().plan_fft_forward((sample_rate / FFT_FREQUENCY) as usize);
+    let analysis_options = AnalysisOptions { spectral_features: true, ..AnalysisOptions::default() };
+
+    // Times in the file are measured from the start of the song, as if it had been played
+    let start = Instant::now();
+    let mut feature_log = FeatureLog::create(path, start)?;
+    let mut result = Ok(());
+    AnalysisWorker::analyse_offline(source, sample_rate, channels, fft, analysis_options, start, |frame| {
+        if let (Ok(()), Some(features)) = (&result, frame.features.as_ref()) {
+            result = feature_log.write(frame.timestamp, features);
+        }
+    });
+
+    result.and_then(|_| feature_log.flush())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation_counter;
+    use crate::common_audio_manager;
+
+    #[test]
+    fn fft_filter_does_not_allocate() {
+        let (sender, _receiver) = common_audio_manager::frame_channel();
+        let source = SignalGenerator::new(Arc::new(Mutex::new(SignalSettings::default())), SIGNAL_CHANNELS);
+        let fft = FftPlanner::new().plan_fft_forward((source.sample_rate() / FFT_FREQUENCY) as usize);
+        let clock = PlaybackClock::default();
+        let options = Arc::new(Mutex::new(AnalysisOptions::default()));
+        let (samples, _worker) = AnalysisWorker::spawn(sender, source.sample_rate(), source.channels(), fft, clock.clone(), options);
+        let mut filter = FftFilter::new(source, samples, clock, Arc::new(AtomicUsize::new(0)));
+
+        // A second of audio passes many batches to the worker
+        let allocations = allocation_counter::allocations(|| {
+            for _ in 0..48000 * SIGNAL_CHANNELS as usize {
+                filter.next();
+            }
+        });
+        assert_eq!(allocations, 0);
+    }
+
+    #[test]
+    fn fft_filter_passes_on_last_partial_batch() {
+        let (samples, mut consumer) = crate::sample_ring::sample_ring(BATCH_FRAMES * 4);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        // Ends half way through a frame, which can't be analysed
+        let source = rodio::buffer::SamplesBuffer::new(2, 48000, vec![0.5; 301]);
+        let filter = FftFilter::new(source, samples, PlaybackClock::default(), dropped.clone());
+        assert_eq!(filter.count(), 301);
+
+        let mut output = Vec::new();
+        assert_eq!(consumer.pop_into(&mut output), 300);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn fft_filter_counts_dropped_samples() {
+        // Only room for one batch, as if the worker had stopped reading
+        let (samples, _consumer) = crate::sample_ring::sample_ring(BATCH_FRAMES * 2);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let source = rodio::buffer::SamplesBuffer::new(2, 48000, vec![0.5; BATCH_FRAMES * 2 * 3]);
+        let filter = FftFilter::new(source, samples, PlaybackClock::default(), dropped.clone());
+        filter.for_each(drop);
+
+        assert_eq!(dropped.load(Ordering::Relaxed), BATCH_FRAMES * 2 * 2);
     }
 }
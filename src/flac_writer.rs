@@ -0,0 +1,455 @@
+use std::{fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, path::Path};
+
+/// Number of samples of each channel in a frame.
+const BLOCK_SIZE: usize = 4096;
+
+/// Highest order of the fixed predictors.
+const MAX_ORDER: usize = 4;
+
+/// Largest Rice parameter that can be coded without an escape.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Writes audio to a FLAC file.
+/// 
+/// Each channel of each frame is encoded with whichever fixed polynomial predictor gives the smallest Rice coded
+/// residual, falling back to storing the samples verbatim. This is simpler than a full LPC encoder but still
+/// compresses music to roughly two thirds of its size.
+pub struct FlacWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u32,
+    blocks: Vec<Vec<i32>>,
+    current_channel: usize,
+    frame_number: u64,
+    total_samples: u64,
+}
+
+impl FlacWriter {
+    /// Creates the FLAC file and writes its header.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Is the path of the file.
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the audio, at most 8.
+    /// 
+    /// * `bits_per_sample` - Is the bits each sample is stored with, 8, 12, 16, 20 or 24.
+    pub fn create(path: &Path, sample_rate: u32, channels: u16, bits_per_sample: u32) -> io::Result<Self> {
+        if channels == 0 || channels > 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FLAC supports between 1 and 8 channels"));
+        }
+        if sample_size_code(bits_per_sample).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FLAC is written with 8, 12, 16, 20 or 24 bits per sample"));
+        }
+
+        let mut flac_writer = FlacWriter {
+            writer: BufWriter::new(File::create(path)?),
+            sample_rate,
+            channels,
+            bits_per_sample,
+            blocks: (0..channels).map(|_| Vec::with_capacity(BLOCK_SIZE)).collect(),
+            current_channel: 0,
+            frame_number: 0,
+            total_samples: 0,
+        };
+
+        // The stream information is written again once the length is known
+        flac_writer.writer.write_all(b"fLaC")?;
+        let stream_info = flac_writer.stream_info();
+        flac_writer.writer.write_all(&stream_info)?;
+
+        Ok(flac_writer)
+    }
+
+    /// Writes a single interleaved sample.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample` - Is the sample of the current channel where 1 is full scale.
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.blocks[self.current_channel].push(quantise(sample, self.bits_per_sample));
+
+        self.current_channel += 1;
+        if self.current_channel == self.blocks.len() {
+            self.current_channel = 0;
+            if self.blocks[0].len() == BLOCK_SIZE {
+                self.write_frame()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes any remaining samples and completes the stream information, this must be called to produce a valid file.
+    pub fn finalize(mut self) -> io::Result<()> {
+        // Drop any incomplete sample frame so every channel has the same length
+        let length = self.blocks.iter().map(|block| block.len()).min().unwrap_or(0);
+        self.blocks.iter_mut().for_each(|block| block.truncate(length));
+
+        if length > 0 {
+            self.write_frame()?;
+        }
+
+        let stream_info = self.stream_info();
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&stream_info)?;
+        self.writer.flush()
+    }
+
+    /// Returns the stream information metadata block.
+    fn stream_info(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+
+        // Header marking this as the last metadata block
+        bits.write(1, 1);
+        bits.write(0, 7);
+        bits.write(34, 24);
+
+        // Every frame but the last holds a whole block and the last is allowed to be shorter, so even a file shorter than
+        // the 16 sample minimum a block size can be has a valid one. Frame sizes and the MD5 signature are left as unknown.
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(0, 24);
+        bits.write(0, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        bits.write(self.total_samples >> 32, 4);
+        bits.write(self.total_samples & 0xFFFF_FFFF, 32);
+        for _ in 0..4 {
+            bits.write(0, 32);
+        }
+
+        bits.into_bytes()
+    }
+
+    /// Encodes the buffered samples as a frame and clears them.
+    fn write_frame(&mut self) -> io::Result<()> {
+        let block_size = self.blocks[0].len();
+        let mut bits = BitWriter::default();
+
+        // Sync code followed by a fixed block size strategy
+        bits.write(0xFFF8, 16);
+
+        // Block size at the end of the header, sample rate from the stream information and independent channels
+        bits.write(0b0111, 4);
+        bits.write(0b0000, 4);
+        bits.write(self.channels as u64 - 1, 4);
+        bits.write(sample_size_code(self.bits_per_sample).unwrap(), 3);
+        bits.write(0, 1);
+        bits.write_utf8(self.frame_number);
+        bits.write(block_size as u64 - 1, 16);
+        let header_crc = crc8(bits.bytes());
+        bits.write(header_crc as u64, 8);
+
+        for block in self.blocks.iter() {
+            Self::write_subframe(&mut bits, block, self.bits_per_sample);
+        }
+
+        bits.align();
+        let frame_crc = crc16(bits.bytes());
+        bits.write(frame_crc as u64, 16);
+        self.writer.write_all(bits.bytes())?;
+
+        self.frame_number += 1;
+        self.total_samples += block_size as u64;
+        self.blocks.iter_mut().for_each(|block| block.clear());
+
+        Ok(())
+    }
+
+    /// Encodes the samples of a single channel.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `bits` - Is the frame being written.
+    /// 
+    /// * `samples` - Is the samples of the channel.
+    /// 
+    /// * `bits_per_sample` - Is the bits each sample is stored with.
+    fn write_subframe(bits: &mut BitWriter, samples: &[i32], bits_per_sample: u32) {
+        let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+
+        // Choose the cheapest predictor, higher orders need more warm up samples than there may be
+        let best = (0..=MAX_ORDER.min(samples.len()))
+            .map(|order| {
+                let residuals = Self::residuals(samples, order);
+                let (parameter, cost) = Self::rice_parameter(&residuals);
+                (order, residuals, parameter, cost + (order as u64 * bits_per_sample as u64))
+            })
+            .min_by_key(|(_, _, _, cost)| *cost);
+
+        match best {
+            Some((order, residuals, parameter, cost)) if cost < verbatim_bits => {
+                bits.write(0, 1);
+                bits.write(0b001000 | order as u64, 6);
+                bits.write(0, 1);
+
+                for &sample in samples[..order].iter() {
+                    bits.write_signed(sample as i64, bits_per_sample);
+                }
+
+                // Rice coding with a single partition
+                bits.write(0b00, 2);
+                bits.write(0, 4);
+                bits.write(parameter as u64, 4);
+                for &residual in residuals.iter() {
+                    let folded = Self::fold(residual);
+                    bits.write_unary(folded >> parameter);
+                    bits.write(folded & ((1 << parameter) - 1), parameter);
+                }
+            }
+            _ => {
+                bits.write(0, 1);
+                bits.write(0b000001, 6);
+                bits.write(0, 1);
+
+                for &sample in samples.iter() {
+                    bits.write_signed(sample as i64, bits_per_sample);
+                }
+            }
+        }
+    }
+
+    /// Returns the residuals of the fixed polynomial predictor of an order.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the samples of the channel.
+    /// 
+    /// * `order` - Is the order of the predictor.
+    fn residuals(samples: &[i32], order: usize) -> Vec<i64> {
+        (order..samples.len()).map(|i| {
+            let x = |offset: usize| samples[i - offset] as i64;
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        }).collect()
+    }
+
+    /// Returns the Rice parameter giving the fewest bits for the residuals, along with that number of bits.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `residuals` - Is the residuals to code.
+    fn rice_parameter(residuals: &[i64]) -> (u32, u64) {
+        (0..=MAX_RICE_PARAMETER)
+            .map(|parameter| {
+                let cost: u64 = residuals.iter().map(|&residual| (Self::fold(residual) >> parameter) + 1 + parameter as u64).sum();
+                (parameter, cost)
+            })
+            .min_by_key(|(_, cost)| *cost)
+            .unwrap()
+    }
+
+    /// Maps a signed residual to an unsigned value, interleaving positive and negative values.
+    fn fold(residual: i64) -> u64 {
+        ((residual << 1) ^ (residual >> 63)) as u64
+    }
+}
+
+/// Returns a sample as an integer of a bit depth, where 1 is full scale.
+/// 
+/// # Arguments
+/// 
+/// * `sample` - Is the sample.
+/// 
+/// * `bits` - Is the number of bits of the integer, at most 32.
+pub fn quantise(sample: f32, bits: u32) -> i32 {
+    let scale = (1_i64 << (bits - 1)) as f64;
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+/// Returns the code a frame header gives a bit depth with, if FLAC can store it.
+/// 
+/// # Arguments
+/// 
+/// * `bits_per_sample` - Is the number of bits each sample is stored with.
+fn sample_size_code(bits_per_sample: u32) -> Option<u64> {
+    match bits_per_sample {
+        8 => Some(0b001),
+        12 => Some(0b010),
+        16 => Some(0b100),
+        20 => Some(0b101),
+        24 => Some(0b110),
+        _ => None,
+    }
+}
+
+/// Packs values into bytes most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest bits of a value.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `value` - Is the value to write.
+    /// 
+    /// * `bits` - Is the number of bits to write, at most 32.
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.count += bits;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.bytes.push((self.accumulator >> self.count) as u8);
+        }
+    }
+
+    /// Writes a signed value in two's complement.
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Writes a value in unary as that many zeros followed by a one.
+    fn write_unary(&mut self, value: u64) {
+        let mut remaining = value;
+        while remaining >= 32 {
+            self.write(0, 32);
+            remaining -= 32;
+        }
+        self.write(1, remaining as u32 + 1);
+    }
+
+    /// Writes a value with the variable length coding used for frame numbers, the same as UTF-8 but up to 36 bits.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+
+        let mut length = 2;
+        while value >= 1 << (5 * length + 1) {
+            length += 1;
+        }
+
+        let prefix = (0xFF00 >> length) & 0xFF;
+        self.write(prefix | (value >> (6 * (length - 1))), 8);
+        for i in (0..length - 1).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    /// Pads with zeros to the next byte.
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+
+    /// Returns the complete bytes written so far.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes written, padding the last byte with zeros.
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// Returns the CRC-8 of the bytes with the polynomial used by FLAC frame headers.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+    })
+}
+
+/// Returns the CRC-16 of the bytes with the polynomial used by FLAC frames.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes samples to a FLAC file and decodes it again, returning the stream information and the decoded samples.
+    fn round_trip(name: &str, samples: &[f32], channels: u16, bits_per_sample: u32) -> (claxon::metadata::StreamInfo, Vec<i32>) {
+        let path = std::env::temp_dir().join(format!("musualiser-{}-{name}.flac", std::process::id()));
+        let mut writer = FlacWriter::create(&path, 48000, channels, bits_per_sample).unwrap();
+        for &sample in samples.iter() {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut reader = claxon::FlacReader::open(&path).unwrap();
+        let decoded = reader.samples().map(|sample| sample.unwrap()).collect();
+        let stream_info = reader.streaminfo();
+        std::fs::remove_file(&path).unwrap();
+        (stream_info, decoded)
+    }
+
+    /// Returns a stereo sine with a different level in each channel.
+    fn sine(frames: usize) -> Vec<f32> {
+        (0..frames).flat_map(|i| {
+            let value = (i as f32 * 0.05).sin();
+            [value * 0.9, value * -0.3]
+        }).collect()
+    }
+
+    #[test]
+    fn decodes_24_bit() {
+        // Several whole blocks and a shorter last one
+        let samples = sine(BLOCK_SIZE * 2 + 1000);
+        let (stream_info, decoded) = round_trip("24", &samples, 2, 24);
+
+        assert_eq!(stream_info.bits_per_sample, 24);
+        assert_eq!(stream_info.channels, 2);
+        assert_eq!(stream_info.samples, Some((BLOCK_SIZE * 2 + 1000) as u64));
+        let expected: Vec<i32> = samples.iter().map(|&sample| quantise(sample, 24)).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn decodes_16_bit() {
+        let samples = sine(BLOCK_SIZE + 10);
+        let (stream_info, decoded) = round_trip("16", &samples, 2, 16);
+
+        assert_eq!(stream_info.bits_per_sample, 16);
+        let expected: Vec<i32> = samples.iter().map(|&sample| quantise(sample, 16)).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn decodes_file_shorter_than_minimum_block() {
+        let samples = sine(5);
+        let (stream_info, decoded) = round_trip("short", &samples, 2, 24);
+
+        assert!(stream_info.min_block_size >= 16);
+        assert_eq!(stream_info.samples, Some(5));
+        assert_eq!(decoded.len(), 10);
+    }
+
+    #[test]
+    fn rejects_unsupported_bit_depth() {
+        let path = std::env::temp_dir().join(format!("musualiser-{}-32.flac", std::process::id()));
+        assert!(FlacWriter::create(&path, 48000, 2, 32).is_err());
+    }
+
+    #[test]
+    fn quantises_full_scale() {
+        assert_eq!(quantise(1.0, 16), i16::MAX as i32);
+        assert_eq!(quantise(-1.0, 16), i16::MIN as i32);
+        assert_eq!(quantise(0.5, 24), 1 << 22);
+    }
+}
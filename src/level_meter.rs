@@ -0,0 +1,323 @@
+use crate::scales;
+
+/// Lowest level in dBFS shown on the meters.
+pub const METER_FLOOR: f32 = -60.0;
+
+/// Peak counted as clipping, the largest positive 16 bit sample is decoded as 32767/32768 so full scale is never reached.
+const CLIP_LEVEL: f32 = 1.0 - 1.0 / 32768.0;
+
+/// Time in seconds the peak hold is held for before falling.
+const HOLD_TIME: f32 = 1.5;
+
+/// Time in seconds without new levels after which the meter falls as if the audio were silent.
+const STALE_TIME: f32 = 0.2;
+
+/// Time constant in seconds of the RMS averaging.
+const RMS_TIME: f32 = 0.3;
+
+/// Time constant in seconds of the VU meter in both directions.
+const VU_TIME: f32 = 0.3;
+
+/// Time constant in seconds of the PPM attack.
+const PPM_ATTACK_TIME: f32 = 0.01;
+
+/// Rate in dB per second a PPM falls at.
+const PPM_RELEASE_RATE: f32 = 8.6;
+
+/// Rate in dB per second a digital peak meter falls at.
+const DIGITAL_RELEASE_RATE: f32 = 20.0;
+
+/// Peak and RMS of a channel over a period of time, both are linear where 1 is full scale.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ChannelLevel {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl ChannelLevel {
+    /// Returns the louder of two levels, used to combine levels over several periods.
+    pub fn max(self, other: ChannelLevel) -> ChannelLevel {
+        ChannelLevel { peak: self.peak.max(other.peak), rms: self.rms.max(other.rms) }
+    }
+}
+
+/// Holds all information needed to measure the level of each channel of interleaved audio.
+pub struct LevelMeter {
+    peaks: Vec<f32>,
+    sum_squares: Vec<f32>,
+    current_channel: usize,
+    frames: usize,
+}
+
+impl LevelMeter {
+    /// Create a new level meter.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the audio.
+    pub fn new(channels: u16) -> Self {
+        LevelMeter {
+            peaks: vec![0.0; channels as usize],
+            sum_squares: vec![0.0; channels as usize],
+            current_channel: 0,
+            frames: 0,
+        }
+    }
+
+    /// Processes a single interleaved sample.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample` - Is the sample of the current channel.
+    pub fn process_sample(&mut self, sample: f32) {
+        if self.peaks.is_empty() {
+            return;
+        }
+
+        self.peaks[self.current_channel] = self.peaks[self.current_channel].max(sample.abs());
+        self.sum_squares[self.current_channel] += sample * sample;
+
+        self.current_channel += 1;
+        if self.current_channel == self.peaks.len() {
+            self.current_channel = 0;
+            self.frames += 1;
+        }
+    }
+
+    /// Replaces the levels with the level of each channel since this was last called and starts measuring again.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `levels` - Is the vector the levels are written to, its memory is reused.
+    pub fn take_levels(&mut self, levels: &mut Vec<ChannelLevel>) {
+        let frames = self.frames.max(1) as f32;
+        levels.clear();
+        levels.extend(self.peaks.iter()
+            .zip(self.sum_squares.iter())
+            .map(|(peak, sum_squares)| ChannelLevel { peak: *peak, rms: (sum_squares / frames).sqrt() }));
+
+        self.peaks.iter_mut().for_each(|x| *x = 0.0);
+        self.sum_squares.iter_mut().for_each(|x| *x = 0.0);
+        self.frames = 0;
+    }
+}
+
+/// How quickly the meters respond to changes in level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ballistics {
+    /// Peaks are shown instantly and fall at a constant rate.
+    Digital,
+    /// Peaks rise almost instantly and fall slowly, as a peak programme meter.
+    Ppm,
+    /// Levels rise and fall with a 300 ms time constant, as a VU meter.
+    Vu,
+}
+
+impl Ballistics {
+    /// All ballistics in the order they are shown to the user.
+    pub const ALL: [Ballistics; 3] = [Ballistics::Digital, Ballistics::Ppm, Ballistics::Vu];
+
+    /// Returns the display name of the ballistics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ballistics::Digital => "Digital",
+            Ballistics::Ppm => "PPM",
+            Ballistics::Vu => "VU",
+        }
+    }
+}
+
+/// Holds the displayed state of a single channel's meter, all levels are in dBFS.
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelMeter {
+    pub peak: f32,
+    pub rms: f32,
+    pub hold: f32,
+    pub clipped: bool,
+    input: ChannelLevel,
+    hold_timer: f32,
+    since_update: f32,
+}
+
+impl Default for ChannelMeter {
+    fn default() -> Self {
+        ChannelMeter {
+            peak: METER_FLOOR,
+            rms: METER_FLOOR,
+            hold: METER_FLOOR,
+            clipped: false,
+            input: ChannelLevel::default(),
+            hold_timer: 0.0,
+            since_update: 0.0,
+        }
+    }
+}
+
+impl ChannelMeter {
+    /// Moves the meter towards the latest level of the channel.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `level` - Is the level measured since the last update, if any.
+    /// 
+    /// * `ballistics` - Is how quickly the meter responds.
+    /// 
+    /// * `delta_time` - Is the time in seconds since the last update.
+    pub fn update(&mut self, level: Option<ChannelLevel>, ballistics: Ballistics, delta_time: f32) {
+        // Levels arrive less often than the meter is drawn, so the last level is held until it goes stale
+        match level {
+            Some(level) => {
+                self.input = level;
+                self.since_update = 0.0;
+                if level.peak >= CLIP_LEVEL {
+                    self.clipped = true;
+                }
+            }
+            None => {
+                self.since_update += delta_time;
+                if self.since_update > STALE_TIME {
+                    self.input = ChannelLevel::default();
+                }
+            }
+        }
+
+        let peak = scales::to_decibels(self.input.peak).max(METER_FLOOR);
+        let rms = scales::to_decibels(self.input.rms).max(METER_FLOOR);
+
+        match ballistics {
+            Ballistics::Digital => {
+                self.peak = if peak > self.peak { peak } else { (self.peak - DIGITAL_RELEASE_RATE * delta_time).max(peak) };
+                self.rms = Self::approach(self.rms, rms, RMS_TIME, delta_time);
+            }
+            Ballistics::Ppm => {
+                self.peak = if peak > self.peak {
+                    Self::approach(self.peak, peak, PPM_ATTACK_TIME, delta_time)
+                } else {
+                    (self.peak - PPM_RELEASE_RATE * delta_time).max(peak)
+                };
+                self.rms = Self::approach(self.rms, rms, RMS_TIME, delta_time);
+            }
+            Ballistics::Vu => {
+                self.peak = Self::approach(self.peak, rms, VU_TIME, delta_time);
+                self.rms = self.peak;
+            }
+        }
+
+        // Hold the highest peak for a while before letting it fall
+        if self.peak >= self.hold {
+            self.hold = self.peak;
+            self.hold_timer = HOLD_TIME;
+        } else if self.hold_timer > 0.0 {
+            self.hold_timer -= delta_time;
+        } else {
+            self.hold = (self.hold - DIGITAL_RELEASE_RATE * delta_time).max(self.peak);
+        }
+    }
+
+    /// Returns a value moved towards a target with a time constant.
+    fn approach(current: f32, target: f32, time: f32, delta_time: f32) -> f32 {
+        current + (target - current) * (1.0 - (-delta_time / time).exp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    /// Updates a meter with the same level for a number of 10 ms steps.
+    fn update(meter: &mut ChannelMeter, level: ChannelLevel, ballistics: Ballistics, steps: usize) {
+        for _ in 0..steps {
+            meter.update(Some(level), ballistics, 0.01);
+        }
+    }
+
+    fn level(peak: f32, rms: f32) -> ChannelLevel {
+        ChannelLevel { peak, rms }
+    }
+
+    #[test]
+    fn measures_peak_and_rms_of_sine() {
+        let mut meter = LevelMeter::new(2);
+        for index in 0..48000 {
+            meter.process_sample(0.5 * (TAU * 1000.0 * index as f32 / 48000.0).sin());
+            meter.process_sample(0.0);
+        }
+
+        let mut levels = Vec::new();
+        meter.take_levels(&mut levels);
+        assert!((levels[0].peak - 0.5).abs() < 1e-3, "{:?}", levels[0]);
+        assert!((levels[0].rms - 0.5 / 2_f32.sqrt()).abs() < 1e-3, "{:?}", levels[0]);
+        assert_eq!((levels[1].peak, levels[1].rms), (0.0, 0.0));
+
+        // Measuring starts again once the levels are taken
+        meter.take_levels(&mut levels);
+        assert_eq!(levels[0].peak, 0.0);
+    }
+
+    #[test]
+    fn latches_clipping_just_below_full_scale() {
+        let mut meter = ChannelMeter::default();
+        meter.update(Some(level(0.999, 0.5)), Ballistics::Digital, 0.01);
+        assert!(!meter.clipped);
+
+        meter.update(Some(level(i16::MAX as f32 / 32768.0, 0.5)), Ballistics::Digital, 0.01);
+        assert!(meter.clipped);
+        update(&mut meter, level(0.0, 0.0), Ballistics::Digital, 100);
+        assert!(meter.clipped);
+    }
+
+    #[test]
+    fn peaks_release_at_their_rate() {
+        for (ballistics, rate) in [(Ballistics::Digital, DIGITAL_RELEASE_RATE), (Ballistics::Ppm, PPM_RELEASE_RATE)] {
+            let mut meter = ChannelMeter::default();
+            update(&mut meter, level(1.0, 0.5), ballistics, 100);
+            assert!(meter.peak.abs() < 0.01, "{ballistics:?} rose to {}", meter.peak);
+
+            update(&mut meter, level(0.0, 0.0), ballistics, 50);
+            assert!((meter.peak + rate * 0.5).abs() < 0.01, "{ballistics:?} fell to {}", meter.peak);
+        }
+    }
+
+    #[test]
+    fn vu_follows_rms_with_its_time_constant() {
+        let mut meter = ChannelMeter::default();
+        update(&mut meter, level(1.0, 1.0), Ballistics::Vu, 30);
+
+        // After one time constant the meter has covered all but 1/e of the way from the floor
+        let expected = METER_FLOOR / std::f32::consts::E;
+        assert!((meter.peak - expected).abs() < 0.1, "{}", meter.peak);
+        assert_eq!(meter.peak, meter.rms);
+    }
+
+    #[test]
+    fn holds_peak_before_falling() {
+        let mut meter = ChannelMeter::default();
+        update(&mut meter, level(0.5, 0.25), Ballistics::Digital, 1);
+        let held = meter.hold;
+        assert!((held - scales::to_decibels(0.5)).abs() < 0.01);
+
+        // The hold stays put for its hold time, then falls
+        update(&mut meter, level(0.0, 0.0), Ballistics::Digital, 140);
+        assert_eq!(meter.hold, held);
+        update(&mut meter, level(0.0, 0.0), Ballistics::Digital, 20);
+        assert!(meter.hold < held);
+    }
+
+    #[test]
+    fn falls_when_levels_stop() {
+        let mut meter = ChannelMeter::default();
+        update(&mut meter, level(1.0, 1.0), Ballistics::Digital, 1);
+
+        // The last level is held until it goes stale
+        for _ in 0..10 {
+            meter.update(None, Ballistics::Digital, 0.01);
+        }
+        assert_eq!(meter.peak, 0.0);
+        for _ in 0..20 {
+            meter.update(None, Ballistics::Digital, 0.01);
+        }
+        assert!(meter.peak < 0.0);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{collections::VecDeque, f64::consts::PI, fs::OpenOptions, io::Write, path::Path};
+
+/// Length in seconds of each block the loudness is accumulated over.
+const BLOCK_LENGTH: f64 = 0.1;
+
+/// Number of blocks in the 400 ms momentary window.
+const MOMENTARY_BLOCKS: usize = 4;
+
+/// Number of blocks in the 3 s short-term window.
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Absolute gate in LUFS below which blocks are ignored.
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Relative gate in LU below the ungated loudness for integrated loudness.
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+
+/// Relative gate in LU below the ungated loudness for loudness range.
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+/// Length in seconds of block history allocated up front, longer sessions grow the history as needed.
+const PREALLOCATED_LENGTH: f64 = 3600.0;
+
+/// Number of blocks between recalculating the integrated loudness and loudness range.
+const SUMMARY_INTERVAL: usize = 10;
+
+/// Factor the audio is oversampled by to find the true peak.
+const OVERSAMPLING: usize = 4;
+
+/// Number of taps of each phase of the oversampling filter.
+const TAPS_PER_PHASE: usize = 12;
+
+/// Results of loudness metering, levels are in LUFS, the range is in LU and the peak is in dBTP.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LoudnessReading {
+    pub momentary: Option<f32>,
+    pub short_term: Option<f32>,
+    pub integrated: Option<f32>,
+    pub range: Option<f32>,
+    pub true_peak: Option<f32>,
+    pub max_momentary: Option<f32>,
+    pub max_short_term: Option<f32>,
+}
+
+impl LoudnessReading {
+    /// Appends the reading as a row of a CSV file, a header is written if the file is new.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Is the path of the CSV file.
+    /// 
+    /// * `track` - Is the name of the track the reading is for.
+    pub fn export(&self, path: &Path, track: &str) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "track,integrated_lufs,loudness_range_lu,true_peak_dbtp,max_momentary_lufs,max_short_term_lufs")?;
+        }
+
+        let format = |value: Option<f32>| value.map(|value| format!("{value:.1}")).unwrap_or_default();
+        writeln!(
+            file,
+            "\"{}\",{},{},{},{},{}",
+            track.replace('"', "\"\""),
+            format(self.integrated),
+            format(self.range),
+            format(self.true_peak),
+            format(self.max_momentary),
+            format(self.max_short_term)
+        )
+    }
+}
+
+/// Biquad filter in direct form II transposed.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    /// Filters a single sample.
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// Holds the filters and accumulators for a single channel.
+#[derive(Clone)]
+struct ChannelState {
+    shelf: Biquad,
+    high_pass: Biquad,
+    weight: f64,
+    sum_squares: f64,
+    history: VecDeque<f32>,
+}
+
+/// Holds all information needed to measure loudness according to ITU-R BS.1770 and EBU R128.
+pub struct LoudnessMeter {
+    channels: Vec<ChannelState>,
+    current_channel: usize,
+    block_frames: usize,
+    frames_in_block: usize,
+    recent_blocks: VecDeque<f64>,
+    gating_blocks: Vec<f64>,
+    short_term_blocks: Vec<f64>,
+    range_loudness: Vec<f64>,
+    oversampling_filter: Vec<f32>,
+    reading: LoudnessReading,
+    true_peak: f32,
+}
+
+impl LoudnessMeter {
+    /// Create a new loudness meter.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    /// 
+    /// * `channels` - Is the number of interleaved channels in the audio.
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let (shelf, high_pass) = Self::k_weighting(sample_rate as f64);
+        let channels = (0..channels as usize).map(|channel| ChannelState {
+            shelf,
+            high_pass,
+            weight: Self::channel_weight(channel, channels as usize),
+            sum_squares: 0.0,
+            history: VecDeque::from(vec![0.0; TAPS_PER_PHASE]),
+        }).collect();
+        let preallocated_blocks = (PREALLOCATED_LENGTH / BLOCK_LENGTH) as usize;
+
+        LoudnessMeter {
+            channels,
+            current_channel: 0,
+            block_frames: ((sample_rate as f64 * BLOCK_LENGTH) as usize).max(1),
+            frames_in_block: 0,
+            recent_blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            gating_blocks: Vec::with_capacity(preallocated_blocks),
+            short_term_blocks: Vec::with_capacity(preallocated_blocks),
+            range_loudness: Vec::with_capacity(preallocated_blocks),
+            oversampling_filter: Self::oversampling_filter(),
+            reading: LoudnessReading::default(),
+            true_peak: 0.0,
+        }
+    }
+
+    /// Returns the latest reading.
+    pub fn reading(&self) -> LoudnessReading {
+        self.reading
+    }
+
+    /// Processes a single interleaved sample.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample` - Is the sample of the current channel.
+    pub fn process_sample(&mut self, sample: f32) {
+        if self.channels.is_empty() {
+            return;
+        }
+
+        let peak = self.oversampled_peak(sample);
+        self.true_peak = self.true_peak.max(peak);
+
+        let channel = &mut self.channels[self.current_channel];
+        let filtered = channel.high_pass.process(channel.shelf.process(sample as f64));
+        channel.sum_squares += filtered * filtered;
+
+        self.current_channel += 1;
+        if self.current_channel == self.channels.len() {
+            self.current_channel = 0;
+            self.frames_in_block += 1;
+            if self.frames_in_block == self.block_frames {
+                self.finish_block();
+            }
+        }
+    }
+
+    /// Completes the current 100 ms block and updates the reading.
+    fn finish_block(&mut self) {
+        let power: f64 = self.channels.iter().map(|channel| channel.weight * channel.sum_squares / self.block_frames as f64).sum();
+        self.channels.iter_mut().for_each(|channel| channel.sum_squares = 0.0);
+        self.frames_in_block = 0;
+
+        if self.recent_blocks.len() == SHORT_TERM_BLOCKS {
+            self.recent_blocks.pop_front();
+        }
+        self.recent_blocks.push_back(power);
+
+        // Momentary and short-term loudness are the mean power over their windows once they are full
+        if self.recent_blocks.len() >= MOMENTARY_BLOCKS {
+            let momentary = Self::mean_power(self.recent_blocks.iter().rev().take(MOMENTARY_BLOCKS));
+            self.gating_blocks.push(momentary);
+            self.reading.momentary = Self::loudness(momentary);
+            self.reading.max_momentary = Self::max_option(self.reading.max_momentary, self.reading.momentary);
+        }
+
+        if self.recent_blocks.len() == SHORT_TERM_BLOCKS {
+            let short_term = Self::mean_power(self.recent_blocks.iter());
+            self.short_term_blocks.push(short_term);
+            self.reading.short_term = Self::loudness(short_term);
+            self.reading.max_short_term = Self::max_option(self.reading.max_short_term, self.reading.short_term);
+        }
+
+        if self.true_peak > 0.0 {
+            self.reading.true_peak = Some(20.0 * self.true_peak.log10());
+        }
+
+        // The gated measurements cover the whole history so are only recalculated periodically
+        if self.gating_blocks.len().is_multiple_of(SUMMARY_INTERVAL) {
+            self.reading.integrated = Self::integrated(&self.gating_blocks);
+            self.reading.range = self.range();
+        }
+    }
+
+    /// Returns the integrated loudness of the gating blocks.
+    fn integrated(blocks: &[f64]) -> Option<f32> {
+        let gate = Self::gate(blocks, INTEGRATED_RELATIVE_GATE);
+        Self::loudness(Self::mean_power(blocks.iter().filter(|&&power| power > gate)))
+    }
+
+    /// Returns the loudness range, the spread between the 10th and 95th percentile of short-term loudness.
+    fn range(&mut self) -> Option<f32> {
+        let gate = Self::gate(&self.short_term_blocks, RANGE_RELATIVE_GATE);
+        self.range_loudness.clear();
+        self.range_loudness.extend(self.short_term_blocks.iter()
+            .filter(|&&power| power > gate)
+            .map(|power| -0.691 + 10.0 * power.log10()));
+
+        if self.range_loudness.is_empty() {
+            return None;
+        }
+
+        let gated = &mut self.range_loudness;
+        gated.sort_unstable_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| gated[((gated.len() - 1) as f64 * p).round() as usize];
+
+        Some((percentile(0.95) - percentile(0.10)) as f32)
+    }
+
+    /// Returns the power blocks must exceed to pass both the absolute gate and the relative gate below the mean
+    /// loudness of the blocks above the absolute gate.
+    fn gate(blocks: &[f64], relative_gate: f64) -> f64 {
+        let absolute_power = Self::power(ABSOLUTE_GATE);
+        let relative_power = Self::mean_power(blocks.iter().filter(|&&power| power > absolute_power)) * 10_f64.powf(relative_gate / 10.0);
+
+        absolute_power.max(relative_power)
+    }
+
+    /// Returns the largest interpolated absolute value between the previous sample and this one.
+    fn oversampled_peak(&mut self, sample: f32) -> f32 {
+        let history = &mut self.channels[self.current_channel].history;
+        history.pop_back();
+        history.push_front(sample);
+
+        let mut peak: f32 = 0.0;
+        for phase in 0..OVERSAMPLING {
+            let value: f32 = history.iter()
+                .enumerate()
+                .map(|(tap, x)| x * self.oversampling_filter[tap * OVERSAMPLING + phase])
+                .sum();
+            peak = peak.max(value.abs());
+        }
+
+        peak
+    }
+
+    /// Returns the coefficients of a windowed sinc filter for oversampling, each phase is normalised to unity gain.
+    fn oversampling_filter() -> Vec<f32> {
+        let length = TAPS_PER_PHASE * OVERSAMPLING;
+        let centre = (length - 1) as f64 / 2.0;
+        let mut filter: Vec<f64> = (0..length).map(|n| {
+            let x = (n as f64 - centre) / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / length as f64).cos();
+            sinc * window
+        }).collect();
+
+        for phase in 0..OVERSAMPLING {
+            let sum: f64 = (0..TAPS_PER_PHASE).map(|tap| filter[tap * OVERSAMPLING + phase]).sum();
+            (0..TAPS_PER_PHASE).for_each(|tap| filter[tap * OVERSAMPLING + phase] /= sum);
+        }
+
+        filter.into_iter().map(|x| x as f32).collect()
+    }
+
+    /// Returns the two stages of the K-weighting filter for a sample rate.
+    fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+        // High shelf modelling the acoustic effect of the head
+        let (frequency, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * frequency / sample_rate).tan();
+        let vh = 10_f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        };
+
+        // High pass removing the lowest frequencies
+        let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * frequency / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        };
+
+        (shelf, high_pass)
+    }
+
+    /// Returns the weight of a channel, surround channels are louder and the LFE channel is ignored.
+    fn channel_weight(channel: usize, channels: usize) -> f64 {
+        if channels < 6 {
+            return 1.0;
+        }
+
+        // The surrounds of 5.1, and the back and side surrounds of 7.1
+        match channel {
+            3 => 0.0,
+            4..=7 => 1.41,
+            _ => 1.0,
+        }
+    }
+
+    /// Returns the mean of a set of powers.
+    fn mean_power<'a>(powers: impl Iterator<Item = &'a f64>) -> f64 {
+        let (sum, count) = powers.fold((0.0, 0), |(sum, count), power| (sum + power, count + 1));
+        if count == 0 { 0.0 } else { sum / count as f64 }
+    }
+
+    /// Converts a power into loudness, silence has no loudness.
+    fn loudness(power: f64) -> Option<f32> {
+        if power > 0.0 {
+            Some((-0.691 + 10.0 * power.log10()) as f32)
+        } else {
+            None
+        }
+    }
+
+    /// Converts loudness into a power.
+    fn power(loudness: f64) -> f64 {
+        10_f64.powf((loudness + 0.691) / 10.0)
+    }
+
+    /// Returns the larger of two optional values.
+    fn max_option(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds a sine to every channel of a meter.
+    fn play_tone(meter: &mut LoudnessMeter, sample_rate: u32, channels: u16, frequency: f64, level: f64, seconds: f64) {
+        let amplitude = 10_f64.powf(level / 20.0);
+        for index in 0..(seconds * sample_rate as f64) as usize {
+            let sample = amplitude * (2.0 * PI * frequency * index as f64 / sample_rate as f64).sin();
+            (0..channels).for_each(|_| meter.process_sample(sample as f32));
+        }
+    }
+
+    fn assert_near(value: Option<f32>, expected: f32, tolerance: f32) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() <= tolerance, "{value} isn't {expected}");
+    }
+
+    #[test]
+    fn reads_reference_tone_at_every_rate() {
+        // A stereo 997 Hz tone at -23 dBFS is -23 LUFS, as in EBU Tech 3341
+        for sample_rate in [44100, 48000, 96000] {
+            let mut meter = LoudnessMeter::new(sample_rate, 2);
+            play_tone(&mut meter, sample_rate, 2, 997.0, -23.0, 5.0);
+
+            let reading = meter.reading();
+            assert_near(reading.integrated, -23.0, 0.1);
+            assert_near(reading.momentary, -23.0, 0.1);
+            assert_near(reading.short_term, -23.0, 0.1);
+        }
+    }
+
+    #[test]
+    fn gates_quiet_passages() {
+        let mut meter = LoudnessMeter::new(48000, 2);
+
+        // Silence falls below the absolute gate and the quiet tone below the relative gate
+        play_tone(&mut meter, 48000, 2, 997.0, -36.0, 10.0);
+        play_tone(&mut meter, 48000, 2, 997.0, -23.0, 20.0);
+        play_tone(&mut meter, 48000, 2, 997.0, -36.0, 10.0);
+        (0..48000 * 2 * 10).for_each(|_| meter.process_sample(0.0));
+
+        assert_near(meter.reading().integrated, -23.0, 0.1);
+    }
+
+    #[test]
+    fn finds_peaks_between_samples() {
+        // A quarter sample rate sine with its peaks between samples, which only reach 0.707 of the true peak
+        let mut meter = LoudnessMeter::new(48000, 1);
+        for index in 0..48000 {
+            let sample = 0.5 * (PI / 2.0 * index as f64 + PI / 4.0).sin();
+            meter.process_sample(sample as f32);
+        }
+
+        assert_near(meter.reading().true_peak, -6.02, 0.3);
+    }
+
+    #[test]
+    fn weights_surround_channels() {
+        let weights: Vec<f64> = (0..8).map(|channel| LoudnessMeter::channel_weight(channel, 8)).collect();
+        assert_eq!(weights, [1.0, 1.0, 1.0, 0.0, 1.41, 1.41, 1.41, 1.41]);
+        assert_eq!(LoudnessMeter::channel_weight(4, 2), 1.0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, path::Path, time::Instant};
 use imgui::{Key, Ui};
 use rfd::FileDialog;
 
@@ -7,19 +7,80 @@ mod common_audio_manager;
 mod file_audio_manager;
 mod app_audio_manager;
 mod fft_renderer;
+mod scales;
+mod notes;
+mod theme;
+mod settings;
+mod beat_tracker;
+mod pitch_analyser;
+mod loudness_meter;
+mod level_meter;
+mod constant_q;
+mod spectral_features;
+mod sample_ring;
+mod flac_writer;
+mod recorder;
+mod mixer;
+mod network_source;
+mod stream_source;
+mod signal_generator;
+mod equaliser;
+#[cfg(test)]
+mod allocation_counter;
 
 use fft_renderer::FftRenderer;
 use file_audio_manager::FileAudioManager;
-use app_audio_manager::AppAudioManager;
+use app_audio_manager::{AppAudioManager, CaptureSource, CaptureStatus};
+use recorder::RecordingFormat;
+use mixer::MixMode;
+use network_source::{NetworkEncoding, NetworkProtocol};
+use stream_source::StreamSettings;
+use signal_generator::{Waveform, SIGNAL_SAMPLE_RATES};
+use equaliser::{EqBand, EqPreset, FilterType, MAX_GAIN, Q_RANGE};
+use scales::{AmplitudeScale, FrequencyScale, MAX_FREQUENCY, MIN_FREQUENCY};
+use theme::{GradientMode, Theme};
+use level_meter::Ballistics;
+use common_audio_manager::{ChannelMode, SpectrumMode};
 
 /// This is number of "full" FFTs to perform per second, in order to
 /// smooth the visualisation, a windowing of 25% is used which means the
 /// number of FFTs calculated is actually 4 times this.
 const FFT_FREQUENCY: u32 = 5;
 
+/// This is the number of FFTs calculated per window, each FFT advances by
+/// this fraction of the window.
+const FFT_OVERLAP: u32 = 4;
+
+/// Colour of text reporting a problem.
+const ERROR_COLOUR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+
 fn main() {
+    // The features of a file can be exported without opening the window, such as to build a dataset
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "--export-features") {
+        let [_, song, path] = args.as_slice() else {
+            eprintln!("{}", stream_source::USAGE);
+            std::process::exit(2);
+        };
+
+        if let Err(error) = file_audio_manager::export_features(Path::new(song), Path::new(path)) {
+            eprintln!("Failed to export spectral features: {error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // A stream of raw samples can be given on the command line, such as one piped from sox or ffmpeg
+    let stream_settings = match StreamSettings::from_args(args.into_iter()) {
+        Ok(stream_settings) => stream_settings,
+        Err(error) => {
+            eprintln!("{error}\n{}", stream_source::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     // Initialise app and helpers
-    let app = application::Application::new();
+    let app = application::Application::new(stream_settings);
 
     // Run app
     app.main_loop(application_loop);
@@ -40,7 +101,356 @@ fn application_loop(_: &mut bool, ui: &mut Ui, renderer: &mut FftRenderer, file_
         let draw_list = ui.get_window_draw_list();
         let size = ui.content_region_avail();
         let origin = ui.cursor_screen_pos();
-        renderer.render(draw_list, size, origin);
+
+        // Sources mixed separately are named in the legend rather than by the channel they are on
+        let overlay_names = if app_audio_manager.is_playing() { app_audio_manager.mix_channel_names() } else { Vec::new() };
+        renderer.set_overlay_names(overlay_names);
+        renderer.render(&draw_list, size, origin, ui.io().delta_time);
+
+        // Draw the equaliser over the spectrum so its bands can be dragged
+        if renderer.show_equaliser() {
+            let mut equaliser = file_audio_manager.equaliser();
+            renderer.render_equaliser(ui, &draw_list, size, origin, &mut equaliser, file_audio_manager.equaliser_sample_rate());
+            if equaliser != file_audio_manager.equaliser() {
+                file_audio_manager.set_equaliser(equaliser);
+            }
+        }
+
+        // Show details of the spectrum under the cursor
+        if ui.is_window_hovered() && !renderer.is_editing_equaliser() {
+            renderer.render_hover(ui, &draw_list, size, origin);
+        }
+    });
+
+    // Window for editing the equaliser applied to songs and saving it as presets
+    ui.window("Equaliser").size([300.0, 300.0], imgui::Condition::FirstUseEver).build(|| {
+        let mut equaliser = file_audio_manager.equaliser();
+        ui.checkbox("Enabled", &mut equaliser.enabled);
+
+        let mut show_equaliser = renderer.show_equaliser();
+        if ui.checkbox("Edit on Spectrum", &mut show_equaliser) {
+            renderer.set_show_equaliser(show_equaliser);
+        }
+
+        // Choosing a preset replaces the bands, the preset matching the current bands is shown as selected
+        let mut presets = file_audio_manager.eq_presets().to_vec();
+        let names: Vec<&str> = presets.iter().map(|preset| preset.name.as_str()).collect();
+        let mut index = presets.iter().position(|preset| preset.bands == equaliser.bands).unwrap_or(usize::MAX);
+        if ui.combo_simple_string("Preset", &mut index, &names) {
+            equaliser.bands = presets[index].bands.clone();
+        }
+
+        // Saving replaces any preset with the same name
+        let mut name = String::new();
+        if ui.input_text("Save As", &mut name).hint("Name, then press enter").enter_returns_true(true).build()
+            && EqPreset::save(&mut presets, &name, &equaliser.bands) {
+            file_audio_manager.set_eq_presets(presets);
+        } else if index < presets.len() && ui.button("Delete Preset") {
+            presets.remove(index);
+            file_audio_manager.set_eq_presets(presets);
+        }
+
+        let filter_types: Vec<&str> = FilterType::ALL.iter().map(|filter_type| filter_type.name()).collect();
+        let mut removed = None;
+        for (index, band) in equaliser.bands.iter_mut().enumerate() {
+            let _id = ui.push_id_usize(index);
+            ui.separator();
+            ui.text(format!("Band {}", index + 1));
+
+            let mut type_index = FilterType::ALL.iter().position(|&filter_type| filter_type == band.filter_type).unwrap();
+            if ui.combo_simple_string("Type", &mut type_index, &filter_types) {
+                band.filter_type = FilterType::ALL[type_index];
+            }
+
+            ui.slider_config("Frequency (Hz)", MIN_FREQUENCY, MAX_FREQUENCY)
+                .flags(imgui::SliderFlags::LOGARITHMIC)
+                .display_format("%.0f")
+                .build(&mut band.frequency);
+
+            if band.filter_type.has_gain() {
+                ui.slider("Gain (dB)", -MAX_GAIN, MAX_GAIN, &mut band.gain);
+            }
+
+            ui.slider_config("Q", Q_RANGE.0, Q_RANGE.1)
+                .flags(imgui::SliderFlags::LOGARITHMIC)
+                .build(&mut band.q);
+
+            if ui.small_button("Remove") {
+                removed = Some(index);
+            }
+        }
+
+        if let Some(index) = removed {
+            equaliser.bands.remove(index);
+        }
+
+        ui.separator();
+        if ui.button("Add Band") {
+            equaliser.bands.push(EqBand::new(FilterType::Peaking, 1000.0));
+        }
+
+        if equaliser != file_audio_manager.equaliser() {
+            file_audio_manager.set_equaliser(equaliser);
+        }
+    });
+
+    // Side panel with a level meter for each channel, clicking it resets the clip indicators
+    ui.window("Levels").size([120.0, 300.0], imgui::Condition::FirstUseEver).build(|| {
+        let ballistics: Vec<&str> = Ballistics::ALL.iter().map(|ballistics| ballistics.name()).collect();
+        let mut index = Ballistics::ALL.iter().position(|&ballistics| ballistics == renderer.ballistics()).unwrap();
+        let width_specifier = ui.push_item_width(-1.0);
+        if ui.combo_simple_string("##ballistics", &mut index, &ballistics) {
+            renderer.set_ballistics(Ballistics::ALL[index]);
+        }
+        width_specifier.end();
+
+        let draw_list = ui.get_window_draw_list();
+        let size = ui.content_region_avail();
+        let origin = ui.cursor_screen_pos();
+        renderer.render_levels(&draw_list, size, origin, ui.io().delta_time);
+
+        if ui.is_window_hovered() && ui.is_mouse_clicked(imgui::MouseButton::Left) && ui.io().mouse_pos[1] >= origin[1] {
+            renderer.reset_clips();
+        }
+    });
+
+    // Window for metering the loudness of the audio against delivery specifications
+    ui.window("Loudness").size([300.0, 200.0], imgui::Condition::FirstUseEver).build(|| {
+        let loudness = *renderer.loudness();
+        let format = |value: Option<f32>, unit: &str| value.map(|value| format!("{value:.1} {unit}")).unwrap_or_else(|| format!("-- {unit}"));
+
+        // Bars for the windowed measurements between -60 and 0 LUFS
+        let draw_list = ui.get_window_draw_list();
+        let width = ui.content_region_avail()[0];
+        for (label, value) in [("M", loudness.momentary), ("S", loudness.short_term)] {
+            let position = ui.cursor_screen_pos();
+            let fraction = value.map(|value| ((value + 60.0) / 60.0).clamp(0.0, 1.0)).unwrap_or(0.0);
+            draw_list.add_rect(position, [position[0] + width, position[1] + 12.0], [1.0, 1.0, 1.0, 0.2]).filled(true).build();
+            draw_list.add_rect(position, [position[0] + width * fraction, position[1] + 12.0], [0.2, 0.8, 0.4, 1.0]).filled(true).build();
+            draw_list.add_text(position, [1.0, 1.0, 1.0, 1.0], label);
+            ui.dummy([width, 14.0]);
+        }
+
+        ui.text(format!("Momentary: {}", format(loudness.momentary, "LUFS")));
+        ui.text(format!("Short-term: {}", format(loudness.short_term, "LUFS")));
+        ui.text(format!("Integrated: {}", format(loudness.integrated, "LUFS")));
+        ui.text(format!("Loudness Range: {}", format(loudness.range, "LU")));
+
+        // True peak is highlighted when above the common -1 dBTP delivery limit
+        let peak = format!("True Peak: {}", format(loudness.true_peak, "dBTP"));
+        if loudness.true_peak.is_some_and(|peak| peak > -1.0) {
+            ui.text_colored([1.0, 0.3, 0.2, 1.0], peak);
+        } else {
+            ui.text(peak);
+        }
+
+        // Append the measurements of the current track to a CSV file
+        if ui.button("Export") {
+            if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).set_file_name("loudness.csv").save_file() {
+                let track = source_name(file_audio_manager, app_audio_manager);
+                let error = loudness.export(&path, &track).err().map(|error| format!("Failed to export loudness: {error}"));
+                renderer.set_loudness_export_error(error);
+            }
+        }
+
+        if let Some(error) = renderer.loudness_export_error() {
+            ui.text_colored(ERROR_COLOUR, error);
+        }
+    });
+
+    // Window for displaying the pitch classes present over time
+    ui.window("Chroma").size([400.0, 200.0], imgui::Condition::FirstUseEver).build(|| {
+        let draw_list = ui.get_window_draw_list();
+        let size = ui.content_region_avail();
+        let origin = ui.cursor_screen_pos();
+        renderer.render_chroma(&draw_list, size, origin);
+    });
+
+    // Window for adjusting how the visualisation is displayed
+    ui.window("Visualisation Settings").size([300.0, 220.0], imgui::Condition::FirstUseEver).build(|| {
+        let mut attack = renderer.attack();
+        if ui.slider("Attack (s)", 0.0, 1.0, &mut attack) {
+            renderer.set_attack(attack);
+        }
+
+        let mut release = renderer.release();
+        if ui.slider("Release (s)", 0.0, 2.0, &mut release) {
+            renderer.set_release(release);
+        }
+
+        // Delay the visualisation for outputs that add their own latency, such as Bluetooth headsets
+        let mut av_offset = renderer.av_offset();
+        if ui.slider("A/V Offset (s)", -0.5, 1.0, &mut av_offset) {
+            renderer.set_av_offset(av_offset);
+        }
+
+        // Transform used to calculate the spectrum
+        let mut options = renderer.analysis_options();
+        let spectrum_modes: Vec<&str> = SpectrumMode::ALL.iter().map(|mode| mode.name()).collect();
+        let mut index = SpectrumMode::ALL.iter().position(|&mode| mode == options.spectrum_mode).unwrap();
+        if ui.combo_simple_string("Analyser", &mut index, &spectrum_modes) {
+            options.spectrum_mode = SpectrumMode::ALL[index];
+            renderer.set_analysis_options(options);
+        }
+
+        // Channels of the source to analyse, individual channels are offered for surround sources
+        let channel_modes = ChannelMode::available(renderer.channels());
+        let channel_names: Vec<String> = channel_modes.iter().map(|mode| mode.name(renderer.channels())).collect();
+        let mut index = channel_modes.iter().position(|&mode| mode == options.channel_mode).unwrap_or(0);
+        if ui.combo_simple_string("Channels", &mut index, &channel_names) {
+            options.channel_mode = channel_modes[index];
+            renderer.set_analysis_options(options);
+        }
+
+        // Spectral features are only extracted when needed as they cost time on the analysis thread
+        if ui.checkbox("Spectral Features", &mut options.spectral_features) {
+            renderer.set_analysis_options(options);
+        }
+
+        // Scales used for the axes
+        let frequency_scales: Vec<&str> = FrequencyScale::ALL.iter().map(|scale| scale.name()).collect();
+        let mut index = FrequencyScale::ALL.iter().position(|&scale| scale == renderer.frequency_scale()).unwrap();
+        if ui.combo_simple_string("Frequency Scale", &mut index, &frequency_scales) {
+            renderer.set_frequency_scale(FrequencyScale::ALL[index]);
+        }
+
+        let amplitude_scales: Vec<&str> = AmplitudeScale::ALL.iter().map(|scale| scale.name()).collect();
+        let mut index = AmplitudeScale::ALL.iter().position(|&scale| scale == renderer.amplitude_scale()).unwrap();
+        if ui.combo_simple_string("Amplitude Scale", &mut index, &amplitude_scales) {
+            renderer.set_amplitude_scale(AmplitudeScale::ALL[index]);
+        }
+
+        if renderer.amplitude_scale() == AmplitudeScale::Decibel {
+            let mut db_floor = renderer.db_floor();
+            if ui.slider("Floor (dB)", -120.0, -20.0, &mut db_floor) {
+                renderer.set_db_floor(db_floor);
+            }
+        }
+
+        let mut show_grid = renderer.show_grid();
+        if ui.checkbox("Grid", &mut show_grid) {
+            renderer.set_show_grid(show_grid);
+        }
+
+        let mut show_axis_labels = renderer.show_axis_labels();
+        if ui.checkbox("Axis Labels", &mut show_axis_labels) {
+            renderer.set_show_axis_labels(show_axis_labels);
+        }
+    });
+
+    // Window for showing the results of analysing the audio
+    ui.window("Analysis").size([300.0, 120.0], imgui::Condition::FirstUseEver).build(|| {
+        match renderer.bpm() {
+            Some(bpm) => ui.text(format!("Tempo: {bpm:.1} BPM")),
+            None => ui.text("Tempo: --"),
+        }
+
+        // Tuner readout with a needle showing how far from the nearest note the pitch is
+        let pitch = renderer.pitch();
+        let cents = match pitch.frequency {
+            Some(frequency) => {
+                let (note, cents) = notes::nearest_note(frequency);
+                ui.text(format!("Pitch: {note} {cents:+.0} cents ({frequency:.1} Hz, {:.0}% clear)", pitch.clarity * 100.0));
+                Some(cents)
+            }
+            None => {
+                ui.text("Pitch: --");
+                None
+            }
+        };
+
+        let draw_list = ui.get_window_draw_list();
+        let position = ui.cursor_screen_pos();
+        let width = ui.content_region_avail()[0];
+        let centre = position[0] + width / 2.0;
+        draw_list.add_rect(position, [position[0] + width, position[1] + 12.0], [1.0, 1.0, 1.0, 0.3]).build();
+        draw_list.add_line([centre, position[1]], [centre, position[1] + 12.0], [1.0, 1.0, 1.0, 0.5]).build();
+        if let Some(cents) = cents {
+            let needle = centre + cents / 50.0 * width / 2.0;
+            let colour = if cents.abs() < 5.0 { [0.2, 1.0, 0.3, 1.0] } else { [1.0, 0.6, 0.1, 1.0] };
+            draw_list.add_line([needle, position[1]], [needle, position[1] + 12.0], colour).thickness(3.0).build();
+        }
+        ui.dummy([width, 16.0]);
+
+        // Lights that flash on each beat and on each onset the beat is tracked from
+        let position = ui.cursor_screen_pos();
+        let pulse = renderer.beat_pulse();
+        draw_list.add_circle([position[0] + 8.0, position[1] + 8.0], 6.0, [1.0, 0.3, 0.2, 0.2 + 0.8 * pulse]).filled(true).build();
+        let pulse = renderer.onset_pulse();
+        draw_list.add_circle([position[0] + 24.0, position[1] + 8.0], 4.0, [0.3, 0.7, 1.0, 0.2 + 0.8 * pulse]).filled(true).build();
+        ui.dummy([32.0, 16.0]);
+
+        // Spectral features, these are only available when enabled in the visualisation settings
+        if let Some(features) = renderer.features() {
+            ui.text(format!("Centroid: {:.0} Hz", features.centroid));
+            ui.text(format!("Spread: {:.0} Hz", features.spread));
+            ui.text(format!("Rolloff: {:.0} Hz", features.rolloff));
+            ui.text(format!("Flatness: {:.3}", features.flatness));
+            ui.text(format!("Flux: {:.3}", features.flux));
+            ui.text(format!("Zero Crossing Rate: {:.3}", features.zero_crossing_rate));
+            ui.text(format!("RMS: {:.3}", features.rms));
+        }
+
+        // Write the features of every frame to a CSV file until stopped
+        if renderer.is_logging_features() {
+            if ui.button("Stop Export") {
+                renderer.stop_feature_log();
+            }
+        } else if ui.button("Export Features") {
+            if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).set_file_name("features.csv").save_file() {
+                renderer.start_feature_log(&path);
+            }
+        }
+
+        if let Some(error) = renderer.feature_log_error() {
+            ui.text_colored(ERROR_COLOUR, error);
+        }
+    });
+
+    // Window for editing the colours of the visualisation
+    ui.window("Theme").size([300.0, 260.0], imgui::Condition::FirstUseEver).build(|| {
+        let mut theme = renderer.theme().clone();
+
+        // Applying a palette replaces the whole theme
+        let palettes = Theme::palettes();
+        let names: Vec<&str> = palettes.iter().map(|(name, _)| *name).collect();
+        let mut index = palettes.iter().position(|(_, palette)| *palette == theme).unwrap_or(usize::MAX);
+        if ui.combo_simple_string("Palette", &mut index, &names) {
+            theme = palettes[index].1.clone();
+        }
+
+        ui.separator();
+
+        let gradients: Vec<&str> = GradientMode::ALL.iter().map(|gradient| gradient.name()).collect();
+        let mut index = GradientMode::ALL.iter().position(|&gradient| gradient == theme.gradient).unwrap();
+        if ui.combo_simple_string("Gradient", &mut index, &gradients) {
+            theme.gradient = GradientMode::ALL[index];
+        }
+
+        if theme.gradient == GradientMode::None {
+            ui.color_edit4("Line Colour", &mut theme.line_colour);
+        } else {
+            ui.color_edit4("Gradient Start", &mut theme.gradient_start);
+            ui.color_edit4("Gradient End", &mut theme.gradient_end);
+        }
+        ui.slider("Thickness", 0.5, 8.0, &mut theme.line_thickness);
+
+        ui.checkbox("Fill", &mut theme.fill);
+        if theme.fill {
+            ui.slider("Fill Opacity", 0.0, 1.0, &mut theme.fill_opacity);
+        }
+
+        ui.checkbox("Glow", &mut theme.glow);
+        if theme.glow {
+            ui.slider("Glow Intensity", 0.0, 1.0, &mut theme.glow_intensity);
+        }
+
+        ui.checkbox("Pulse on Beat", &mut theme.pulse);
+        ui.color_edit4("Background", &mut theme.background_colour);
+
+        if theme != *renderer.theme() {
+            renderer.set_theme(theme);
+        }
     });
 
     // Window for controlling currently selected and open songs
@@ -62,26 +472,194 @@ fn application_loop(_: &mut bool, ui: &mut Ui, renderer: &mut FftRenderer, file_
         // Create dropdown of applications
         if value {
             let width_specifier = ui.push_item_width(-1.0);
-            let list_box = imgui::ListBox::new("##source_list_box");
-
-            // Add all currently opened applications and get selected application
-            let items = app_audio_manager.opened_applications();
-            let names: Vec<String> = items.iter().map(|item| item.0.clone()).collect();
-            let curr_pid = app_audio_manager.current_pid();
-            let mut index = if let Some(curr_pid) = curr_pid {
-                items.iter().enumerate().position(|(_, (_, pid))| curr_pid == *pid).unwrap()
-            } else {
-                0
-            };
 
-            // Build list box
-            fn label_function(item: &String) -> Cow<'_, str> { Cow::from(item.as_str()) }
-            imgui::ListBox::build_simple(list_box, ui, &mut index, &names, &label_function);
+            if let Some(error) = app_audio_manager.monitor_error() {
+                ui.text_colored(ERROR_COLOUR, error.to_string());
+            }
+
+            // Add the system output, all currently opened applications and input devices and get selected sources
+            let items = app_audio_manager.sources();
+            let mut selected = app_audio_manager.current_sources();
+
+            // Sources that have gone, such as an application that closed, stop being captured and the first source is
+            // captured instead if nothing is left
+            selected.retain(|source| items.iter().any(|(_, item)| item == source));
+
+            // Build list box, clicking selects a single source and ctrl-clicking adds or removes sources to mix
+            let add_to_selection = ui.io().key_ctrl;
+            imgui::ListBox::new("##source_list_box").build(ui, || {
+                for (index, (name, source)) in items.iter().enumerate() {
+                    let _id = ui.push_id_usize(index);
+                    let is_selected = selected.contains(source);
+                    if ui.selectable_config(name).selected(is_selected).build() {
+                        if !add_to_selection {
+                            selected = vec![source.clone()];
+                        } else if !is_selected {
+                            selected.push(source.clone());
+                        } else if selected.len() > 1 {
+                            selected.retain(|selected_source| selected_source != source);
+                        }
+                    }
+
+                    // Show where an application's icon comes from, which tells apart applications with the same name
+                    if let CaptureSource::Application(pid) = source {
+                        if ui.is_item_hovered() {
+                            if let Some(icon_path) = app_audio_manager.application_icon_path(*pid) {
+                                ui.tooltip_text(icon_path);
+                            }
+                        }
+                    }
+                }
+            });
 
             width_specifier.end();
 
+            // Capture the first source if nothing is selected
+            if selected.is_empty() {
+                if let Some((_, source)) = items.first() {
+                    selected.push(source.clone());
+                }
+            }
+
             // Update app audio manager if needed
-            app_audio_manager.update(items[index].clone());
+            app_audio_manager.update(&selected);
+
+            // Show how several sources are combined and the gain of each
+            if selected.len() > 1 {
+                let modes: Vec<&str> = MixMode::ALL.iter().map(|mode| mode.name()).collect();
+                let mut index = MixMode::ALL.iter().position(|&mode| mode == app_audio_manager.mix_mode()).unwrap();
+                if ui.combo_simple_string("Mix", &mut index, &modes) {
+                    app_audio_manager.set_mix_mode(MixMode::ALL[index]);
+                }
+
+                for (index, source) in selected.iter().enumerate() {
+                    let name = items.iter().find(|(_, item)| item == source).map(|(name, _)| name.as_str()).unwrap_or_default();
+                    let mut gain = app_audio_manager.source_gain(source);
+                    if ui.slider(format!("{name}##gain{index}"), 0.0, 2.0, &mut gain) {
+                        app_audio_manager.set_source_gain(source.clone(), gain);
+                    }
+                }
+            }
+
+            // Show the status of each source and the format it is captured in, along with the other formats input
+            // devices support
+            for source in selected.iter() {
+                let name = items.iter().find(|(_, item)| item == source).map(|(name, _)| name.as_str()).unwrap_or_default();
+                match app_audio_manager.capture_status(source) {
+                    Some(CaptureStatus::Retrying { error, retry_at }) => {
+                        let seconds = retry_at.saturating_duration_since(Instant::now()).as_secs_f32().ceil();
+                        ui.text_colored(ERROR_COLOUR, format!("{name}: {error}, retrying in {seconds:.0} s"));
+                    }
+                    Some(CaptureStatus::Stopped(error)) => ui.text_colored(ERROR_COLOUR, format!("{name}: {error}")),
+                    Some(CaptureStatus::Starting) => ui.text_wrapped(format!("{name}: Starting")),
+                    _ => if let Some(format) = app_audio_manager.capture_format(source) {
+                        ui.text_wrapped(format!("{name}: {}", format.name()));
+                    }
+                }
+
+                if let CaptureSource::InputDevice(id) = source {
+                    if let Some(device) = app_audio_manager.input_devices().iter().find(|device| device.id == *id) {
+                        for format in device.formats.iter() {
+                            ui.bullet_text(format.name());
+                        }
+                    }
+                }
+            }
+
+            if selected.len() > 1 {
+                if let Some((sample_rate, channels)) = app_audio_manager.mix_format() {
+                    ui.text(format!("Mixed to {sample_rate} Hz, {channels} channels"));
+                }
+            }
+
+            // Dropped samples leave gaps in the analysis, so the user knows to close other programs or use fewer sources
+            let dropped = app_audio_manager.dropped_samples();
+            if dropped > 0 {
+                ui.text_colored(ERROR_COLOUR, format!("Dropped {dropped} samples"));
+            }
+
+            if ui.button("Refresh Devices") {
+                app_audio_manager.refresh_input_devices();
+            }
+
+            // Receive audio sent from another machine, changes are applied when enter is pressed
+            if ui.collapsing_header("Network", imgui::TreeNodeFlags::empty()) {
+                let mut enabled = app_audio_manager.is_network_enabled();
+                if ui.checkbox("Listen", &mut enabled) {
+                    app_audio_manager.set_network_enabled(enabled);
+                }
+
+                let mut settings = app_audio_manager.network_settings();
+
+                // Only this machine can send to the default address, 0.0.0.0 accepts audio from any machine
+                let mut address = settings.address.to_string();
+                if ui.input_text("Address", &mut address).enter_returns_true(true).build() {
+                    if let Ok(address) = address.trim().parse() {
+                        settings.address = address;
+                    }
+                }
+
+                let mut port = settings.port as i32;
+                if ui.input_int("Port", &mut port).enter_returns_true(true).build() {
+                    settings.port = port.clamp(1, u16::MAX as i32) as u16;
+                }
+
+                let protocols: Vec<&str> = NetworkProtocol::ALL.iter().map(|protocol| protocol.name()).collect();
+                let mut index = NetworkProtocol::ALL.iter().position(|&protocol| protocol == settings.protocol).unwrap();
+                if ui.combo_simple_string("Protocol", &mut index, &protocols) {
+                    settings.protocol = NetworkProtocol::ALL[index];
+                }
+
+                let encodings: Vec<&str> = NetworkEncoding::ALL.iter().map(|encoding| encoding.name()).collect();
+                let mut index = NetworkEncoding::ALL.iter().position(|&encoding| encoding == settings.encoding).unwrap();
+                if ui.combo_simple_string("Encoding", &mut index, &encodings) {
+                    settings.encoding = NetworkEncoding::ALL[index];
+                }
+
+                let mut sample_rate = settings.sample_rate as i32;
+                if ui.input_int("Sample Rate", &mut sample_rate).enter_returns_true(true).build() {
+                    settings.sample_rate = sample_rate.clamp(8000, 384000) as u32;
+                }
+
+                let mut channels = settings.channels as i32;
+                if ui.input_int("Channels", &mut channels).enter_returns_true(true).build() {
+                    settings.channels = channels.clamp(1, 8) as u16;
+                }
+
+                if settings != app_audio_manager.network_settings() {
+                    app_audio_manager.set_network_settings(settings);
+                }
+            }
+
+            // Record the captured audio to a file until stopped
+            let recording_paths = app_audio_manager.recording_paths();
+            if !recording_paths.is_empty() {
+                for path in recording_paths.iter() {
+                    ui.text_wrapped(format!("Recording to {}", path.display()));
+                }
+                if ui.button("Stop Recording") {
+                    app_audio_manager.stop_recording();
+                }
+            } else {
+                let formats: Vec<&str> = RecordingFormat::ALL.iter().map(|format| format.name()).collect();
+                let mut index = RecordingFormat::ALL.iter().position(|&format| format == app_audio_manager.recording_format()).unwrap();
+                if ui.combo_simple_string("Format", &mut index, &formats) {
+                    app_audio_manager.set_recording_format(RecordingFormat::ALL[index]);
+                }
+
+                if ui.button("Record") {
+                    app_audio_manager.start_recording();
+                }
+            }
+
+            if let Some(error) = app_audio_manager.recording_error() {
+                ui.text_colored(ERROR_COLOUR, error);
+            }
+
+            let dropped = app_audio_manager.recording_dropped_samples();
+            if dropped > 0 {
+                ui.text_colored(ERROR_COLOUR, format!("Dropped {dropped} samples from the recording"));
+            }
         }
 
         // Only allow the user to select file audio if app audio is not playing
@@ -105,6 +683,11 @@ fn application_loop(_: &mut bool, ui: &mut Ui, renderer: &mut FftRenderer, file_
                 file_audio_manager.change_current_song(selected_item);
             }
 
+            let dropped = file_audio_manager.dropped_samples();
+            if dropped > 0 {
+                ui.text_colored(ERROR_COLOUR, format!("Dropped {dropped} samples"));
+            }
+
             // Open file dialogue for the user to select a song
             if ui.button_with_size("Select Songs", [window_size[0], 10.0]) {
                 // TODO: Deal with errors this could throw
@@ -128,6 +711,96 @@ fn application_loop(_: &mut bool, ui: &mut Ui, renderer: &mut FftRenderer, file_
                     file_audio_manager.play();
                 }
             }
+
+            // Generate a known signal to check the analysis and axes against
+            if ui.collapsing_header("Test Signal", imgui::TreeNodeFlags::empty()) {
+                if file_audio_manager.is_signal_playing() {
+                    if ui.button("Stop Signal") {
+                        file_audio_manager.clear_queue();
+                    }
+                } else if ui.button("Play Signal") {
+                    file_audio_manager.play_signal();
+                }
+
+                let mut audible = file_audio_manager.is_signal_audible();
+                if ui.checkbox("Audible", &mut audible) {
+                    file_audio_manager.set_signal_audible(audible);
+                }
+
+                let mut settings = file_audio_manager.signal_settings().clone();
+                let waveforms: Vec<&str> = Waveform::ALL.iter().map(|waveform| waveform.name()).collect();
+                let mut index = Waveform::ALL.iter().position(|&waveform| waveform == settings.waveform).unwrap();
+                if ui.combo_simple_string("Waveform", &mut index, &waveforms) {
+                    settings.waveform = Waveform::ALL[index];
+                }
+
+                match settings.waveform {
+                    Waveform::WhiteNoise | Waveform::PinkNoise => {}
+                    Waveform::MultiTone => {
+                        // Frequencies are typed as a list, such as "100, 1000, 10000"
+                        let tones: Vec<String> = settings.tones.iter().map(|tone| format!("{tone}")).collect();
+                        let mut text = tones.join(", ");
+                        if ui.input_text("Tones (Hz)", &mut text).enter_returns_true(true).build() {
+                            settings.tones = text.split(',').filter_map(|tone| tone.trim().parse().ok()).filter(|&tone: &f32| tone > 0.0).collect();
+                        }
+                    }
+                    waveform => {
+                        let label = if waveform == Waveform::Sweep { "Start (Hz)" } else { "Frequency (Hz)" };
+                        ui.slider_config(label, 1.0, 24000.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .display_format("%.1f")
+                            .build(&mut settings.frequency);
+
+                        if waveform == Waveform::Sweep {
+                            ui.slider_config("End (Hz)", 1.0, 24000.0)
+                                .flags(imgui::SliderFlags::LOGARITHMIC)
+                                .display_format("%.1f")
+                                .build(&mut settings.sweep_end);
+                            ui.slider("Sweep Time (s)", 1.0, 60.0, &mut settings.sweep_time);
+                        }
+                    }
+                }
+
+                ui.slider("Level (dBFS)", -60.0, 0.0, &mut settings.level);
+
+                let sample_rates: Vec<String> = SIGNAL_SAMPLE_RATES.iter().map(|sample_rate| format!("{sample_rate} Hz")).collect();
+                let mut index = SIGNAL_SAMPLE_RATES.iter().position(|&sample_rate| sample_rate == settings.sample_rate).unwrap();
+                if ui.combo_simple_string("Sample Rate", &mut index, &sample_rates) {
+                    settings.sample_rate = SIGNAL_SAMPLE_RATES[index];
+                }
+
+                if settings != *file_audio_manager.signal_settings() {
+                    file_audio_manager.set_signal_settings(settings);
+                }
+            }
         }
     });
-}
\ No newline at end of file
+}
+
+/// Returns the name of the source currently being visualised.
+/// 
+/// # Arguments
+/// 
+/// * `file_audio_manager` - Is the Audio Manager class that handles playing audio from files.
+/// 
+/// * `app_audio_manager` - Is the Audio Manager class that handles capturing audio from applications and devices.
+fn source_name(file_audio_manager: &FileAudioManager, app_audio_manager: &AppAudioManager) -> String {
+    if app_audio_manager.is_playing() {
+        let current = app_audio_manager.current_sources();
+        return app_audio_manager.sources()
+            .into_iter()
+            .filter(|(_, source)| current.contains(source))
+            .map(|(name, _)| name)
+            .collect::<Vec<String>>()
+            .join(" + ");
+    }
+
+    if file_audio_manager.is_signal_playing() {
+        return file_audio_manager.signal_settings().name();
+    }
+
+    file_audio_manager.opened_songs()
+        .get(file_audio_manager.selected_song_index())
+        .cloned()
+        .unwrap_or_default()
+}
@@ -0,0 +1,632 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Condvar, Mutex}, thread, time::{Duration, Instant}};
+use rustfft::FftPlanner;
+
+use crate::FFT_FREQUENCY;
+use crate::app_audio_manager::{CaptureSource, DeviceFormat};
+use crate::common_audio_manager::{AnalysisOptions, AnalysisWorker, ChannelMode, FrameSender, PlaybackClock, WorkerHandle};
+use crate::sample_ring::{self, SampleConsumer, SampleProducer};
+
+/// Time between each block of mixed samples.
+const MIX_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Time samples wait before being mixed, giving every source a chance to deliver them.
+const MIX_LATENCY: Duration = Duration::from_millis(40);
+
+/// Length in seconds of audio a source can queue before its oldest samples are dropped, this stops clock drift building up.
+const INPUT_QUEUE_LENGTH: f32 = 0.2;
+
+/// Length in seconds of audio that can wait in the ring between a capture thread and the mixer.
+const INPUT_RING_LENGTH: f32 = 0.5;
+
+/// How several captured sources are combined.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MixMode {
+    /// Sources are summed into one signal.
+    Mixed,
+    /// Each source is downmixed to its own channel so it can be shown as a separate curve.
+    Separate,
+}
+
+impl MixMode {
+    /// All mix modes in the order they are shown to the user.
+    pub const ALL: [MixMode; 2] = [MixMode::Mixed, MixMode::Separate];
+
+    /// Returns the display name of the mix mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MixMode::Mixed => "Mixed",
+            MixMode::Separate => "Separate Curves",
+        }
+    }
+}
+
+/// A captured source being mixed.
+struct MixerInput {
+    source: CaptureSource,
+    samples: SampleConsumer,
+    format: DeviceFormat,
+    incoming: Vec<f32>,
+    /// Frames at the source rate waiting to be resampled.
+    pending: Vec<f32>,
+    /// Position of the next output frame within the pending frames.
+    position: f64,
+    /// Frames at the output rate waiting to be mixed.
+    queue: VecDeque<f32>,
+    /// The recording the source's samples are written to as captured, if it is being recorded.
+    recording: Option<SampleProducer>,
+}
+
+impl MixerInput {
+    /// Reads the samples captured since the last call, records them, and resamples them to the output rate.
+    /// 
+    /// Resampling is linear, when the rates match the samples are passed through unchanged.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `output_rate` - Is the sample rate of the mixed audio.
+    fn resample(&mut self, output_rate: u32) {
+        self.samples.pop_into(&mut self.incoming);
+        self.record();
+        self.pending.extend_from_slice(&self.incoming);
+
+        let channels = self.format.channels as usize;
+        let frames = self.pending.len() / channels;
+        let step = self.format.sample_rate as f64 / output_rate as f64;
+
+        // The last frame is kept back as it is needed to interpolate towards
+        while self.position + 1.0 < frames as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            for channel in 0..channels {
+                let current = self.pending[index * channels + channel];
+                let next = self.pending[(index + 1) * channels + channel];
+                self.queue.push_back(current + (next - current) * fraction);
+            }
+            self.position += step;
+        }
+
+        // Remove the frames that have been passed
+        let consumed = (self.position as usize).min(frames);
+        self.pending.drain(..consumed * channels);
+        self.position -= consumed as f64;
+
+        // Drop the oldest frames if this source is delivering faster than it is mixed
+        let limit = (INPUT_QUEUE_LENGTH * output_rate as f32) as usize * channels;
+        if self.queue.len() > limit {
+            let excess = self.queue.len() - limit;
+            self.queue.drain(..excess);
+        }
+    }
+
+    /// Writes the samples just read to the recording, in the format they were captured in.
+    fn record(&mut self) {
+        if self.recording.as_ref().is_some_and(|recording| recording.is_abandoned()) {
+            self.recording = None;
+        }
+
+        // Samples the recording has no room for are counted by its ring and shown with the recording
+        if let Some(recording) = self.recording.as_mut() {
+            if !self.incoming.is_empty() {
+                recording.push_slice(&self.incoming);
+            }
+        }
+    }
+}
+
+/// State shared between the mixer thread and its handles.
+struct MixerState {
+    inputs: Vec<MixerInput>,
+    gains: HashMap<CaptureSource, f32>,
+    mode: MixMode,
+    output_format: Option<(u32, u16)>,
+    /// Source on each channel of the mixed audio when sources are mixed separately.
+    output_sources: Vec<CaptureSource>,
+    /// Number of samples dropped by removed sources and by the analysis worker falling behind the mix.
+    dropped_samples: usize,
+}
+
+/// Combines the audio of every captured source and passes it on for analysis, recording each source before it is mixed.
+/// 
+/// Each capture thread writes to its own lock-free ring, so a slow or stalled source never holds up the others.
+/// The mixer reads the rings on a fixed clock, padding any source that has fallen behind with silence.
+#[derive(Clone)]
+pub struct Mixer {
+    state: Arc<Mutex<MixerState>>,
+}
+
+impl Mixer {
+    /// Creates a new mixer and starts its thread.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_destination` - Is the destination to send the analysed frames for rendering.
+    /// 
+    /// * `analysis_options` - Is the options used to analyse the audio.
+    /// 
+    /// * `playing` - Is whether capture is running, the mixer waits while it isn't.
+    pub fn new(sample_destination: FrameSender, analysis_options: Arc<Mutex<AnalysisOptions>>, playing: Arc<(Mutex<bool>, Condvar)>) -> Self {
+        let state = Arc::new(Mutex::new(MixerState {
+            inputs: Vec::new(),
+            gains: HashMap::new(),
+            mode: MixMode::Mixed,
+            output_format: None,
+            output_sources: Vec::new(),
+            dropped_samples: 0,
+        }));
+
+        let mut mixer_thread = MixerThread {
+            state: state.clone(),
+            sample_destination,
+            fft_planner: FftPlanner::new(),
+            analysis_options,
+            worker_options: Arc::new(Mutex::new(AnalysisOptions::default())),
+            playing,
+            output: None,
+            worker: None,
+            playback: PlaybackClock::default(),
+            written_frames: 0,
+            output_channels: 1,
+            mixed: Vec::new(),
+            clock: None,
+        };
+
+        thread::Builder::new()
+            .name("Mixer".to_string())
+            .spawn(move || mixer_thread.run())
+            .unwrap();
+
+        Mixer { state }
+    }
+
+    /// Adds a source to the mix, returning the ring its samples should be written to.
+    /// 
+    /// The source is removed once the returned producer is dropped and its remaining samples have been mixed. A source
+    /// that is restarted is added again while the old input drains, so the newest input for a source is the one that is
+    /// used, and it takes over the recording of the old one.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source being captured.
+    /// 
+    /// * `format` - Is the format the source is captured in.
+    pub fn add_input(&self, source: CaptureSource, format: DeviceFormat) -> SampleProducer {
+        let format = DeviceFormat { channels: format.channels.max(1), ..format };
+        let capacity = (INPUT_RING_LENGTH * format.sample_rate as f32) as usize * format.channels as usize;
+        let (producer, samples) = sample_ring::sample_ring(capacity);
+
+        let mut state = self.state.lock().unwrap();
+        let recording = state.inputs.iter_mut().rev().find(|input| input.source == source).and_then(|input| input.recording.take());
+        state.inputs.push(MixerInput {
+            source,
+            samples,
+            format,
+            incoming: Vec::with_capacity(capacity),
+            pending: Vec::with_capacity(capacity),
+            position: 0.0,
+            queue: VecDeque::with_capacity(capacity),
+            recording,
+        });
+
+        producer
+    }
+
+    /// Returns every source being mixed and the format it is captured in.
+    pub fn inputs(&self) -> Vec<(CaptureSource, DeviceFormat)> {
+        let mut inputs: Vec<(CaptureSource, DeviceFormat)> = Vec::new();
+        for input in self.state.lock().unwrap().inputs.iter().rev() {
+            if !inputs.iter().any(|(source, _)| *source == input.source) {
+                inputs.push((input.source.clone(), input.format));
+            }
+        }
+
+        inputs.reverse();
+        inputs
+    }
+
+    /// Records a source from now on, replacing any recording of it. Its samples are written as captured, before mixing.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to record.
+    /// 
+    /// * `recording` - Is the producer of the recording the samples are written to.
+    pub fn record_input(&self, source: &CaptureSource, recording: SampleProducer) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(input) = state.inputs.iter_mut().rev().find(|input| input.source == *source) {
+            input.recording = Some(recording);
+        }
+    }
+
+    /// Returns the format a source is captured in, if it is being mixed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to get the format of.
+    pub fn input_format(&self, source: &CaptureSource) -> Option<DeviceFormat> {
+        self.state.lock().unwrap().inputs.iter().rev().find(|input| input.source == *source).map(|input| input.format)
+    }
+
+    /// Returns the gain applied to a source.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to get the gain of.
+    pub fn gain(&self, source: &CaptureSource) -> f32 {
+        self.state.lock().unwrap().gains.get(source).copied().unwrap_or(1.0)
+    }
+
+    /// Sets the gain applied to a source, this is kept if the source is captured again.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source` - Is the source to set the gain of.
+    /// 
+    /// * `gain` - Is the new gain where 1 leaves the source unchanged.
+    pub fn set_gain(&self, source: CaptureSource, gain: f32) {
+        self.state.lock().unwrap().gains.insert(source, gain);
+    }
+
+    /// Returns how the sources are combined.
+    pub fn mode(&self) -> MixMode {
+        self.state.lock().unwrap().mode
+    }
+
+    /// Sets how the sources are combined.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `mode` - Is the new mix mode.
+    pub fn set_mode(&self, mode: MixMode) {
+        self.state.lock().unwrap().mode = mode;
+    }
+
+    /// Returns the sample rate and number of channels of the mixed audio, if any sources are being mixed.
+    pub fn output_format(&self) -> Option<(u32, u16)> {
+        self.state.lock().unwrap().output_format
+    }
+
+    /// Returns the source on each channel of the mixed audio when sources are mixed separately, otherwise nothing.
+    pub fn output_sources(&self) -> Vec<CaptureSource> {
+        self.state.lock().unwrap().output_sources.clone()
+    }
+
+    /// Returns the number of samples dropped because the mixer or the analysis fell behind the sources.
+    pub fn dropped_samples(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.dropped_samples + state.inputs.iter().map(|input| input.samples.dropped()).sum::<usize>()
+    }
+}
+
+/// Holds everything owned by the mixer thread.
+struct MixerThread {
+    state: Arc<Mutex<MixerState>>,
+    sample_destination: FrameSender,
+    fft_planner: FftPlanner<f32>,
+    analysis_options: Arc<Mutex<AnalysisOptions>>,
+    /// Options the analysis worker uses, the user's options with the channel mode overridden for separate curves.
+    worker_options: Arc<Mutex<AnalysisOptions>>,
+    playing: Arc<(Mutex<bool>, Condvar)>,
+    /// The analysis worker the mixed audio is passed to.
+    output: Option<SampleProducer>,
+    worker: Option<WorkerHandle>,
+    /// When the audio passed to the analysis worker was heard.
+    playback: PlaybackClock,
+    /// Number of frames written to the analysis worker.
+    written_frames: u64,
+    output_channels: usize,
+    mixed: Vec<f32>,
+    /// When mixing started and how many frames have been mixed since.
+    clock: Option<(Instant, u64)>,
+}
+
+impl MixerThread {
+    /// Mixes the sources on a fixed clock for the lifetime of the app.
+    fn run(&mut self) {
+        loop {
+            self.wait_until_playing();
+            thread::sleep(MIX_INTERVAL);
+
+            let Some(captured) = self.mix() else { continue };
+
+            if let Some(output) = self.output.as_mut() {
+                if !self.mixed.is_empty() {
+                    if output.push_slice(&self.mixed) {
+                        self.playback.set(self.written_frames, captured);
+                        self.written_frames += (self.mixed.len() / self.output_channels) as u64;
+                    } else {
+                        self.state.lock().unwrap().dropped_samples += self.mixed.len();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blocks while capture is stopped, restarting the clock and discarding stale samples when it resumes.
+    fn wait_until_playing(&mut self) {
+        let (lock, cvar) = &*self.playing;
+        let mut playing = lock.lock().unwrap();
+        if *playing {
+            return;
+        }
+
+        while !*playing {
+            playing = cvar.wait(playing).unwrap();
+        }
+        drop(playing);
+
+        self.clock = None;
+        for input in self.state.lock().unwrap().inputs.iter_mut() {
+            input.queue.clear();
+        }
+    }
+
+    /// Mixes the samples due since the last call into the mixed buffer, returning when the first of them was captured.
+    /// 
+    /// Samples are mixed a fixed latency after they are captured, and captured samples are already being heard.
+    fn mix(&mut self) -> Option<Instant> {
+        self.mixed.clear();
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+
+        // Remove sources whose capture has stopped once everything they captured has been mixed, keeping their drop counts
+        let MixerState { inputs, dropped_samples, .. } = &mut *state;
+        inputs.retain(|input| {
+            let finished = input.samples.is_closed() && input.queue.is_empty();
+            if finished {
+                *dropped_samples += input.samples.dropped();
+            }
+            !finished
+        });
+        if state.inputs.is_empty() {
+            state.output_format = None;
+            state.output_sources.clear();
+            self.stop_output();
+            self.clock = None;
+            return None;
+        }
+
+        // Mix at the highest rate so no source loses detail, separate curves need a channel for every source
+        let sample_rate = state.inputs.iter().map(|input| input.format.sample_rate).max().unwrap();
+        let channels = match state.mode {
+            MixMode::Mixed => state.inputs.iter().map(|input| input.format.channels).max().unwrap(),
+            MixMode::Separate => state.inputs.len() as u16,
+        };
+
+        if state.output_format != Some((sample_rate, channels)) {
+            self.restart_output(sample_rate, channels);
+            state.output_format = Some((sample_rate, channels));
+        }
+
+        // Separate sources are only drawn as separate curves when each channel is analysed
+        let mode = state.mode;
+        let mut options = *self.analysis_options.lock().unwrap();
+        if mode == MixMode::Separate {
+            options.channel_mode = ChannelMode::Overlay;
+        }
+        *self.worker_options.lock().unwrap() = options;
+
+        let separate = mode == MixMode::Separate;
+        if !state.output_sources.iter().eq(state.inputs.iter().map(|input| &input.source).filter(|_| separate)) {
+            state.output_sources = state.inputs.iter().map(|input| input.source.clone()).filter(|_| separate).collect();
+        }
+
+        for input in state.inputs.iter_mut() {
+            input.resample(sample_rate);
+        }
+
+        // Work out how many frames are due, skipping ahead rather than catching up after a stall
+        let (start, emitted) = self.clock.get_or_insert((Instant::now(), 0));
+        let target = (start.elapsed().saturating_sub(MIX_LATENCY).as_secs_f64() * sample_rate as f64) as u64;
+        let limit = (INPUT_QUEUE_LENGTH * sample_rate as f32) as u64;
+        if target.saturating_sub(*emitted) > limit {
+            *emitted = target - limit;
+        }
+        let frames = target.saturating_sub(*emitted) as usize;
+        let captured = *start + Duration::from_secs_f64(*emitted as f64 / sample_rate as f64);
+        *emitted += frames as u64;
+
+        let MixerState { inputs, gains, .. } = &mut *state;
+        mix_block(inputs, gains, mode, frames, channels as usize, &mut self.mixed);
+
+        Some(captured)
+    }
+
+    /// Replaces the analysis worker with one for a new output format.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_rate` - Is the sample rate of the mixed audio.
+    /// 
+    /// * `channels` - Is the number of channels of the mixed audio.
+    fn restart_output(&mut self, sample_rate: u32, channels: u16) {
+        let fft = self.fft_planner.plan_fft_forward(sample_rate as usize / FFT_FREQUENCY as usize);
+
+        // Captured samples are heard as they are captured, so the clock is set from when each block was captured
+        self.playback = PlaybackClock::default();
+        self.written_frames = 0;
+        self.output_channels = channels as usize;
+        self.stop_output();
+        let (samples, worker) = AnalysisWorker::spawn(self.sample_destination.clone(), sample_rate, channels, fft, self.playback.clone(), self.worker_options.clone());
+        self.output = Some(samples);
+        self.worker = Some(worker);
+        self.clock = None;
+    }
+
+    /// Stops the analysis worker, waiting for its thread to finish.
+    fn stop_output(&mut self) {
+        self.output = None;
+        self.worker = None;
+    }
+}
+
+/// Replaces the output with a block of frames mixed from the queued samples of each input.
+/// 
+/// # Arguments
+/// 
+/// * `inputs` - Is the inputs whose queues are mixed.
+/// 
+/// * `gains` - Is the gain applied to each source.
+/// 
+/// * `mode` - Is how the inputs are combined.
+/// 
+/// * `frames` - Is the number of frames to mix.
+/// 
+/// * `output_channels` - Is the number of channels of the mixed audio.
+/// 
+/// * `output` - Is the vector the mixed samples are written to, its memory is reused.
+fn mix_block(
+    inputs: &mut [MixerInput],
+    gains: &HashMap<CaptureSource, f32>,
+    mode: MixMode,
+    frames: usize,
+    output_channels: usize,
+    output: &mut Vec<f32>
+) {
+    output.clear();
+    output.resize(frames * output_channels, 0.0);
+
+    for (index, input) in inputs.iter_mut().enumerate() {
+        let gain = gains.get(&input.source).copied().unwrap_or(1.0);
+        let input_channels = input.format.channels as usize;
+
+        // A source that has fallen behind is silent for the rest of the block
+        let available = frames.min(input.queue.len() / input_channels);
+        for (position, sample) in input.queue.drain(..available * input_channels).enumerate() {
+            let (frame, channel) = (position / input_channels, position % input_channels);
+            let sample = sample * gain;
+            let output = &mut output[frame * output_channels..(frame + 1) * output_channels];
+
+            match mode {
+                // Mono sources are heard in every channel
+                MixMode::Mixed if input_channels == 1 => output.iter_mut().for_each(|value| *value += sample),
+                MixMode::Mixed => output[channel] += sample,
+                MixMode::Separate => output[index] += sample / input_channels as f32,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_audio_manager::SampleEncoding;
+
+    /// Returns an input reading from a new ring, along with the producer of the ring.
+    fn input(sample_rate: u32, channels: u16) -> (MixerInput, SampleProducer) {
+        let (producer, samples) = sample_ring::sample_ring(4096);
+        let input = MixerInput {
+            source: CaptureSource::Stream,
+            samples,
+            format: DeviceFormat { sample_rate, channels, encoding: SampleEncoding::Float32 },
+            incoming: Vec::new(),
+            pending: Vec::new(),
+            position: 0.0,
+            queue: VecDeque::new(),
+            recording: None,
+        };
+        (input, producer)
+    }
+
+    #[test]
+    fn records_samples_before_resampling() {
+        let (mut input, mut producer) = input(24000, 1);
+        let (recording, mut recorded) = sample_ring::sample_ring(4096);
+        input.recording = Some(recording);
+
+        producer.push_slice(&[0.0, 0.5, 1.0]);
+        input.resample(48000);
+
+        // The recording gets the samples as captured while the mix gets them at its own rate
+        let mut output = Vec::new();
+        recorded.pop_into(&mut output);
+        assert_eq!(output, vec![0.0, 0.5, 1.0]);
+        assert_eq!(input.queue.iter().copied().collect::<Vec<f32>>(), vec![0.0, 0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn stops_recording_once_abandoned() {
+        let (mut input, mut producer) = input(48000, 2);
+        let (recording, recorded) = sample_ring::sample_ring(2);
+        input.recording = Some(recording);
+
+        // A full recording counts what it drops rather than holding up the mix
+        producer.push_slice(&[0.1, 0.2, 0.3, 0.4]);
+        input.resample(48000);
+        assert_eq!(recorded.dropped(), 4);
+
+        drop(recorded);
+        producer.push_slice(&[0.5, 0.6]);
+        input.resample(48000);
+        assert!(input.recording.is_none());
+    }
+
+    /// Returns an input with samples already queued for mixing.
+    fn queued(source: CaptureSource, channels: u16, samples: &[f32]) -> MixerInput {
+        let (mut input, _) = input(48000, channels);
+        input.source = source;
+        input.queue.extend(samples);
+        input
+    }
+
+    #[test]
+    fn sums_sources_with_their_gains() {
+        let mut inputs = [
+            queued(CaptureSource::Stream, 2, &[0.1, 0.2, 0.3, 0.4]),
+            queued(CaptureSource::Network, 2, &[0.5, 0.5, 0.5, 0.5]),
+        ];
+        let gains = HashMap::from([(CaptureSource::Network, 0.5)]);
+        let mut output = Vec::new();
+
+        mix_block(&mut inputs, &gains, MixMode::Mixed, 2, 2, &mut output);
+        assert_eq!(output, [0.35, 0.45, 0.55, 0.65]);
+        assert!(inputs.iter().all(|input| input.queue.is_empty()));
+    }
+
+    #[test]
+    fn plays_mono_sources_in_every_channel() {
+        let mut inputs = [
+            queued(CaptureSource::Stream, 2, &[0.1, 0.2]),
+            queued(CaptureSource::Network, 1, &[0.5, 0.25]),
+        ];
+        let mut output = Vec::new();
+
+        // The second frame of the stereo source hasn't arrived, so it is silent
+        mix_block(&mut inputs, &HashMap::new(), MixMode::Mixed, 2, 2, &mut output);
+        assert_eq!(output, [0.6, 0.7, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn puts_separate_sources_on_their_own_channels() {
+        let mut inputs = [
+            queued(CaptureSource::Stream, 2, &[0.25, 0.75, 0.5, 1.0]),
+            queued(CaptureSource::Network, 1, &[0.5, 0.25]),
+            queued(CaptureSource::SystemOutput, 1, &[1.0, 1.0]),
+        ];
+        let gains = HashMap::from([(CaptureSource::SystemOutput, 0.5)]);
+        let mut output = Vec::new();
+
+        // Each source is downmixed to the channel matching its position
+        mix_block(&mut inputs, &gains, MixMode::Separate, 2, 3, &mut output);
+        assert_eq!(output, [0.5, 0.5, 0.5, 0.75, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn uses_newest_input_of_a_restarted_source() {
+        let (sender, _receiver) = crate::common_audio_manager::frame_channel();
+        let playing = Arc::new((Mutex::new(false), Condvar::new()));
+        let mixer = Mixer::new(sender, Arc::new(Mutex::new(AnalysisOptions::default())), playing);
+        let format = |sample_rate| DeviceFormat { sample_rate, channels: 2, encoding: SampleEncoding::Float32 };
+
+        let _old = mixer.add_input(CaptureSource::Stream, format(44100));
+        let _new = mixer.add_input(CaptureSource::Stream, format(48000));
+        assert_eq!(mixer.input_format(&CaptureSource::Stream), Some(format(48000)));
+        assert_eq!(mixer.inputs(), [(CaptureSource::Stream, format(48000))]);
+
+        // A recording follows the source when it is restarted again
+        let (recording, _recorded) = sample_ring::sample_ring(16);
+        mixer.record_input(&CaptureSource::Stream, recording);
+        let _newest = mixer.add_input(CaptureSource::Stream, format(96000));
+        let state = mixer.state.lock().unwrap();
+        assert!(state.inputs.iter().map(|input| input.recording.is_some()).eq([false, false, true]));
+    }
+}
@@ -0,0 +1,634 @@
+use std::{collections::BTreeMap, io, net::{Ipv4Addr, UdpSocket}, sync::{mpsc::{Receiver, Sender, TryRecvError}, Arc, Condvar, Mutex}, time::{Duration, Instant}};
+
+use crate::app_audio_manager::{CaptureError, CaptureEvent, CaptureSource, DeviceFormat, SampleEncoding};
+use crate::mixer::Mixer;
+use crate::sample_ring::SampleProducer;
+
+/// Largest datagram that can be received.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Time the socket waits for a datagram before playing what is due and checking whether the receiver should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Length in seconds of audio held back to put packets that arrive out of order or late back in sequence.
+const JITTER_DELAY: f32 = 0.06;
+
+/// Multiple of the jitter delay the buffer can reach before the oldest packets are dropped, this stops a sender that
+/// jumps ahead building up latency.
+const MAX_JITTER_MULTIPLE: f32 = 3.0;
+
+/// Change to the rate packets are played at for each multiple of the delay the buffer is away from it.
+const RATE_ADJUSTMENT: f64 = 0.01;
+
+/// Largest change to the rate packets are played at, enough to follow a sender's clock without being heard.
+const MAX_RATE_CHANGE: f64 = 0.005;
+
+/// Fraction of the way the rate moves towards its target each time packets are played, so it changes smoothly.
+const RATE_SMOOTHING: f64 = 0.05;
+
+/// How the audio is packed into datagrams.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NetworkProtocol {
+    /// Each datagram is only samples.
+    Raw,
+    /// Each datagram is an RTP packet, so packets can be reordered and losses filled.
+    Rtp,
+}
+
+impl NetworkProtocol {
+    /// All protocols in the order they are shown to the user.
+    pub const ALL: [NetworkProtocol; 2] = [NetworkProtocol::Raw, NetworkProtocol::Rtp];
+
+    /// Returns the display name of the protocol.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkProtocol::Raw => "Raw PCM",
+            NetworkProtocol::Rtp => "RTP",
+        }
+    }
+}
+
+/// The encodings network audio can be sent in, both big endian as used by RTP.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NetworkEncoding {
+    L16,
+    L24,
+}
+
+impl NetworkEncoding {
+    /// All encodings in the order they are shown to the user.
+    pub const ALL: [NetworkEncoding; 2] = [NetworkEncoding::L16, NetworkEncoding::L24];
+
+    /// Returns the display name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkEncoding::L16 => "L16",
+            NetworkEncoding::L24 => "L24",
+        }
+    }
+
+    /// Returns the number of bytes each sample takes.
+    fn bytes(&self) -> usize {
+        match self {
+            NetworkEncoding::L16 => 2,
+            NetworkEncoding::L24 => 3,
+        }
+    }
+
+    /// Returns a sample as a float where 1 is full scale.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `bytes` - Is the big endian bytes of the sample.
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            NetworkEncoding::L16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            NetworkEncoding::L24 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) as f32 / 2147483648.0,
+        }
+    }
+}
+
+/// Where network audio is received and what format it is sent in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NetworkSettings {
+    /// Address of the interface to listen on, `0.0.0.0` listens on every interface.
+    pub address: Ipv4Addr,
+    pub port: u16,
+    pub protocol: NetworkProtocol,
+    pub encoding: NetworkEncoding,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl NetworkSettings {
+    /// Decodes whole frames of samples, ignoring any incomplete frame at the end.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `payload` - Is the encoded samples.
+    /// 
+    /// * `output` - Is where the decoded samples are added.
+    fn decode(&self, payload: &[u8], output: &mut Vec<f32>) {
+        let frame_bytes = self.encoding.bytes() * self.channels.max(1) as usize;
+        let complete = payload.len() - payload.len() % frame_bytes;
+        output.extend(payload[..complete].chunks_exact(self.encoding.bytes()).map(|bytes| self.encoding.decode(bytes)));
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        // The default port and format of an RTP stream sent by ffmpeg with pcm_s16be, only from this machine until the
+        // user chooses to listen on the network
+        NetworkSettings {
+            address: Ipv4Addr::LOCALHOST,
+            port: 5004,
+            protocol: NetworkProtocol::Rtp,
+            encoding: NetworkEncoding::L16,
+            sample_rate: 48000,
+            channels: 2,
+        }
+    }
+}
+
+/// The fields of an RTP packet needed to play it back in order.
+struct RtpPacket<'a> {
+    sequence: u16,
+    ssrc: u32,
+    payload: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    /// Parses an RTP packet, returning nothing if it isn't a valid RTP version 2 packet.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `datagram` - Is the bytes of the packet.
+    fn parse(datagram: &'a [u8]) -> Option<Self> {
+        if datagram.len() < 12 || datagram[0] >> 6 != 2 {
+            return None;
+        }
+
+        let padding = datagram[0] & 0x20 != 0;
+        let extension = datagram[0] & 0x10 != 0;
+        let contributors = (datagram[0] & 0x0F) as usize;
+        let sequence = u16::from_be_bytes([datagram[2], datagram[3]]);
+        let ssrc = u32::from_be_bytes([datagram[8], datagram[9], datagram[10], datagram[11]]);
+
+        // Skip the contributing sources and any header extension
+        let mut start = 12 + 4 * contributors;
+        if extension {
+            let header = datagram.get(start..start + 4)?;
+            start += 4 + 4 * u16::from_be_bytes([header[2], header[3]]) as usize;
+        }
+
+        let mut end = datagram.len();
+        if padding {
+            end = end.checked_sub(*datagram.last()? as usize)?;
+        }
+
+        Some(RtpPacket { sequence, ssrc, payload: datagram.get(start..end)? })
+    }
+}
+
+/// Holds packets back for a short time so they can be played in order, filling any that are lost with silence.
+/// 
+/// Packets are played on the receiver's clock rather than as they arrive. The rate they are played at is adjusted
+/// slightly to keep the buffer at its delay, so a sender whose clock runs a little fast or slow neither builds up
+/// latency nor runs the buffer dry.
+struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<f32>>,
+    /// Sequence number of the next packet to play, extended past 16 bits so it never wraps.
+    next_sequence: Option<u64>,
+    ssrc: Option<u32>,
+    /// Number of samples in the packets waiting to be played.
+    buffered_samples: usize,
+    delay_samples: usize,
+    /// Length of the last packet played, used for the silence filling a lost packet.
+    last_packet_len: usize,
+    sample_rate: u32,
+    channels: usize,
+    /// Samples of the packets taken in order, waiting to be resampled.
+    pending: Vec<f32>,
+    /// Position of the next frame played within the pending samples.
+    position: f64,
+    /// Number of frames of the stream played for each frame output.
+    rate: f64,
+    /// When playing started and how many frames have been output since, nothing while the buffer fills.
+    clock: Option<(Instant, u64)>,
+}
+
+impl JitterBuffer {
+    /// Creates an empty jitter buffer.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `settings` - Is the format of the stream.
+    fn new(settings: &NetworkSettings) -> Self {
+        let channels = settings.channels.max(1) as usize;
+        JitterBuffer {
+            packets: BTreeMap::new(),
+            next_sequence: None,
+            ssrc: None,
+            buffered_samples: 0,
+            delay_samples: ((JITTER_DELAY * settings.sample_rate as f32) as usize * channels).max(1),
+            last_packet_len: 0,
+            sample_rate: settings.sample_rate,
+            channels,
+            pending: Vec::new(),
+            position: 0.0,
+            rate: 1.0,
+            clock: None,
+        }
+    }
+
+    /// Forgets every packet, such as when the stream restarts.
+    fn clear(&mut self) {
+        self.packets.clear();
+        self.next_sequence = None;
+        self.ssrc = None;
+        self.buffered_samples = 0;
+        self.pending.clear();
+        self.position = 0.0;
+        self.rate = 1.0;
+        self.clock = None;
+    }
+
+    /// Returns the number of samples waiting to be played.
+    fn fill(&self) -> usize {
+        (self.buffered_samples + self.pending.len()).saturating_sub(self.position as usize * self.channels)
+    }
+
+    /// Adds a packet, dropping it if it arrived too late to be played.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sequence` - Is the sequence number of the packet.
+    /// 
+    /// * `ssrc` - Is the identifier of the sender, which changes when it restarts.
+    /// 
+    /// * `samples` - Is the decoded samples of the packet.
+    fn insert(&mut self, sequence: u16, ssrc: u32, samples: Vec<f32>) {
+        // A new source means the sender restarted
+        if self.ssrc != Some(ssrc) {
+            self.clear();
+            self.ssrc = Some(ssrc);
+        }
+
+        // Extend the sequence number to the value closest to the next packet expected
+        let next = *self.next_sequence.get_or_insert(sequence as u64 + (1 << 16));
+        let offset = sequence.wrapping_sub(next as u16) as i16 as i64;
+        let sequence = next as i64 + offset;
+        if sequence < next as i64 {
+            return;
+        }
+
+        self.buffered_samples += samples.len();
+        if let Some(replaced) = self.packets.insert(sequence as u64, samples) {
+            self.buffered_samples -= replaced.len();
+        }
+    }
+
+    /// Adds the samples of the next packet to the pending samples, returning false if there are no packets to take.
+    fn take_packet(&mut self) -> bool {
+        loop {
+            let (Some(next), Some(&first)) = (self.next_sequence, self.packets.keys().next()) else { return false };
+            if first == next {
+                let samples = self.packets.remove(&next).unwrap();
+                self.buffered_samples -= samples.len();
+                self.last_packet_len = samples.len();
+                self.pending.extend_from_slice(&samples);
+                self.next_sequence = Some(next + 1);
+                return true;
+            }
+
+            // The packet hasn't arrived by the time it is needed so is treated as lost, but a gap longer than the delay
+            // means the sender skipped ahead and is jumped over rather than filled
+            if (first - next) as usize * self.last_packet_len > self.delay_samples {
+                self.next_sequence = Some(first);
+                continue;
+            }
+
+            self.pending.resize(self.pending.len() + self.last_packet_len, 0.0);
+            self.next_sequence = Some(next + 1);
+            return true;
+        }
+    }
+
+    /// Plays the frames that are due by a moment into the output, in order.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `now` - Is the current time.
+    /// 
+    /// * `output` - Is where the samples are added.
+    fn pop_ready(&mut self, now: Instant, output: &mut Vec<f32>) {
+        // Drop the oldest packets if the sender has jumped far ahead
+        let max_samples = (self.delay_samples as f32 * MAX_JITTER_MULTIPLE) as usize;
+        while self.buffered_samples > max_samples {
+            let Some((sequence, samples)) = self.packets.pop_first() else { break };
+            self.buffered_samples -= samples.len();
+            self.next_sequence = Some(sequence + 1);
+        }
+
+        // Wait until the buffer has filled to its delay before playing, and again whenever it runs dry
+        let (start, mut played) = match self.clock {
+            Some(clock) => clock,
+            None if self.fill() >= self.delay_samples => (now, 0),
+            None => return,
+        };
+
+        // Play slightly faster while the buffer is above its delay and slower while it is below
+        let error = (self.fill() as f64 - self.delay_samples as f64) / self.delay_samples as f64;
+        let target = 1.0 + (error * RATE_ADJUSTMENT).clamp(-MAX_RATE_CHANGE, MAX_RATE_CHANGE);
+        self.rate += (target - self.rate) * RATE_SMOOTHING;
+
+        let due = (now.saturating_duration_since(start).as_secs_f64() * self.sample_rate as f64) as u64;
+        self.clock = Some((start, played));
+        while played < due {
+            // The last frame is kept back as it is needed to interpolate towards
+            while self.position + 1.0 >= (self.pending.len() / self.channels) as f64 {
+                if !self.take_packet() {
+                    self.clock = None;
+                    self.discard_played();
+                    return;
+                }
+            }
+
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            for channel in 0..self.channels {
+                let current = self.pending[index * self.channels + channel];
+                let next = self.pending[(index + 1) * self.channels + channel];
+                output.push(current + (next - current) * fraction);
+            }
+            self.position += self.rate;
+            played += 1;
+            self.clock = Some((start, played));
+        }
+
+        self.discard_played();
+    }
+
+    /// Removes the pending frames that have been played past.
+    fn discard_played(&mut self) {
+        let frames = self.pending.len() / self.channels;
+        let consumed = (self.position as usize).min(frames);
+        self.pending.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+    }
+}
+
+/// Returns a function converting a socket error into a capture error.
+/// 
+/// # Arguments
+/// 
+/// * `action` - Is what was being done, such as `listen on the port`.
+fn network_error(action: &'static str) -> impl FnOnce(io::Error) -> CaptureError {
+    move |error| CaptureError::Network(action, error.to_string())
+}
+
+/// Receives audio sent over UDP and passes it to the mixer.
+/// 
+/// A stream the default settings receive can be sent from the same machine with
+/// `ffmpeg -re -i song.wav -acodec pcm_s16be -ar 48000 -ac 2 -f rtp rtp://127.0.0.1:5004`, another machine can send to
+/// it once the address is set to the interface it is sent to or to `0.0.0.0`.
+pub struct NetworkReceiver {
+    settings: NetworkSettings,
+    socket: UdpSocket,
+    playing: Arc<(Mutex<bool>, Condvar)>,
+    events: Sender<CaptureEvent>,
+    samples: SampleProducer,
+    kill: Receiver<bool>,
+}
+
+impl NetworkReceiver {
+    /// Binds the socket audio is received on and adds it to the mix.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `settings` - Is the address and port to listen on and the format the audio is sent in.
+    /// 
+    /// * `mixer` - Is the mixer the audio is passed to.
+    /// 
+    /// * `playing` - Is whether capture is running, the receiver waits while it isn't.
+    /// 
+    /// * `events` - Is where the receiver reports its status.
+    /// 
+    /// * `kill` - Is used to stop the receiver.
+    pub fn new(
+        settings: NetworkSettings,
+        mixer: Mixer,
+        playing: Arc<(Mutex<bool>, Condvar)>,
+        events: Sender<CaptureEvent>,
+        kill: Receiver<bool>
+    ) -> Result<Self, CaptureError> {
+        let socket = UdpSocket::bind((settings.address, settings.port)).map_err(network_error("listen on the port"))?;
+        socket.set_read_timeout(Some(READ_TIMEOUT)).map_err(network_error("configure the socket"))?;
+
+        // The samples are decoded to float before they reach the mixer, the encoding is only reported to the user
+        let encoding = match settings.encoding {
+            NetworkEncoding::L16 => SampleEncoding::Int16,
+            NetworkEncoding::L24 => SampleEncoding::Int24,
+        };
+        let format = DeviceFormat { sample_rate: settings.sample_rate, channels: settings.channels, encoding };
+        let samples = mixer.add_input(CaptureSource::Network, format);
+
+        Ok(NetworkReceiver { settings, socket, playing, events, samples, kill })
+    }
+
+    /// Receives audio until killed or an error occurs.
+    pub fn receive_loop(&mut self) -> Result<(), CaptureError> {
+        let mut datagram = vec![0; MAX_DATAGRAM_SIZE];
+        let mut jitter_buffer = JitterBuffer::new(&self.settings);
+        let mut data = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+        // Raw datagrams have no sequence numbers, so are numbered as they arrive
+        let mut raw_sequence: u16 = 0;
+        let _ = self.events.send(CaptureEvent::Started);
+
+        loop {
+            // Wait while stopped, anything received in the meantime is stale
+            let (lock, cvar) = &*self.playing;
+            let mut playing = lock.lock().unwrap();
+            if !*playing {
+                while !*playing {
+                    playing = cvar.wait(playing).unwrap();
+                }
+                jitter_buffer.clear();
+            }
+            drop(playing);
+
+            data.clear();
+            match self.socket.recv(&mut datagram) {
+                Ok(length) => match self.settings.protocol {
+                    // Raw datagrams can't be reordered but still pass through the buffer so they are played on time
+                    NetworkProtocol::Raw => {
+                        let mut samples = Vec::with_capacity(length);
+                        self.settings.decode(&datagram[..length], &mut samples);
+                        jitter_buffer.insert(raw_sequence, 0, samples);
+                        raw_sequence = raw_sequence.wrapping_add(1);
+                    }
+                    NetworkProtocol::Rtp => if let Some(packet) = RtpPacket::parse(&datagram[..length]) {
+                        let mut samples = Vec::with_capacity(packet.payload.len());
+                        self.settings.decode(packet.payload, &mut samples);
+                        jitter_buffer.insert(packet.sequence, packet.ssrc, samples);
+                    }
+                },
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(error) => return Err(network_error("receive audio")(error)),
+            }
+
+            jitter_buffer.pop_ready(Instant::now(), &mut data);
+            if !data.is_empty() {
+                self.samples.push_slice(&data);
+            }
+
+            // Allow the main thread to kill this thread if necessary
+            match self.kill.try_recv() {
+                Ok(value) => if value { return Ok(()) },
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+    use crate::sample_ring;
+
+    /// Returns the settings of a mono 48 kHz stream.
+    fn settings(protocol: NetworkProtocol) -> NetworkSettings {
+        NetworkSettings { channels: 1, protocol, ..NetworkSettings::default() }
+    }
+
+    /// Returns the pending samples after taking every packet that can be taken.
+    fn take_all(buffer: &mut JitterBuffer) -> Vec<f32> {
+        while buffer.take_packet() {}
+        buffer.pending.clone()
+    }
+
+    /// Returns an RTP packet of L16 samples.
+    fn rtp_packet(sequence: u16, ssrc: u32, samples: &[i16]) -> Vec<u8> {
+        let mut packet = vec![0x80, 96];
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+        packet.extend(samples.iter().flat_map(|sample| sample.to_be_bytes()));
+        packet
+    }
+
+    #[test]
+    fn parses_rtp_packets() {
+        let datagram = rtp_packet(7, 42, &[1, -1]);
+        let packet = RtpPacket::parse(&datagram).unwrap();
+        assert_eq!((packet.sequence, packet.ssrc, packet.payload.len()), (7, 42, 4));
+        assert!(RtpPacket::parse(&datagram[..8]).is_none());
+    }
+
+    #[test]
+    fn reorders_packets() {
+        let mut buffer = JitterBuffer::new(&settings(NetworkProtocol::Rtp));
+        buffer.insert(10, 1, vec![0.0]);
+        buffer.insert(12, 1, vec![2.0]);
+        buffer.insert(11, 1, vec![1.0]);
+        assert_eq!(take_all(&mut buffer), vec![0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn follows_sequence_wrap() {
+        let mut buffer = JitterBuffer::new(&settings(NetworkProtocol::Rtp));
+        buffer.insert(65534, 1, vec![0.0]);
+        buffer.insert(0, 1, vec![2.0]);
+        buffer.insert(65535, 1, vec![1.0]);
+        buffer.insert(1, 1, vec![3.0]);
+        assert_eq!(take_all(&mut buffer), vec![0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn fills_lost_packets_with_silence() {
+        let mut buffer = JitterBuffer::new(&settings(NetworkProtocol::Rtp));
+        buffer.insert(0, 1, vec![0.5, 0.5]);
+        buffer.insert(2, 1, vec![0.25, 0.25]);
+        assert_eq!(take_all(&mut buffer), vec![0.5, 0.5, 0.0, 0.0, 0.25, 0.25]);
+
+        // A packet arriving after it was filled is too late to be played
+        buffer.insert(1, 1, vec![1.0, 1.0]);
+        assert!(buffer.packets.is_empty());
+    }
+
+    #[test]
+    fn restarts_when_source_changes() {
+        let mut buffer = JitterBuffer::new(&settings(NetworkProtocol::Rtp));
+        buffer.insert(100, 1, vec![0.5]);
+        buffer.insert(101, 1, vec![0.5]);
+
+        // A restarted sender starts from a new sequence number, which would otherwise be treated as late or a jump
+        buffer.insert(5, 2, vec![0.25]);
+        assert_eq!(buffer.buffered_samples, 1);
+        assert_eq!(take_all(&mut buffer), vec![0.25]);
+    }
+
+    /// Plays two minutes of packets sent by a clock running at a different speed, returning the average fill of the
+    /// buffer once it has settled and its delay.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `speed` - Is the speed of the sender's clock, where 1 is the same as the receiver's.
+    fn play_drifting(speed: f64) -> (f32, f32) {
+        let mut buffer = JitterBuffer::new(&settings(NetworkProtocol::Raw));
+        let start = Instant::now();
+        let packet_time = Duration::from_secs_f64(480.0 / 48000.0 / speed);
+        let mut output = Vec::new();
+        let mut fills = Vec::new();
+
+        for sequence in 0..12000_u32 {
+            buffer.insert(sequence as u16, 0, vec![0.5; 480]);
+            buffer.pop_ready(start + packet_time * sequence, &mut output);
+            if sequence > 6000 {
+                fills.push(buffer.fill());
+                assert!(buffer.clock.is_some(), "the buffer ran dry after {sequence} packets");
+            }
+        }
+
+        // Changing the rate leaves the audio unchanged
+        assert!(output.iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+        (fills.iter().sum::<usize>() as f32 / fills.len() as f32, buffer.delay_samples as f32)
+    }
+
+    #[test]
+    fn follows_a_slow_sender() {
+        // Without adjusting the rate the buffer would run dry every 30 seconds
+        let (fill, delay) = play_drifting(0.998);
+        assert!(fill > delay * 0.5 && fill < delay * 1.5, "average fill {fill} for a delay of {delay}");
+    }
+
+    #[test]
+    fn follows_a_fast_sender() {
+        // Without adjusting the rate the buffer would fill until packets were dropped
+        let (fill, delay) = play_drifting(1.002);
+        assert!(fill > delay * 0.5 && fill < delay * 1.5, "average fill {fill} for a delay of {delay}");
+    }
+
+    #[test]
+    fn receives_over_loopback() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        let address = socket.local_addr().unwrap();
+        let (samples, mut consumer) = sample_ring::sample_ring(48000);
+        let (events, event_receiver) = mpsc::channel();
+        let (kill, kill_receiver) = mpsc::channel();
+        let mut receiver = NetworkReceiver {
+            settings: settings(NetworkProtocol::Rtp),
+            socket,
+            playing: Arc::new((Mutex::new(true), Condvar::new())),
+            events,
+            samples,
+            kill: kill_receiver,
+        };
+        let thread = thread::spawn(move || receiver.receive_loop());
+        assert!(matches!(event_receiver.recv().unwrap(), CaptureEvent::Started));
+
+        // Ten millisecond packets, sent with the second and third swapped
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        for sequence in [0, 2, 1, 3, 4, 5, 6, 7, 8, 9] {
+            sender.send_to(&rtp_packet(sequence, 7, &[16384; 480]), address).unwrap();
+        }
+
+        // Once filled to its delay the buffer plays in real time, until the packets run out
+        let mut received = Vec::new();
+        let mut incoming = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while received.len() < 4000 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            consumer.pop_into(&mut incoming);
+            received.extend_from_slice(&incoming);
+        }
+
+        kill.send(true).unwrap();
+        assert!(thread.join().unwrap().is_ok());
+        assert!(received.len() >= 4000, "only received {} samples", received.len());
+        assert!(received.iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,47 @@
+/// Names of the notes in an octave starting from C.
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Frequency of A4 in Hz which all other notes are tuned relative to.
+const A4_FREQUENCY: f32 = 440.0;
+
+/// MIDI note number of A4.
+const A4_MIDI: f32 = 69.0;
+
+/// Returns the fractional MIDI note number of a frequency.
+/// 
+/// # Arguments
+/// 
+/// * `frequency` - Is the frequency in Hz.
+pub fn midi_note(frequency: f32) -> f32 {
+    A4_MIDI + 12.0 * (frequency / A4_FREQUENCY).log2()
+}
+
+/// Returns the frequency in Hz of a fractional MIDI note number.
+/// 
+/// # Arguments
+/// 
+/// * `note` - Is the MIDI note number.
+pub fn note_frequency(note: f32) -> f32 {
+    A4_FREQUENCY * 2_f32.powf((note - A4_MIDI) / 12.0)
+}
+
+/// Returns the name of a MIDI note including its octave, such as `A4`.
+/// 
+/// # Arguments
+/// 
+/// * `note` - Is the MIDI note number.
+pub fn note_name(note: i32) -> String {
+    format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12) - 1)
+}
+
+/// Returns the name of the nearest note to a frequency and how many cents the frequency is away from it.
+/// 
+/// # Arguments
+/// 
+/// * `frequency` - Is the frequency in Hz.
+pub fn nearest_note(frequency: f32) -> (String, f32) {
+    let note = midi_note(frequency);
+    let nearest = note.round();
+
+    (note_name(nearest as i32), (note - nearest) * 100.0)
+}
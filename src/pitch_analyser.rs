@@ -0,0 +1,194 @@
+use rustfft::num_complex::Complex;
+
+use crate::notes;
+
+/// Lowest fundamental frequency that can be detected.
+const MIN_PITCH: f32 = 50.0;
+
+/// Highest fundamental frequency that can be detected.
+const MAX_PITCH: f32 = 2000.0;
+
+/// Length in seconds of the window the difference function is integrated over.
+const YIN_WINDOW: f32 = 0.025;
+
+/// Threshold on the normalised difference below which a period is accepted, lower is stricter.
+const YIN_THRESHOLD: f32 = 0.15;
+
+/// Level below which the audio is treated as silence and no pitch is reported.
+const SILENCE_THRESHOLD: f32 = 1e-4;
+
+/// Lowest frequency included in the chromagram, below this bins are too coarse to resolve notes.
+const MIN_CHROMA_FREQUENCY: f32 = 55.0;
+
+/// Highest frequency included in the chromagram, above this harmonics blur the pitch classes.
+const MAX_CHROMA_FREQUENCY: f32 = 5000.0;
+
+/// Results of pitch analysis for a single frame.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PitchInfo {
+    /// Fundamental frequency in Hz, if the audio is pitched.
+    pub frequency: Option<f32>,
+    /// How periodic the audio is between 0 and 1.
+    pub clarity: f32,
+    /// Energy in each pitch class starting from C, normalised so the largest is 1.
+    pub chroma: [f32; 12],
+}
+
+/// Holds all information needed to estimate the pitch and harmony of audio.
+/// 
+/// The fundamental frequency is found with the YIN algorithm for monophonic sources, and a chromagram is created from
+/// the spectrum for polyphonic material.
+pub struct PitchAnalyser {
+    sample_rate: u32,
+    difference: Vec<f32>,
+}
+
+impl PitchAnalyser {
+    /// Create a new pitch analyser.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sample_rate` - Is the sample rate of the audio.
+    pub fn new(sample_rate: u32) -> Self {
+        PitchAnalyser { sample_rate, difference: Vec::new() }
+    }
+
+    /// Analyses a frame of audio.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the audio data of the frame.
+    /// 
+    /// * `spectrum` - Is the FFT data of the frame.
+    pub fn process(&mut self, samples: &[f32], spectrum: &[(Complex<f32>, f32)]) -> PitchInfo {
+        let (frequency, clarity) = match self.yin(samples) {
+            Some((frequency, clarity)) => (Some(frequency), clarity),
+            None => (None, 0.0),
+        };
+
+        PitchInfo { frequency, clarity, chroma: Self::chroma(spectrum) }
+    }
+
+    /// Estimates the fundamental frequency using YIN, returning it along with its clarity.
+    /// 
+    /// The most recent samples are used so the pitch is as current as possible.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the audio data of the frame.
+    fn yin(&mut self, samples: &[f32]) -> Option<(f32, f32)> {
+        let sample_rate = self.sample_rate as f32;
+        let window = (YIN_WINDOW * sample_rate) as usize;
+        let min_lag = (sample_rate / MAX_PITCH).floor() as usize;
+        let max_lag = (sample_rate / MIN_PITCH).ceil() as usize;
+        if samples.len() < window + max_lag + 1 {
+            return None;
+        }
+
+        let samples = &samples[samples.len() - (window + max_lag + 1)..];
+
+        // Don't try to find the pitch of silence
+        let power = samples[..window].iter().map(|x| x * x).sum::<f32>() / window as f32;
+        if power.sqrt() < SILENCE_THRESHOLD {
+            return None;
+        }
+
+        // Cumulative mean normalised difference function
+        self.difference.resize(max_lag + 2, 0.0);
+        self.difference[0] = 1.0;
+        let mut running_sum = 0.0;
+        for lag in 1..max_lag + 2 {
+            let difference: f32 = (0..window).map(|j| {
+                let delta = samples[j] - samples[j + lag];
+                delta * delta
+            }).sum();
+
+            running_sum += difference;
+            self.difference[lag] = if running_sum > 0.0 { difference * lag as f32 / running_sum } else { 1.0 };
+        }
+
+        // Take the first dip below the threshold, following it down to its minimum
+        let mut lag = (min_lag.max(2)..=max_lag).find(|&lag| self.difference[lag] < YIN_THRESHOLD)?;
+        while lag < max_lag && self.difference[lag + 1] < self.difference[lag] {
+            lag += 1;
+        }
+
+        // Refine the period between samples with a parabola through the neighbouring values
+        let (before, current, after) = (self.difference[lag - 1], self.difference[lag], self.difference[lag + 1]);
+        let denominator = before - 2.0 * current + after;
+        let offset = if denominator != 0.0 { (0.5 * (before - after) / denominator).clamp(-0.5, 0.5) } else { 0.0 };
+
+        Some((sample_rate / (lag as f32 + offset), (1.0 - current).clamp(0.0, 1.0)))
+    }
+
+    /// Returns the energy in each pitch class of the spectrum, normalised so the largest is 1.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `spectrum` - Is the FFT data of the frame.
+    fn chroma(spectrum: &[(Complex<f32>, f32)]) -> [f32; 12] {
+        let mut chroma = [0.0; 12];
+        for (amplitude, frequency) in spectrum {
+            if *frequency < MIN_CHROMA_FREQUENCY || *frequency > MAX_CHROMA_FREQUENCY {
+                continue;
+            }
+
+            let pitch_class = (notes::midi_note(*frequency).round() as i32).rem_euclid(12) as usize;
+            chroma[pitch_class] += amplitude.norm_sqr();
+        }
+
+        let largest = chroma.iter().cloned().fold(0.0, f32::max);
+        if largest > 0.0 {
+            chroma.iter_mut().for_each(|x| *x /= largest);
+        }
+
+        chroma
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..9600).map(|n| 0.5 * (TAU * frequency * n as f32 / SAMPLE_RATE as f32).sin()).collect()
+    }
+
+    #[test]
+    fn finds_pitch_of_sine() {
+        let mut analyser = PitchAnalyser::new(SAMPLE_RATE);
+        for frequency in [82.41, 220.0, 440.0, 1234.5] {
+            let (found, clarity) = analyser.yin(&sine(frequency)).unwrap();
+            let cents = 1200.0 * (found / frequency).log2();
+            assert!(cents.abs() < 5.0, "{frequency} Hz was found as {found} Hz");
+            assert!(clarity > 0.9, "{frequency} Hz has clarity {clarity}");
+        }
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        let mut analyser = PitchAnalyser::new(SAMPLE_RATE);
+        let info = analyser.process(&[0.0; 9600], &[]);
+        assert!(info.frequency.is_none());
+        assert_eq!(info.clarity, 0.0);
+
+        // Too few samples to cover the longest period
+        assert!(analyser.yin(&sine(440.0)[..100]).is_none());
+    }
+
+    #[test]
+    fn puts_a_in_pitch_class_9() {
+        // An A with its octave, a quieter E and an ignored sub-bass bin
+        let spectrum = [(1.0, 440.0), (0.5, 880.0), (0.25, 659.26), (2.0, 30.0)]
+            .map(|(level, frequency)| (Complex::new(level, 0.0), frequency));
+        let chroma = PitchAnalyser::chroma(&spectrum);
+
+        assert_eq!(chroma[9], 1.0);
+        assert!((chroma[4] - 0.0625 / 1.25).abs() < 1e-6);
+        assert!(chroma.iter().enumerate().all(|(class, energy)| class == 9 || class == 4 || *energy == 0.0));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{fs::{self, File}, io::{self, BufWriter}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}, thread::{self, JoinHandle}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::app_audio_manager::{DeviceFormat, SampleEncoding};
+use crate::flac_writer::{self, FlacWriter};
+use crate::sample_ring::{self, SampleConsumer, SampleProducer};
+
+/// Directory recordings are saved in, next to the settings file.
+const RECORDING_DIRECTORY: &str = "recordings";
+
+/// Length in seconds of audio that can wait to be written before samples are dropped.
+const RECORDING_BUFFER_LENGTH: f32 = 5.0;
+
+/// Time the writer sleeps for when there are no samples waiting.
+const WRITER_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The file formats audio can be recorded to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecordingFormat {
+    /// Uncompressed WAV in the encoding each source is captured in.
+    Wav,
+    /// Lossless compressed FLAC, at 16 bits for 16 bit sources and 24 bits for the rest.
+    Flac,
+}
+
+impl RecordingFormat {
+    /// All recording formats in the order they are shown to the user.
+    pub const ALL: [RecordingFormat; 2] = [RecordingFormat::Wav, RecordingFormat::Flac];
+
+    /// Returns the display name of the recording format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "WAV",
+            RecordingFormat::Flac => "FLAC",
+        }
+    }
+
+    /// Returns the file extension of the recording format.
+    fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::Flac => "flac",
+        }
+    }
+}
+
+/// The writer for each recording format.
+enum SampleWriter {
+    /// A WAV file and the encoding its samples are written in.
+    Wav(hound::WavWriter<BufWriter<File>>, SampleEncoding),
+    Flac(FlacWriter),
+}
+
+impl SampleWriter {
+    /// Creates the file for a recording.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Is the path of the file.
+    /// 
+    /// * `format` - Is the file format to write.
+    /// 
+    /// * `source_format` - Is the format the source is captured in, which the file keeps.
+    fn create(path: &Path, format: RecordingFormat, source_format: DeviceFormat) -> io::Result<Self> {
+        let DeviceFormat { sample_rate, channels, encoding } = source_format;
+        match format {
+            RecordingFormat::Wav => {
+                let sample_format = if encoding == SampleEncoding::Float32 { hound::SampleFormat::Float } else { hound::SampleFormat::Int };
+                let spec = hound::WavSpec { channels, sample_rate, bits_per_sample: encoding.bits() as u16, sample_format };
+                hound::WavWriter::create(path, spec).map(|writer| SampleWriter::Wav(writer, encoding)).map_err(io::Error::other)
+            }
+            RecordingFormat::Flac => {
+                // FLAC can't store floats or more than 24 bits, so those sources are kept at the most it can
+                let bits_per_sample = if encoding == SampleEncoding::Int16 { 16 } else { 24 };
+                FlacWriter::create(path, sample_rate, channels, bits_per_sample).map(SampleWriter::Flac)
+            }
+        }
+    }
+
+    /// Writes a single interleaved sample.
+    fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        match self {
+            SampleWriter::Wav(writer, SampleEncoding::Float32) => writer.write_sample(sample),
+            SampleWriter::Wav(writer, SampleEncoding::Int16) => writer.write_sample(flac_writer::quantise(sample, 16) as i16),
+            SampleWriter::Wav(writer, encoding) => writer.write_sample(flac_writer::quantise(sample, encoding.bits())),
+            SampleWriter::Flac(writer) => return writer.write_sample(sample),
+        }.map_err(io::Error::other)
+    }
+
+    /// Completes the file.
+    fn finalize(self) -> io::Result<()> {
+        match self {
+            SampleWriter::Wav(writer, _) => writer.finalize().map_err(io::Error::other),
+            SampleWriter::Flac(writer) => writer.finalize(),
+        }
+    }
+}
+
+/// A recording of a single source in progress, which stops and completes its file when dropped.
+/// 
+/// Samples are passed through a lock-free ring to a writer thread, so writing them never waits on the disk.
+pub struct Recording {
+    stop: Arc<AtomicBool>,
+    /// Number of samples dropped because the writer fell behind, updated by the writer.
+    dropped_samples: Arc<AtomicUsize>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    path: PathBuf,
+}
+
+impl Recording {
+    /// Starts recording a source to a new file named with the current time, returning the recording and the producer
+    /// its interleaved samples are written to.
+    /// 
+    /// The file keeps the format the source is captured in, and is completed when either the recording or the producer
+    /// is dropped.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `format` - Is the file format to record to.
+    /// 
+    /// * `source_format` - Is the format the source is captured in.
+    /// 
+    /// * `source_name` - Is the name the file is given after the time, such as `system-output`.
+    pub fn start(format: RecordingFormat, source_format: DeviceFormat, source_name: &str) -> io::Result<(Self, SampleProducer)> {
+        fs::create_dir_all(RECORDING_DIRECTORY)?;
+        let name = format!("{}-{source_name}.{}", timestamp_name(SystemTime::now()), format.extension());
+        let path = Path::new(RECORDING_DIRECTORY).join(name);
+
+        // The file is created here so any problem with it is reported straight away
+        let writer = SampleWriter::create(&path, format, source_format)?;
+        let capacity = (RECORDING_BUFFER_LENGTH * source_format.sample_rate as f32) as usize * source_format.channels as usize;
+        let (samples, consumer) = sample_ring::sample_ring(capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+        let dropped_samples = Arc::new(AtomicUsize::new(0));
+        let (writer_stop, writer_dropped) = (stop.clone(), dropped_samples.clone());
+
+        let writer = thread::Builder::new()
+            .name("Recorder".to_string())
+            .spawn(move || Self::write_loop(consumer, writer, capacity, &writer_stop, &writer_dropped))?;
+
+        Ok((Recording { stop, dropped_samples, writer: Some(writer), path }, samples))
+    }
+
+    /// Returns the path of the file being recorded to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of samples that are missing from the file because the writer fell behind.
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the file has been completed or failed to write, without the recording being stopped.
+    /// 
+    /// A recording finishes by itself once its source stops being captured.
+    pub fn is_finished(&self) -> bool {
+        self.writer.as_ref().is_none_or(|writer| writer.is_finished())
+    }
+
+    /// Stops the recording and waits for the file to be completed, returning any error writing it.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.writer.take().map(|writer| writer.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("the writer stopped unexpectedly")),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes samples as they arrive until the recording or its producer is dropped, then completes the file.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the ring the samples are read from.
+    /// 
+    /// * `writer` - Is the writer of the file.
+    /// 
+    /// * `capacity` - Is the number of samples the ring can hold.
+    /// 
+    /// * `stop` - Is set when the recording is stopped.
+    /// 
+    /// * `dropped_samples` - Is updated with the number of samples the ring has dropped.
+    fn write_loop(
+        mut samples: SampleConsumer,
+        mut writer: SampleWriter,
+        capacity: usize,
+        stop: &AtomicBool,
+        dropped_samples: &AtomicUsize
+    ) -> io::Result<()> {
+        let mut incoming = Vec::with_capacity(capacity);
+        loop {
+            // Check before reading so samples pushed just before the recording was stopped are still written
+            let closed = samples.is_closed() || stop.load(Ordering::Relaxed);
+            dropped_samples.store(samples.dropped(), Ordering::Relaxed);
+            if samples.pop_into(&mut incoming) == 0 {
+                if closed {
+                    return writer.finalize();
+                }
+
+                thread::sleep(WRITER_POLL_INTERVAL);
+                continue;
+            }
+
+            for &sample in incoming.iter() {
+                writer.write_sample(sample)?;
+            }
+        }
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        // The writer finishes once it has written what is waiting, waiting for it ensures the file is complete before exiting
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Returns a file name for a moment in UTC, such as `musualiser-2024-03-09-142501`.
+/// 
+/// # Arguments
+/// 
+/// * `time` - Is the moment to name.
+fn timestamp_name(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (days, seconds_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Convert days since the epoch to a civil date
+    let era_days = days + 719468;
+    let era = era_days.div_euclid(146097);
+    let day_of_era = era_days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "musualiser-{year:04}-{month:02}-{day:02}-{:02}{:02}{:02}",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes samples with a sample writer, returning the path of the file.
+    fn write(name: &str, format: RecordingFormat, encoding: SampleEncoding, samples: &[f32]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("musualiser-{}-{name}.{}", std::process::id(), format.extension()));
+        let mut writer = SampleWriter::create(&path, format, DeviceFormat { sample_rate: 44100, channels: 2, encoding }).unwrap();
+        for &sample in samples.iter() {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn wav_keeps_source_encoding() {
+        let samples = [0.5, -0.25, 1.0, -1.0];
+        for (encoding, sample_format) in [
+            (SampleEncoding::Int16, hound::SampleFormat::Int),
+            (SampleEncoding::Int24, hound::SampleFormat::Int),
+            (SampleEncoding::Int32, hound::SampleFormat::Int),
+            (SampleEncoding::Float32, hound::SampleFormat::Float),
+        ] {
+            let path = write(encoding.name(), RecordingFormat::Wav, encoding, &samples);
+            let mut reader = hound::WavReader::open(&path).unwrap();
+            let spec = reader.spec();
+            assert_eq!((spec.sample_rate, spec.channels), (44100, 2));
+            assert_eq!(spec.bits_per_sample as u32, encoding.bits());
+            assert_eq!(spec.sample_format, sample_format);
+
+            // Decoding the written samples as they were captured gives back the same values
+            let decoded: Vec<f32> = match sample_format {
+                hound::SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+                hound::SampleFormat::Int => {
+                    let scale = (1_i64 << (encoding.bits() - 1)) as f32;
+                    reader.samples::<i32>().map(|sample| sample.unwrap() as f32 / scale).collect()
+                }
+            };
+            assert!(decoded.iter().zip(samples.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn flac_keeps_16_bit_sources() {
+        let path = write("flac16", RecordingFormat::Flac, SampleEncoding::Int16, &[0.5, -0.5]);
+        let reader = claxon::FlacReader::open(&path).unwrap();
+        assert_eq!(reader.streaminfo().bits_per_sample, 16);
+        fs::remove_file(&path).unwrap();
+
+        let path = write("flac32", RecordingFormat::Flac, SampleEncoding::Float32, &[0.5, -0.5]);
+        let reader = claxon::FlacReader::open(&path).unwrap();
+        assert_eq!(reader.streaminfo().bits_per_sample, 24);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn names_files_with_the_time() {
+        // 2024-03-09 14:25:01 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1709994301);
+        assert_eq!(timestamp_name(time), "musualiser-2024-03-09-142501");
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}, Arc};
+
+/// State shared between the two halves of a sample ring.
+struct Shared {
+    buffer: Box<[AtomicU32]>,
+    written: AtomicUsize,
+    read: AtomicUsize,
+    closed: AtomicBool,
+    /// Whether the consumer has been dropped, so nothing written will be read.
+    abandoned: AtomicBool,
+    /// Number of samples the producer couldn't write because the ring was full.
+    dropped: AtomicUsize,
+}
+
+/// Creates a lock-free ring buffer of samples with a single producer and a single consumer.
+/// 
+/// Samples are stored as their bits in atomics so neither side ever blocks or needs unsafe code.
+/// 
+/// # Arguments
+/// 
+/// * `capacity` - Is the number of samples the ring can hold.
+pub fn sample_ring(capacity: usize) -> (SampleProducer, SampleConsumer) {
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+        abandoned: AtomicBool::new(false),
+        dropped: AtomicUsize::new(0),
+    });
+
+    (SampleProducer { shared: shared.clone() }, SampleConsumer { shared })
+}
+
+/// The writing half of a sample ring, the consumer is told the ring is closed when this is dropped.
+pub struct SampleProducer {
+    shared: Arc<Shared>,
+}
+
+impl SampleProducer {
+    /// Writes all of the samples to the ring, or none of them if there isn't room, returning whether they were written.
+    /// 
+    /// Samples that aren't written are counted, so the consumer can report that the producer overran it.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the samples to write.
+    pub fn push_slice(&mut self, samples: &[f32]) -> bool {
+        let capacity = self.shared.buffer.len();
+        let written = self.shared.written.load(Ordering::Relaxed);
+        let read = self.shared.read.load(Ordering::Acquire);
+        if capacity - written.wrapping_sub(read) < samples.len() {
+            self.shared.dropped.fetch_add(samples.len(), Ordering::Relaxed);
+            return false;
+        }
+
+        for (i, sample) in samples.iter().enumerate() {
+            self.shared.buffer[written.wrapping_add(i) % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        }
+
+        // Publish the samples only once they have all been stored
+        self.shared.written.store(written.wrapping_add(samples.len()), Ordering::Release);
+        true
+    }
+
+    /// Returns whether the consumer has been dropped, so the producer can stop writing.
+    pub fn is_abandoned(&self) -> bool {
+        self.shared.abandoned.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for SampleProducer {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+/// The reading half of a sample ring, the producer is told the ring is abandoned when this is dropped.
+pub struct SampleConsumer {
+    shared: Arc<Shared>,
+}
+
+impl Drop for SampleConsumer {
+    fn drop(&mut self) {
+        self.shared.abandoned.store(true, Ordering::Release);
+    }
+}
+
+impl SampleConsumer {
+    /// Replaces the contents of the output with every sample waiting in the ring, returning how many there were.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `output` - Is the vector the samples are read into, its memory is reused.
+    pub fn pop_into(&mut self, output: &mut Vec<f32>) -> usize {
+        let capacity = self.shared.buffer.len();
+        let written = self.shared.written.load(Ordering::Acquire);
+        let read = self.shared.read.load(Ordering::Relaxed);
+        let available = written.wrapping_sub(read);
+
+        output.clear();
+        output.extend((0..available).map(|i| f32::from_bits(self.shared.buffer[read.wrapping_add(i) % capacity].load(Ordering::Relaxed))));
+
+        // Free the space only once the samples have been copied out
+        self.shared.read.store(written, Ordering::Release);
+        available
+    }
+
+    /// Returns whether the producer has been dropped, samples written before it was may still be waiting.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of samples the producer has dropped because the ring was full.
+    pub fn dropped(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = sample_ring(4);
+        let mut output = Vec::new();
+
+        assert!(producer.push_slice(&[1.0, 2.0, 3.0]));
+        assert_eq!(consumer.pop_into(&mut output), 3);
+        assert!(producer.push_slice(&[4.0, 5.0, 6.0]));
+        assert_eq!(consumer.pop_into(&mut output), 3);
+        assert_eq!(output, vec![4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn counts_dropped_samples() {
+        let (mut producer, mut consumer) = sample_ring(4);
+        let mut output = Vec::new();
+
+        // A write that doesn't fit is dropped whole so frames are never split
+        assert!(producer.push_slice(&[1.0, 2.0]));
+        assert!(!producer.push_slice(&[3.0, 4.0, 5.0]));
+        assert_eq!(consumer.dropped(), 3);
+        assert_eq!(consumer.pop_into(&mut output), 2);
+        assert_eq!(output, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn closes_when_producer_dropped() {
+        let (mut producer, mut consumer) = sample_ring(4);
+        let mut output = Vec::new();
+        producer.push_slice(&[1.0]);
+        drop(producer);
+
+        // Samples written before closing can still be read
+        assert!(consumer.is_closed());
+        assert_eq!(consumer.pop_into(&mut output), 1);
+    }
+
+    #[test]
+    fn abandons_when_consumer_dropped() {
+        let (producer, consumer) = sample_ring(4);
+        assert!(!producer.is_abandoned());
+        drop(consumer);
+        assert!(producer.is_abandoned());
+    }
+}
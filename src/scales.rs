@@ -0,0 +1,123 @@
+/// Lowest frequency shown by the visualisation.
+pub const MIN_FREQUENCY: f32 = 20.0;
+
+/// Highest frequency shown by the visualisation.
+pub const MAX_FREQUENCY: f32 = 20000.0;
+
+/// The scales that frequencies can be mapped onto the x axis with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrequencyScale {
+    Linear,
+    Logarithmic,
+    Mel,
+    Bark,
+    Erb,
+}
+
+impl FrequencyScale {
+    /// All frequency scales in the order they are shown to the user.
+    pub const ALL: [FrequencyScale; 5] = [
+        FrequencyScale::Linear,
+        FrequencyScale::Logarithmic,
+        FrequencyScale::Mel,
+        FrequencyScale::Bark,
+        FrequencyScale::Erb,
+    ];
+
+    /// Returns the display name of the scale.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrequencyScale::Linear => "Linear",
+            FrequencyScale::Logarithmic => "Logarithmic",
+            FrequencyScale::Mel => "Mel",
+            FrequencyScale::Bark => "Bark",
+            FrequencyScale::Erb => "ERB",
+        }
+    }
+
+    /// Returns the position between 0 and 1 of a frequency between the minimum and maximum frequency.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frequency` - Is the frequency in Hz.
+    pub fn position(&self, frequency: f32) -> f32 {
+        let min = self.warp(MIN_FREQUENCY);
+        let max = self.warp(MAX_FREQUENCY);
+
+        (self.warp(frequency.max(MIN_FREQUENCY)) - min) / (max - min)
+    }
+
+    /// Returns the frequency at a position between 0 and 1, this is the inverse of `position`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `position` - Is the position along the axis.
+    pub fn frequency(&self, position: f32) -> f32 {
+        let min = self.warp(MIN_FREQUENCY);
+        let max = self.warp(MAX_FREQUENCY);
+
+        self.unwarp(min + position * (max - min))
+    }
+
+    /// Converts a frequency in Hz into the units of this scale.
+    fn warp(&self, frequency: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => frequency,
+            FrequencyScale::Logarithmic => frequency.ln(),
+            FrequencyScale::Mel => 2595.0 * (1.0 + frequency / 700.0).log10(),
+            FrequencyScale::Bark => 26.81 * frequency / (1960.0 + frequency) - 0.53,
+            FrequencyScale::Erb => 21.4 * (1.0 + 0.00437 * frequency).log10(),
+        }
+    }
+
+    /// Converts a value in the units of this scale into Hz.
+    fn unwarp(&self, value: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => value,
+            FrequencyScale::Logarithmic => value.exp(),
+            FrequencyScale::Mel => 700.0 * (10_f32.powf(value / 2595.0) - 1.0),
+            FrequencyScale::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+            FrequencyScale::Erb => (10_f32.powf(value / 21.4) - 1.0) / 0.00437,
+        }
+    }
+}
+
+/// The scales that magnitudes can be mapped onto the y axis with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AmplitudeScale {
+    /// Magnitudes are normalised against the loudest point of each frame.
+    Normalised,
+    /// Magnitudes are shown in decibels relative to full scale.
+    Decibel,
+}
+
+impl AmplitudeScale {
+    /// All amplitude scales in the order they are shown to the user.
+    pub const ALL: [AmplitudeScale; 2] = [AmplitudeScale::Normalised, AmplitudeScale::Decibel];
+
+    /// Returns the display name of the scale.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AmplitudeScale::Normalised => "Normalised",
+            AmplitudeScale::Decibel => "Decibel",
+        }
+    }
+}
+
+/// Converts a linear magnitude into decibels, silence is clamped to avoid negative infinity.
+/// 
+/// # Arguments
+/// 
+/// * `magnitude` - Is the linear magnitude where 1 is full scale.
+pub fn to_decibels(magnitude: f32) -> f32 {
+    20.0 * magnitude.max(1e-10).log10()
+}
+
+/// Converts decibels into a linear magnitude.
+/// 
+/// # Arguments
+/// 
+/// * `decibels` - Is the level in decibels where 0 is full scale.
+pub fn from_decibels(decibels: f32) -> f32 {
+    10_f32.powf(decibels / 20.0)
+}
@@ -0,0 +1,63 @@
+use std::{fs, io::ErrorKind, path::Path};
+use serde::{Deserialize, Serialize};
+
+use crate::equaliser::{EqPreset, Equaliser};
+use crate::theme::Theme;
+
+/// File the settings are stored in, relative to the working directory.
+const SETTINGS_PATH: &str = "musualiser.json";
+
+/// Holds all user settings that persist between runs.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub theme: Theme,
+    pub equaliser: Equaliser,
+    pub eq_presets: Vec<EqPreset>,
+}
+
+impl Settings {
+    /// Loads the settings from disk, the defaults are used if none have been saved yet.
+    /// 
+    /// A file that exists but can't be read or parsed is an error, so the caller knows not to save over it.
+    pub fn load() -> Result<Self, String> {
+        match fs::read_to_string(Path::new(SETTINGS_PATH)) {
+            Ok(contents) => Self::parse(&contents),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Settings::default()),
+            Err(error) => Err(format!("Failed to read {SETTINGS_PATH}: {error}")),
+        }
+    }
+
+    /// Parses saved settings, anything missing is given its default.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `contents` - Is the JSON the settings were saved as.
+    fn parse(contents: &str) -> Result<Self, String> {
+        serde_json::from_str(contents).map_err(|error| format!("Failed to load {SETTINGS_PATH}: {error}"))
+    }
+
+    /// Saves the settings to disk.
+    pub fn save(&self) -> std::io::Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(Path::new(SETTINGS_PATH), contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_missing_settings_with_defaults() {
+        let settings = Settings::parse(r#"{ "eq_presets": [{ "name": "Flat", "bands": [] }] }"#).unwrap();
+        assert_eq!(settings.eq_presets.len(), 1);
+        assert_eq!(settings.equaliser, Equaliser::default());
+    }
+
+    #[test]
+    fn rejects_malformed_settings() {
+        let error = Settings::parse(r#"{ "eq_presets": [{ "name": "Flat" "#).unwrap_err();
+        assert!(error.contains(SETTINGS_PATH), "{error}");
+    }
+}
@@ -0,0 +1,285 @@
+use std::{f32::consts::TAU, sync::{Arc, Mutex}, time::Duration};
+use rodio::Source;
+
+/// Number of frames generated between checks for changed settings.
+const UPDATE_FRAMES: usize = 512;
+
+/// Sample rates the generator can run at.
+pub const SIGNAL_SAMPLE_RATES: [u32; 3] = [44100, 48000, 96000];
+
+/// The shapes of test signal that can be generated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Waveform {
+    Sine,
+    /// A sine whose frequency rises logarithmically from the start to the end frequency, then repeats.
+    Sweep,
+    Square,
+    Saw,
+    WhiteNoise,
+    PinkNoise,
+    /// A single full scale sample at the start of each period.
+    ImpulseTrain,
+    /// Several sines of equal level added together.
+    MultiTone,
+}
+
+impl Waveform {
+    /// All waveforms in the order they are shown to the user.
+    pub const ALL: [Waveform; 8] = [
+        Waveform::Sine,
+        Waveform::Sweep,
+        Waveform::Square,
+        Waveform::Saw,
+        Waveform::WhiteNoise,
+        Waveform::PinkNoise,
+        Waveform::ImpulseTrain,
+        Waveform::MultiTone,
+    ];
+
+    /// Returns the display name of the waveform.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::Sweep => "Sine Sweep",
+            Waveform::Square => "Square",
+            Waveform::Saw => "Saw",
+            Waveform::WhiteNoise => "White Noise",
+            Waveform::PinkNoise => "Pink Noise",
+            Waveform::ImpulseTrain => "Impulse Train",
+            Waveform::MultiTone => "Multi-tone",
+        }
+    }
+}
+
+/// The signal produced by the generator.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SignalSettings {
+    pub waveform: Waveform,
+    /// Frequency of the periodic waveforms and where a sweep starts, in Hz.
+    pub frequency: f32,
+    /// Frequency a sweep ends at, in Hz.
+    pub sweep_end: f32,
+    /// Time a sweep takes, in seconds.
+    pub sweep_time: f32,
+    /// Frequencies of the tones in a multi-tone signal, in Hz.
+    pub tones: Vec<f32>,
+    /// Peak level of the signal, in dBFS.
+    pub level: f32,
+    pub sample_rate: u32,
+}
+
+impl SignalSettings {
+    /// Returns a description of the signal, such as `Sine 1000 Hz, -6 dBFS`.
+    pub fn name(&self) -> String {
+        let signal = match self.waveform {
+            Waveform::Sweep => format!("{} {:.0} - {:.0} Hz", self.waveform.name(), self.frequency, self.sweep_end),
+            Waveform::WhiteNoise | Waveform::PinkNoise => self.waveform.name().to_string(),
+            Waveform::MultiTone => {
+                let tones: Vec<String> = self.tones.iter().map(|tone| format!("{tone:.0}")).collect();
+                format!("{} {} Hz", self.waveform.name(), tones.join(", "))
+            }
+            _ => format!("{} {:.0} Hz", self.waveform.name(), self.frequency),
+        };
+        format!("{signal}, {:.0} dBFS", self.level)
+    }
+}
+
+impl Default for SignalSettings {
+    fn default() -> Self {
+        SignalSettings {
+            waveform: Waveform::Sine,
+            frequency: 1000.0,
+            sweep_end: 20000.0,
+            sweep_time: 10.0,
+            tones: vec![100.0, 1000.0, 10000.0],
+            level: -6.0,
+            sample_rate: 48000,
+        }
+    }
+}
+
+/// Returns the correction that removes the aliasing from a step in a naive waveform, known as PolyBLEP.
+/// 
+/// # Arguments
+/// 
+/// * `phase` - Is how far through the period the waveform is, between 0 and 1.
+/// 
+/// * `step` - Is how far the phase moves each sample.
+fn poly_blep(phase: f32, step: f32) -> f32 {
+    if phase < step {
+        let t = phase / step;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// A rodio source generating a test signal, the same on every channel.
+/// 
+/// The settings are shared so they can be changed while it plays, only the sample rate is fixed.
+pub struct SignalGenerator {
+    settings: Arc<Mutex<SignalSettings>>,
+    current: SignalSettings,
+    channels: u16,
+    /// Channel of the current frame the next sample is for.
+    channel: u16,
+    value: f32,
+    phase: f32,
+    tone_phases: Vec<f32>,
+    /// Time into the current sweep, in seconds.
+    sweep_position: f32,
+    /// State of the xorshift generator used for noise.
+    noise: u32,
+    /// State of the filters shaping white noise into pink noise.
+    pink: [f32; 7],
+    frames_until_update: usize,
+}
+
+impl SignalGenerator {
+    /// Creates a new signal generator.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `settings` - Is the signal to generate, changes are picked up while playing.
+    /// 
+    /// * `channels` - Is the number of channels to generate.
+    pub fn new(settings: Arc<Mutex<SignalSettings>>, channels: u16) -> Self {
+        let current = settings.lock().unwrap().clone();
+        let tone_phases = vec![0.0; current.tones.len()];
+
+        SignalGenerator {
+            settings,
+            current,
+            channels,
+            channel: 0,
+            value: 0.0,
+            phase: 0.0,
+            tone_phases,
+            sweep_position: 0.0,
+            noise: 0x9E3779B9,
+            pink: [0.0; 7],
+            frames_until_update: UPDATE_FRAMES,
+        }
+    }
+
+    /// Picks up changed settings, without waiting on the UI if it is changing them.
+    fn update_settings(&mut self) {
+        if let Ok(settings) = self.settings.try_lock() {
+            if *settings != self.current {
+                self.current = SignalSettings { sample_rate: self.current.sample_rate, ..settings.clone() };
+                self.tone_phases.resize(self.current.tones.len(), 0.0);
+            }
+        }
+    }
+
+    /// Returns a uniformly distributed random value between -1 and 1.
+    fn white_noise(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Returns the next value of pink noise, using Paul Kellet's filter which is accurate to within 0.05 dB above 9 Hz.
+    fn pink_noise(&mut self) -> f32 {
+        let white = self.white_noise();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.969 * b[2] + white * 0.153852;
+        b[3] = 0.8665 * b[3] + white * 0.3104856;
+        b[4] = 0.55 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.016898;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+
+    /// Returns the value of the next frame, between -1 and 1 before the level is applied.
+    fn next_frame(&mut self) -> f32 {
+        let sample_rate = self.current.sample_rate as f32;
+        let nyquist = sample_rate / 2.0;
+        let step = self.current.frequency.clamp(0.0, nyquist) / sample_rate;
+
+        let value = match self.current.waveform {
+            Waveform::Sine => (TAU * self.phase).sin(),
+            Waveform::Sweep => {
+                // Logarithmic so each octave takes the same time
+                let start = self.current.frequency.clamp(1.0, nyquist);
+                let end = self.current.sweep_end.clamp(1.0, nyquist);
+                let duration = self.current.sweep_time.max(0.1);
+                let frequency = start * (end / start).powf(self.sweep_position / duration);
+                self.sweep_position += 1.0 / sample_rate;
+                if self.sweep_position >= duration {
+                    self.sweep_position = 0.0;
+                }
+
+                let value = (TAU * self.phase).sin();
+                self.phase = (self.phase + frequency / sample_rate).fract();
+                return value;
+            }
+            Waveform::Square => {
+                let naive = if self.phase < 0.5 { 1.0 } else { -1.0 };
+                naive + poly_blep(self.phase, step) - poly_blep((self.phase + 0.5).fract(), step)
+            }
+            Waveform::Saw => 2.0 * self.phase - 1.0 - poly_blep(self.phase, step),
+            Waveform::WhiteNoise => return self.white_noise(),
+            Waveform::PinkNoise => return self.pink_noise(),
+            Waveform::ImpulseTrain => if self.phase < step { 1.0 } else { 0.0 },
+            Waveform::MultiTone => {
+                // Divided by the number of tones so their peaks can't clip when they line up
+                let count = self.current.tones.len().max(1) as f32;
+                let mut value = 0.0;
+                for (phase, tone) in self.tone_phases.iter_mut().zip(self.current.tones.iter()) {
+                    value += (TAU * *phase).sin() / count;
+                    *phase = (*phase + tone.clamp(0.0, nyquist) / sample_rate).fract();
+                }
+                return value;
+            }
+        };
+
+        self.phase = (self.phase + step).fract();
+        value
+    }
+}
+
+impl Iterator for SignalGenerator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            if self.frames_until_update == 0 {
+                self.update_settings();
+                self.frames_until_update = UPDATE_FRAMES;
+            }
+            self.frames_until_update -= 1;
+
+            let gain = 10f32.powf(self.current.level / 20.0);
+            self.value = self.next_frame() * gain;
+        }
+
+        self.channel = (self.channel + 1) % self.channels;
+        Some(self.value)
+    }
+}
+
+impl Source for SignalGenerator {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.current.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path, time::Instant};
+use rustfft::num_complex::Complex;
+
+/// Fraction of the spectral energy below the rolloff frequency.
+const ROLLOFF_FRACTION: f32 = 0.85;
+
+/// Standard descriptors of a single frame of audio.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SpectralFeatures {
+    /// Magnitude weighted mean frequency in Hz, perceived as brightness.
+    pub centroid: f32,
+    /// Magnitude weighted standard deviation in Hz around the centroid.
+    pub spread: f32,
+    /// Frequency in Hz below which 85% of the energy lies.
+    pub rolloff: f32,
+    /// Ratio of the geometric and arithmetic mean power, 1 for noise and near 0 for tones.
+    pub flatness: f32,
+    /// Sum of the increases in magnitude since the previous frame.
+    pub flux: f32,
+    /// Fraction of consecutive samples that change sign.
+    pub zero_crossing_rate: f32,
+    /// Root mean square of the samples where 1 is full scale.
+    pub rms: f32,
+}
+
+/// Holds all information needed to extract spectral features from a sequence of frames.
+#[derive(Default)]
+pub struct FeatureExtractor {
+    magnitudes: Vec<f32>,
+    previous_magnitudes: Vec<f32>,
+}
+
+impl FeatureExtractor {
+    /// Extracts the features of a frame.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `samples` - Is the audio data of the frame.
+    /// 
+    /// * `spectrum` - Is the FFT data of the frame.
+    pub fn process(&mut self, samples: &[f32], spectrum: &[(Complex<f32>, f32)]) -> SpectralFeatures {
+        // The buffers of this and the previous frame are swapped each frame so their memory is reused
+        std::mem::swap(&mut self.magnitudes, &mut self.previous_magnitudes);
+        self.magnitudes.clear();
+        self.magnitudes.extend(spectrum.iter().map(|x| x.0.norm()));
+        let magnitudes = &self.magnitudes;
+        let total: f32 = magnitudes.iter().sum();
+
+        let (centroid, spread) = if total > 0.0 {
+            let centroid = spectrum.iter().zip(magnitudes.iter()).map(|(x, m)| x.1 * m).sum::<f32>() / total;
+            let variance = spectrum.iter().zip(magnitudes.iter()).map(|(x, m)| (x.1 - centroid).powi(2) * m).sum::<f32>() / total;
+            (centroid, variance.sqrt())
+        } else {
+            (0.0, 0.0)
+        };
+
+        // Rolloff is the first bin where the cumulative energy passes the fraction of the total
+        let energy: f32 = magnitudes.iter().map(|m| m * m).sum();
+        let mut cumulative = 0.0;
+        let rolloff = spectrum.iter()
+            .zip(magnitudes.iter())
+            .find(|(_, m)| {
+                cumulative += *m * *m;
+                cumulative >= ROLLOFF_FRACTION * energy
+            })
+            .map(|(x, _)| x.1)
+            .unwrap_or(0.0);
+
+        // Flatness is calculated in the log domain to avoid underflow
+        let flatness = if energy > 0.0 && !magnitudes.is_empty() {
+            let count = magnitudes.len() as f32;
+            let log_mean = magnitudes.iter().map(|m| (m * m).max(1e-20).ln()).sum::<f32>() / count;
+            log_mean.exp() / (energy / count)
+        } else {
+            0.0
+        };
+
+        // If the source has changed there is nothing to compare against
+        let flux = if magnitudes.len() == self.previous_magnitudes.len() {
+            magnitudes.iter().zip(self.previous_magnitudes.iter()).map(|(now, before)| (now - before).max(0.0)).sum()
+        } else {
+            0.0
+        };
+
+        let crossings = samples.windows(2).filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0)).count();
+        let zero_crossing_rate = crossings as f32 / samples.len().saturating_sub(1).max(1) as f32;
+        let rms = (samples.iter().map(|x| x * x).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+
+        SpectralFeatures { centroid, spread, rolloff, flatness, flux, zero_crossing_rate, rms }
+    }
+}
+
+/// Writes the features of each frame to a CSV file for offline analysis.
+pub struct FeatureLog {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl FeatureLog {
+    /// Creates the CSV file and writes its header.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Is the path of the CSV file.
+    /// 
+    /// * `start` - Is the moment the times in the file are measured from.
+    pub fn create(path: &Path, start: Instant) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "time_s,centroid_hz,spread_hz,rolloff_hz,flatness,flux,zero_crossing_rate,rms")?;
+
+        Ok(FeatureLog { writer, start })
+    }
+
+    /// Writes the features of a frame as a row.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `timestamp` - Is the moment the frame became audible.
+    /// 
+    /// * `features` - Is the features of the frame.
+    pub fn write(&mut self, timestamp: Instant, features: &SpectralFeatures) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "{:.3},{:.1},{:.1},{:.1},{:.5},{:.5},{:.5},{:.5}",
+            timestamp.saturating_duration_since(self.start).as_secs_f32(),
+            features.centroid,
+            features.spread,
+            features.rolloff,
+            features.flatness,
+            features.flux,
+            features.zero_crossing_rate,
+            features.rms
+        )
+    }
+    /// Writes any buffered rows to the file.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use rustfft::FftPlanner;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Returns the spectrum of some samples, scaled and labelled as the analysis does.
+    fn spectrum(samples: &[f32]) -> Vec<(Complex<f32>, f32)> {
+        let mut buffer: Vec<Complex<f32>> = samples.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        FftPlanner::new().plan_fft_forward(samples.len()).process(&mut buffer);
+
+        let step = SAMPLE_RATE / samples.len() as f32;
+        let scale = 2.0 / samples.len() as f32;
+        buffer[..samples.len() / 2].iter().enumerate().map(|(index, x)| (*x * scale, index as f32 * step)).collect()
+    }
+
+    /// Returns a window of a sine, offset so no sample lands exactly on a zero crossing.
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..9600).map(|n| 0.5 * (TAU * frequency * n as f32 / SAMPLE_RATE + 0.1).sin()).collect()
+    }
+
+    /// Returns a window of uniform white noise.
+    fn white_noise() -> Vec<f32> {
+        let mut state: u32 = 0x12345678;
+        (0..9600).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        }).collect()
+    }
+
+    fn features(samples: &[f32]) -> SpectralFeatures {
+        FeatureExtractor::default().process(samples, &spectrum(samples))
+    }
+
+    #[test]
+    fn tone_is_narrow_and_noise_is_flat() {
+        // Rounding in the FFT leaves a tiny floor across every bin, which moves the centroid of a tone slightly
+        let tone = features(&sine(1000.0));
+        assert!((tone.centroid - 1000.0).abs() < 20.0, "{tone:?}");
+        assert!(tone.flatness < 0.01, "{tone:?}");
+
+        // The centroid of white noise is halfway to Nyquist, and its flatness is near that of ideal noise, e^-γ
+        let noise = features(&white_noise());
+        assert!((noise.centroid - SAMPLE_RATE / 4.0).abs() < SAMPLE_RATE / 40.0, "{noise:?}");
+        assert!(noise.flatness > 0.4 && noise.flatness < 0.7, "{noise:?}");
+        assert!(noise.spread > tone.spread * 10.0 && noise.rolloff > tone.rolloff);
+    }
+
+    #[test]
+    fn counts_zero_crossings_of_sine() {
+        // A sine crosses zero twice a cycle
+        for frequency in [100.0, 1000.0, 5000.0] {
+            let features = features(&sine(frequency));
+            let expected = 2.0 * frequency / SAMPLE_RATE;
+            assert!((features.zero_crossing_rate - expected).abs() < expected * 0.01, "{frequency} Hz: {features:?}");
+            assert!((features.rms - 0.5 / 2_f32.sqrt()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn flux_only_counts_increases() {
+        let mut extractor = FeatureExtractor::default();
+        let (quiet, loud) = (sine(1000.0).iter().map(|x| x * 0.5).collect::<Vec<f32>>(), sine(1000.0));
+
+        assert_eq!(extractor.process(&quiet, &spectrum(&quiet)).flux, 0.0);
+        assert!((extractor.process(&loud, &spectrum(&loud)).flux - 0.25).abs() < 0.01);
+        assert_eq!(extractor.process(&quiet, &spectrum(&quiet)).flux, 0.0);
+    }
+}
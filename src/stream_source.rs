@@ -0,0 +1,265 @@
+use std::{fs::File, io::{self, Read}, path::PathBuf, sync::{mpsc::{Receiver, Sender, TryRecvError}, Arc, Condvar, Mutex}};
+
+use crate::app_audio_manager::{CaptureError, CaptureEvent, CaptureSource, DeviceFormat, SampleEncoding};
+use crate::mixer::Mixer;
+use crate::sample_ring::SampleProducer;
+
+/// Number of bytes read from the stream at a time.
+const READ_SIZE: usize = 16384;
+
+/// Largest number of channels a stream can have.
+const MAX_CHANNELS: u16 = 64;
+
+// A whole frame has to fit in a read, otherwise the space left for the next read could be empty
+const _: () = assert!(MAX_CHANNELS as usize * 4 <= READ_SIZE);
+
+/// How the command line options are used, for reading a stream or exporting the features of a file without the UI.
+pub const USAGE: &str = "Usage: musualiser [--stdin | --fifo <path>] [--format <s16le|s24le|s32le|f32le>] [--rate <hz>] [--channels <count>]
+       musualiser --export-features <audio file> <csv file>";
+
+/// Where a stream of raw samples is read from.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StreamInput {
+    /// The standard input of the process.
+    Stdin,
+    /// A named pipe, reopened whenever the program writing to it closes it.
+    Fifo(PathBuf),
+}
+
+impl StreamInput {
+    /// Returns the display name of the input.
+    pub fn name(&self) -> String {
+        match self {
+            StreamInput::Stdin => "Standard Input".to_string(),
+            StreamInput::Fifo(path) => format!("Pipe ({})", path.display()),
+        }
+    }
+}
+
+/// Where a stream of raw samples is read from and the format it is written in.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StreamSettings {
+    pub input: StreamInput,
+    pub format: DeviceFormat,
+}
+
+impl StreamSettings {
+    /// Parses the command line options, returning nothing if no stream should be read.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `args` - Is the command line arguments, excluding the program name.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut input = None;
+        let mut format = DeviceFormat { sample_rate: 48000, channels: 2, encoding: SampleEncoding::Float32 };
+        let mut options_given = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("Missing value for {arg}"));
+            match arg.as_str() {
+                "--stdin" => input = Some(StreamInput::Stdin),
+                "--fifo" => input = Some(StreamInput::Fifo(PathBuf::from(value()?))),
+                "--format" => {
+                    let name = value()?;
+                    format.encoding = parse_encoding(&name).ok_or_else(|| format!("Unknown format {name}"))?;
+                    options_given = true;
+                }
+                "--rate" => {
+                    format.sample_rate = value()?.parse().ok().filter(|&rate| rate > 0).ok_or("Invalid sample rate")?;
+                    options_given = true;
+                }
+                "--channels" => {
+                    format.channels = value()?.parse().ok()
+                        .filter(|channels| (1..=MAX_CHANNELS).contains(channels))
+                        .ok_or_else(|| format!("Invalid channel count, streams can have 1 to {MAX_CHANNELS} channels"))?;
+                    options_given = true;
+                }
+                _ => return Err(format!("Unknown argument {arg}")),
+            }
+        }
+
+        match input {
+            Some(input) => Ok(Some(StreamSettings { input, format })),
+            None if options_given => Err("--format, --rate and --channels need --stdin or --fifo".to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns the encoding named as sox and ffmpeg name raw formats, such as `f32le`.
+/// 
+/// # Arguments
+/// 
+/// * `name` - Is the name of the format.
+fn parse_encoding(name: &str) -> Option<SampleEncoding> {
+    match name {
+        "s16le" => Some(SampleEncoding::Int16),
+        "s24le" => Some(SampleEncoding::Int24),
+        "s32le" => Some(SampleEncoding::Int32),
+        "f32le" => Some(SampleEncoding::Float32),
+        _ => None,
+    }
+}
+
+/// Returns a function converting a read error into a capture error.
+/// 
+/// # Arguments
+/// 
+/// * `action` - Is what was being done, such as `open the pipe`.
+fn stream_error(action: &'static str) -> impl FnOnce(io::Error) -> CaptureError {
+    move |error| CaptureError::Stream(action, error.to_string())
+}
+
+/// Reads raw little endian samples from standard input or a named pipe and passes them to the mixer.
+/// 
+/// Reads block, so a killed reader only stops once more data arrives. Standard input is locked while it is read, which
+/// keeps a reader that is restarting from taking data until the old one has stopped.
+pub struct StreamReader {
+    source: StreamInput,
+    format: DeviceFormat,
+    input: Box<dyn Read>,
+    playing: Arc<(Mutex<bool>, Condvar)>,
+    events: Sender<CaptureEvent>,
+    samples: SampleProducer,
+    kill: Receiver<bool>,
+}
+
+impl StreamReader {
+    /// Opens the stream and adds it to the mix, opening a named pipe waits until something opens it for writing.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `settings` - Is where the stream is read from and the format it is written in.
+    /// 
+    /// * `mixer` - Is the mixer the audio is passed to.
+    /// 
+    /// * `playing` - Is whether capture is running, the reader waits while it isn't.
+    /// 
+    /// * `events` - Is where the reader reports its status.
+    /// 
+    /// * `kill` - Is used to stop the reader.
+    pub fn new(
+        settings: StreamSettings,
+        mixer: Mixer,
+        playing: Arc<(Mutex<bool>, Condvar)>,
+        events: Sender<CaptureEvent>,
+        kill: Receiver<bool>
+    ) -> Result<Self, CaptureError> {
+        let input: Box<dyn Read> = match &settings.input {
+            StreamInput::Stdin => Box::new(io::stdin().lock()),
+            StreamInput::Fifo(path) => Box::new(File::open(path).map_err(stream_error("open the pipe"))?),
+        };
+        let samples = mixer.add_input(CaptureSource::Stream, settings.format);
+
+        Ok(StreamReader { source: settings.input, format: settings.format, input, playing, events, samples, kill })
+    }
+
+    /// Reads audio until killed, the stream ends or an error occurs.
+    /// 
+    /// A named pipe that ends can be opened again for the next program to write to it, but the end of standard input
+    /// is final.
+    pub fn read_loop(&mut self) -> Result<(), CaptureError> {
+        let sample_bytes = self.format.encoding.bytes();
+        let frame_bytes = sample_bytes * self.format.channels as usize;
+        let mut buffer = vec![0; READ_SIZE];
+        let mut data = Vec::with_capacity(READ_SIZE / sample_bytes);
+        // Bytes of an incomplete frame left at the start of the buffer by the last read
+        let mut pending = 0;
+        let _ = self.events.send(CaptureEvent::Started);
+
+        loop {
+            // Wait while stopped, the writer is held up rather than its audio being lost
+            let (lock, cvar) = &*self.playing;
+            let mut playing = lock.lock().unwrap();
+            while !*playing {
+                playing = cvar.wait(playing).unwrap();
+            }
+            drop(playing);
+
+            let read = match self.input.read(&mut buffer[pending..]) {
+                Ok(0) if self.source == StreamInput::Stdin => return Err(CaptureError::InputEnded),
+                Ok(0) => return Err(CaptureError::StreamEnded),
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(stream_error("read the stream")(error)),
+            };
+
+            // Decode whole frames and keep the rest for the next read
+            let available = pending + read;
+            let complete = self.format.encoding.decode_frames(&buffer[..available], frame_bytes, &mut data);
+            if !data.is_empty() {
+                self.samples.push_slice(&data);
+            }
+            buffer.copy_within(complete..available, 0);
+            pending = available - complete;
+
+            // Allow the main thread to kill this thread if necessary
+            match self.kill.try_recv() {
+                Ok(value) => if value { return Ok(()) },
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use crate::sample_ring::sample_ring;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn read_to_end(source: StreamInput, data: &'static [u8]) -> (Result<(), CaptureError>, Vec<f32>) {
+        let format = DeviceFormat { sample_rate: 48000, channels: 2, encoding: SampleEncoding::Int16 };
+        let (events, _event_receiver) = channel();
+        let (_kill_sender, kill) = channel();
+        let (samples, mut consumer) = sample_ring(64);
+        let mut reader = StreamReader {
+            source,
+            format,
+            input: Box::new(data),
+            playing: Arc::new((Mutex::new(true), Condvar::new())),
+            events,
+            samples,
+            kill,
+        };
+
+        let result = reader.read_loop();
+        let mut output = Vec::new();
+        consumer.pop_into(&mut output);
+        (result, output)
+    }
+
+    #[test]
+    fn parses_stream_options() {
+        let settings = StreamSettings::from_args(args(&["--fifo", "audio", "--format", "s16le", "--channels", "6"]));
+        let format = DeviceFormat { sample_rate: 48000, channels: 6, encoding: SampleEncoding::Int16 };
+        assert_eq!(settings, Ok(Some(StreamSettings { input: StreamInput::Fifo(PathBuf::from("audio")), format })));
+        assert_eq!(StreamSettings::from_args(args(&[])), Ok(None));
+        assert!(StreamSettings::from_args(args(&["--channels", "2"])).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_channels() {
+        assert!(StreamSettings::from_args(args(&["--stdin", "--channels", "64"])).is_ok());
+        assert!(StreamSettings::from_args(args(&["--stdin", "--channels", "65"])).is_err());
+        assert!(StreamSettings::from_args(args(&["--stdin", "--channels", "0"])).is_err());
+    }
+
+    #[test]
+    fn end_of_stdin_is_final() {
+        // One whole frame and half of the next
+        let (result, samples) = read_to_end(StreamInput::Stdin, &[0x00, 0x40, 0x00, 0xc0, 0x00]);
+        assert!(matches!(result, Err(ref error) if error.is_final()), "{result:?}");
+        assert_eq!(samples, [0.5, -0.5]);
+
+        let (result, _) = read_to_end(StreamInput::Fifo(PathBuf::from("audio")), &[]);
+        assert!(matches!(result, Err(CaptureError::StreamEnded)), "{result:?}");
+    }
+}
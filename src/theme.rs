@@ -0,0 +1,151 @@
+use imgui::ImColor32;
+use serde::{Deserialize, Serialize};
+
+/// What the colour of the spectrum curve varies with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GradientMode {
+    /// The curve is drawn in the line colour.
+    None,
+    /// The curve blends from the start to the end colour across the frequency axis.
+    Frequency,
+    /// The curve blends from the start to the end colour as the level increases.
+    Amplitude,
+    /// The whole curve blends from the start to the end colour as the spectral centroid rises.
+    Brightness,
+}
+
+impl GradientMode {
+    /// All gradient modes in the order they are shown to the user.
+    pub const ALL: [GradientMode; 4] = [GradientMode::None, GradientMode::Frequency, GradientMode::Amplitude, GradientMode::Brightness];
+
+    /// Returns the display name of the gradient mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GradientMode::None => "None",
+            GradientMode::Frequency => "Frequency",
+            GradientMode::Amplitude => "Amplitude",
+            GradientMode::Brightness => "Brightness",
+        }
+    }
+}
+
+/// Holds all information describing how the spectrum is coloured.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub line_colour: [f32; 4],
+    pub line_thickness: f32,
+    pub gradient: GradientMode,
+    pub gradient_start: [f32; 4],
+    pub gradient_end: [f32; 4],
+    pub fill: bool,
+    pub fill_opacity: f32,
+    pub glow: bool,
+    pub glow_intensity: f32,
+    pub pulse: bool,
+    pub background_colour: [f32; 4],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            line_colour: [1.0, 1.0, 1.0, 1.0],
+            line_thickness: 1.0,
+            gradient: GradientMode::None,
+            gradient_start: [1.0, 1.0, 1.0, 1.0],
+            gradient_end: [1.0, 1.0, 1.0, 1.0],
+            fill: false,
+            fill_opacity: 0.3,
+            glow: false,
+            glow_intensity: 0.3,
+            pulse: false,
+            background_colour: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl Theme {
+    /// Returns the built in palettes along with their names.
+    pub fn palettes() -> Vec<(&'static str, Theme)> {
+        vec![
+            ("Classic", Theme::default()),
+            ("Neon", Theme {
+                line_thickness: 2.0,
+                gradient: GradientMode::Frequency,
+                gradient_start: [0.0, 1.0, 1.0, 1.0],
+                gradient_end: [1.0, 0.0, 1.0, 1.0],
+                fill: true,
+                fill_opacity: 0.2,
+                glow: true,
+                glow_intensity: 0.4,
+                pulse: true,
+                background_colour: [0.02, 0.0, 0.06, 1.0],
+                ..Theme::default()
+            }),
+            ("Fire", Theme {
+                line_thickness: 2.0,
+                gradient: GradientMode::Amplitude,
+                gradient_start: [0.8, 0.1, 0.0, 1.0],
+                gradient_end: [1.0, 0.9, 0.2, 1.0],
+                fill: true,
+                fill_opacity: 0.35,
+                glow: true,
+                glow_intensity: 0.3,
+                background_colour: [0.05, 0.01, 0.0, 1.0],
+                ..Theme::default()
+            }),
+            ("Ocean", Theme {
+                line_thickness: 1.5,
+                gradient: GradientMode::Frequency,
+                gradient_start: [0.0, 0.3, 0.8, 1.0],
+                gradient_end: [0.3, 1.0, 0.8, 1.0],
+                fill: true,
+                fill_opacity: 0.4,
+                background_colour: [0.0, 0.03, 0.08, 1.0],
+                ..Theme::default()
+            }),
+            ("Mono", Theme {
+                line_colour: [0.1, 1.0, 0.2, 1.0],
+                line_thickness: 1.5,
+                glow: true,
+                glow_intensity: 0.2,
+                background_colour: [0.0, 0.05, 0.0, 1.0],
+                ..Theme::default()
+            }),
+        ]
+    }
+
+    /// Returns the colour of the curve at a point.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `frequency` - Is the position between 0 and 1 of the point along the frequency axis, or of the spectral
+    ///   centroid when the gradient follows brightness.
+    /// 
+    /// * `amplitude` - Is the position between 0 and 1 of the point along the amplitude axis.
+    pub fn colour_at(&self, frequency: f32, amplitude: f32) -> [f32; 4] {
+        let t = match self.gradient {
+            GradientMode::None => return self.line_colour,
+            GradientMode::Frequency | GradientMode::Brightness => frequency,
+            GradientMode::Amplitude => amplitude,
+        }.clamp(0.0, 1.0);
+
+        let mut colour = [0.0; 4];
+        for (i, channel) in colour.iter_mut().enumerate() {
+            *channel = self.gradient_start[i] + (self.gradient_end[i] - self.gradient_start[i]) * t;
+        }
+
+        colour
+    }
+}
+
+/// Converts a colour into an ImGui colour with its alpha multiplied by a factor.
+/// 
+/// # Arguments
+/// 
+/// * `colour` - Is the colour as RGBA between 0 and 1.
+/// 
+/// * `alpha` - Is the factor to multiply the alpha by.
+pub fn with_alpha(colour: [f32; 4], alpha: f32) -> ImColor32 {
+    ImColor32::from_rgba_f32s(colour[0], colour[1], colour[2], colour[3] * alpha)
+}